@@ -1,38 +1,215 @@
 use std::rc::Rc;
 
 use crate::core;
+use crate::primitive;
 use crate::types;
+use crate::vm;
+
+/// A name bound in one slot of a lexical frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    Variable(String),
+    Function(String),
+    /// A special variable, accessed through the global environment
+    Special(String),
+    /// The exit tag of a block
+    Block(String),
+    /// The tag of a tagbody, with the names of its go targets
+    Tags(Vec<String>),
+}
+
+/// Compile-time lexical environment
+///
+/// Mirrors the E register of the VM: each frame holds the names bound by one
+/// function application, the innermost frame last.  Local functions from
+/// flet and labels share the frames with variables but live in their own
+/// namespace.  Special parameters keep their slot but are never looked up
+/// lexically.  The global environment is consulted for macro definitions and
+/// special proclamations.
+///
+/// Blocks record whether a return-from refers to them, those that are never
+/// returned from are compiled away so that their bodies keep tail calls.
+pub struct CompEnv<'a> {
+    frames: Vec<Vec<Binding>>,
+    /// Depths of the frames of blocks referred to by a return-from
+    used_blocks: std::collections::HashSet<usize>,
+    /// Bodies and names of blocks found to be never returned from
+    unused_blocks: std::collections::HashSet<(*const std::cell::RefCell<types::RuccoExp>, String)>,
+    /// Expansions of the macro calls compiled, a body compiled again once
+    /// its block is found unused reuses them instead of expanding again
+    expansions:
+        std::collections::HashMap<*const std::cell::RefCell<types::RuccoExp>, types::RuccoExpRef>,
+    pub global: &'a mut core::RuccoEnv,
+}
+
+impl<'a> CompEnv<'a> {
+    pub fn new(global: &'a mut core::RuccoEnv) -> Self {
+        CompEnv {
+            frames: Vec::new(),
+            used_blocks: std::collections::HashSet::new(),
+            unused_blocks: std::collections::HashSet::new(),
+            expansions: std::collections::HashMap::new(),
+            global,
+        }
+    }
+
+    pub fn push_frame(&mut self, frame: Vec<Binding>) {
+        self.frames.push(frame);
+    }
+
+    pub fn pop_frame(&mut self) -> Option<Vec<Binding>> {
+        self.frames.pop()
+    }
+
+    /// Find the (frame . index) address of a lexical variable
+    pub fn lookup(&self, name: &str) -> Option<(usize, usize)> {
+        self.find(|e| matches!(e, Binding::Variable(n) if n == name))
+    }
+
+    /// Find the (frame . index) address of a local function
+    pub fn lookup_function(&self, name: &str) -> Option<(usize, usize)> {
+        self.find(|e| matches!(e, Binding::Function(n) if n == name))
+    }
+
+    /// Find the (frame . index) address of the tag of a block and record
+    /// that the block is returned from
+    pub fn lookup_block(&mut self, name: &str) -> Option<(usize, usize)> {
+        let (i, j) = self.find(|e| matches!(e, Binding::Block(n) if n == name))?;
+        self.used_blocks.insert(self.frames.len() - 1 - i);
+        Some((i, j))
+    }
+
+    /// Find the (frame . index) address of the tagbody defining a go target,
+    /// together with the index of the segment following the target
+    pub fn lookup_tag(&self, name: &str) -> Option<(usize, usize, usize)> {
+        let (i, j) =
+            self.find(|e| matches!(e, Binding::Tags(tags) if tags.iter().any(|t| t == name)))?;
+        let frame = &self.frames[self.frames.len() - 1 - i];
+        match &frame[j] {
+            Binding::Tags(tags) => tags.iter().position(|t| t == name).map(|k| (i, j, k + 1)),
+            _ => None,
+        }
+    }
+
+    fn find(&self, pred: impl Fn(&Binding) -> bool) -> Option<(usize, usize)> {
+        self.frames
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(i, frame)| frame.iter().position(&pred).map(|j| (i, j)))
+    }
+}
 
 pub fn compile(
     exp: &types::RuccoExpRef,
+    env: &mut core::RuccoEnv,
     arena: &mut types::RuccoArena,
 ) -> anyhow::Result<types::RuccoExpRef> {
     let stop = arena.alloc_symbol("stop");
     let code = types::alloc!(arena, [[stop]]);
-    comp(exp, arena, &mut std::collections::HashMap::new(), &code)
+    comp(exp, arena, &mut CompEnv::new(env), &code)
 }
 
-fn comp(
+/// Compile the expression to run with one frame of local variables in E
+pub fn compile_in_frame(
     exp: &types::RuccoExpRef,
+    locals: &[String],
+    env: &mut core::RuccoEnv,
+    arena: &mut types::RuccoArena,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let stop = arena.alloc_symbol("stop");
+    let code = types::alloc!(arena, [[stop]]);
+    let frame = locals
+        .iter()
+        .map(|name| {
+            if env.is_special(name) {
+                Binding::Special(name.clone())
+            } else {
+                Binding::Variable(name.clone())
+            }
+        })
+        .collect();
+    let mut env = CompEnv::new(env);
+    env.push_frame(frame);
+    comp(exp, arena, &mut env, &code)
+}
+
+/// Expand the form once if it is a macro call
+///
+/// Return the expansion and whether the form was expanded.
+pub fn macroexpand_1(
+    exp: &types::RuccoExpRef,
+    env: &mut core::RuccoEnv,
     arena: &mut types::RuccoArena,
+) -> anyhow::Result<(types::RuccoExpRef, bool)> {
+    let exp_ptr = exp
+        .upgrade()
+        .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+    let (head, args) = match &*exp_ptr.borrow() {
+        types::RuccoExp::Cons { car, cdr } => (car.clone(), cdr.clone()),
+        _ => return Ok((exp.clone(), false)),
+    };
+    let head_ptr = head
+        .upgrade()
+        .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+    let macro_fn = match head_ptr.borrow().as_symbol() {
+        Some(sym) => env.get_macro(sym).cloned(),
+        None => None,
+    };
+    match macro_fn {
+        Some(f) => Ok((vm::call(&f, &args, env, arena)?, true)),
+        None => Ok((exp.clone(), false)),
+    }
+}
+
+/// Expand the form repeatedly until it is no longer a macro call
+pub fn macroexpand(
+    exp: &types::RuccoExpRef,
     env: &mut core::RuccoEnv,
+    arena: &mut types::RuccoArena,
+) -> anyhow::Result<(types::RuccoExpRef, bool)> {
+    let (mut exp, mut expanded) = macroexpand_1(exp, env, arena)?;
+    if expanded {
+        loop {
+            (exp, expanded) = macroexpand_1(&exp, env, arena)?;
+            if !expanded {
+                return Ok((exp, true));
+            }
+        }
+    }
+    Ok((exp, false))
+}
+
+fn comp(
+    exp: &types::RuccoExpRef,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
     code: &types::RuccoExpRef,
 ) -> anyhow::Result<types::RuccoExpRef> {
     let exp_ptr = exp
         .upgrade()
         .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
     let ldc = arena.alloc_symbol("ldc");
-    let sel = arena.alloc_symbol("sel");
-    let join = arena.alloc_symbol("join");
     let x = match &*exp_ptr.borrow() {
         types::RuccoExp::Atom(ref atom) => match atom {
-            types::RuccoAtom::Symbol(ref sym) if sym == "t" || sym == "nil" => {
+            types::RuccoAtom::Symbol(ref sym)
+                if sym == "t" || sym == "nil" || sym.starts_with(':') =>
+            {
                 let exp_code = types::alloc!(arena, [ldc, exp]);
-                Ok(types::alloc!(arena, [exp_code, code]))
-            }
-            types::RuccoAtom::Symbol(ref _sym) => {
-                unimplemented!()
+                Ok(types::alloc!(arena, [exp_code; code]))
             }
+            types::RuccoAtom::Symbol(ref sym) => match env.lookup(sym) {
+                Some((i, j)) => {
+                    let ld = arena.alloc_symbol("ld");
+                    let i = arena.alloc((i as i64).into());
+                    let j = arena.alloc((j as i64).into());
+                    Ok(types::alloc!(arena, [[ld, [i; j]]; code]))
+                }
+                None => {
+                    let ldg = arena.alloc_symbol("ldg");
+                    Ok(types::alloc!(arena, [[ldg, exp]; code]))
+                }
+            },
             _atom => {
                 let exp_code = types::alloc!(arena, [ldc, exp]);
                 Ok(types::alloc!(arena, [exp_code; code]))
@@ -52,26 +229,1401 @@ fn comp(
                         let cdr_code = cdr_code_.car_weak_ref()?;
                         Ok(types::alloc!(arena, [[ldc, cdr_code]; code]))
                     }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "quasiquote" => {
+                        let nil = types::alloc!(arena, []);
+                        let [template] = cdr_ptr.borrow().extract_args::<1, 1>(sym, &nil)?;
+                        let exp = quasiquote(&template, 0, arena)?;
+                        comp(&exp, arena, env, code)
+                    }
                     types::RuccoAtom::Symbol(ref sym) if sym == "if" => {
                         let nil = types::alloc!(arena, []);
                         let [test_ptr, then_ptr, else_ptr] =
                             cdr_ptr.borrow().extract_args::<2, 3>("comp", &nil)?;
 
-                        let join_code = types::alloc!(arena, [[join]]);
+                        let sel_code = if is_tail(code)? {
+                            let selr = arena.alloc_symbol("selr");
+                            let then_code = comp(&Rc::downgrade(&then_ptr), arena, env, code)?;
+                            let else_code = comp(&Rc::downgrade(&else_ptr), arena, env, code)?;
+                            types::alloc!(arena, [[selr, then_code, else_code]])
+                        } else {
+                            let sel = arena.alloc_symbol("sel");
+                            let join = arena.alloc_symbol("join");
+                            let join_code = types::alloc!(arena, [[join]]);
+                            let then_code =
+                                comp(&Rc::downgrade(&then_ptr), arena, env, &join_code)?;
+                            let else_code =
+                                comp(&Rc::downgrade(&else_ptr), arena, env, &join_code)?;
+                            types::alloc!(arena, [[sel, then_code, else_code]; code])
+                        };
 
-                        let test_code = comp(&Rc::downgrade(&test_ptr), arena, env, &nil)?;
-                        let then_code = comp(&Rc::downgrade(&then_ptr), arena, env, &join_code)?;
-                        let else_code = comp(&Rc::downgrade(&else_ptr), arena, env, &join_code)?;
+                        comp(&Rc::downgrade(&test_ptr), arena, env, &sel_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "progn" => {
+                        comp_body(&cdr_ptr, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "lambda" => {
+                        comp_lambda(&cdr_ptr, None, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "function" => {
+                        let nil = types::alloc!(arena, []);
+                        let [fn_ptr] = cdr_ptr.borrow().extract_args::<1, 1>(sym, &nil)?;
+                        comp_function(&fn_ptr, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "setq" => {
+                        comp_setq(&cdr_ptr, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "defvar" => {
+                        let nil = types::alloc!(arena, []);
+                        let [name_ptr, value_ptr, _doc] =
+                            cdr_ptr.borrow().extract_args::<1, 3>(sym, &nil)?;
+                        proclaim_special(&name_ptr, env)?;
+                        let name = Rc::downgrade(&name_ptr);
+                        let ldc_name_code = types::alloc!(arena, [[ldc, name]; code]);
+                        if cdr_ptr.borrow().to_vec()?.len() == 1 {
+                            return Ok(ldc_name_code);
+                        }
 
-                        Ok(types::alloc!(arena, [test_code, [sel, then_code, else_code]; code]))
+                        let prim = arena.alloc_symbol("prim");
+                        let boundp = arena.alloc_symbol("boundp");
+                        let one = arena.alloc(1.into());
+                        let sel = arena.alloc_symbol("sel");
+                        let join = arena.alloc_symbol("join");
+                        let gset = arena.alloc_symbol("gset");
+                        let pop = arena.alloc_symbol("pop");
+                        let set_code = types::alloc!(arena, [[gset, name], [pop], [join]]);
+                        let value_code = comp(&Rc::downgrade(&value_ptr), arena, env, &set_code)?;
+                        Ok(
+                            types::alloc!(arena, [[ldc, name], [prim, boundp, one], [sel, [[join]], value_code]; ldc_name_code]),
+                        )
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "defparameter" => {
+                        let nil = types::alloc!(arena, []);
+                        let [name_ptr, value_ptr, _doc] =
+                            cdr_ptr.borrow().extract_args::<2, 3>(sym, &nil)?;
+                        proclaim_special(&name_ptr, env)?;
+                        let name = Rc::downgrade(&name_ptr);
+                        let gset = arena.alloc_symbol("gset");
+                        let pop = arena.alloc_symbol("pop");
+                        let set_code =
+                            types::alloc!(arena, [[gset, name], [pop], [ldc, name]; code]);
+                        comp(&Rc::downgrade(&value_ptr), arena, env, &set_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "defmacro" => {
+                        let name = cdr_ptr.borrow().car_weak()?;
+                        let gmset = arena.alloc_symbol("gmset");
+                        let pop = arena.alloc_symbol("pop");
+                        let set_code =
+                            types::alloc!(arena, [[gmset, name], [pop], [ldc, name]; code]);
+                        let lambda = block_lambda(&cdr_ptr, arena)?;
+                        comp_lambda(&lambda, Some(&name), arena, env, &set_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "block" => {
+                        let name = cdr_ptr.borrow().car()?;
+                        let name = tag_name(&name, sym)?;
+                        let body = cdr_ptr.borrow().cdr()?;
+                        let key = (Rc::as_ptr(&body), name.clone());
+                        if !env.unused_blocks.contains(&key) {
+                            let block = arena.alloc_symbol("block");
+                            let uncatch = arena.alloc_symbol("uncatch");
+                            let uncatch_code = types::alloc!(arena, [[uncatch]]);
+                            let depth = env.frames.len();
+                            env.push_frame(vec![Binding::Block(name)]);
+                            let body_code = comp_body(&body, arena, env, &uncatch_code);
+                            env.pop_frame();
+                            let used = env.used_blocks.remove(&depth);
+                            let body_code = body_code?;
+                            if used {
+                                return Ok(types::alloc!(arena, [[block, body_code]; code]));
+                            }
+                            env.unused_blocks.insert(key);
+                        }
+                        // no return-from refers to the block after macroexpansion,
+                        // so the body is compiled without it to keep its tail calls
+                        comp_body(&body, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym)
+                        if sym == "return-from" || sym == "return" =>
+                    {
+                        let nil = types::alloc!(arena, []);
+                        let (name, value) = if sym == "return" {
+                            let [value] = cdr_ptr.borrow().extract_args::<0, 1>(sym, &nil)?;
+                            ("nil".to_string(), value)
+                        } else {
+                            let [name, value] = cdr_ptr.borrow().extract_args::<1, 2>(sym, &nil)?;
+                            (tag_name(&name, sym)?, value)
+                        };
+                        let (i, j) = env
+                            .lookup_block(&name)
+                            .ok_or(types::RuccoRuntimeErr::VoidBlock { name })?;
+                        let ld = arena.alloc_symbol("ld");
+                        let throw = arena.alloc_symbol("throw");
+                        let i = arena.alloc((i as i64).into());
+                        let j = arena.alloc((j as i64).into());
+                        let throw_code = types::alloc!(arena, [[throw]; code]);
+                        let value_code = comp(&Rc::downgrade(&value), arena, env, &throw_code)?;
+                        Ok(types::alloc!(arena, [[ld, [i; j]]; value_code]))
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "catch" => {
+                        let tag = cdr_ptr.borrow().car_weak()?;
+                        let body = cdr_ptr.borrow().cdr()?;
+                        let catch = arena.alloc_symbol("catch");
+                        let uncatch = arena.alloc_symbol("uncatch");
+                        let uncatch_code = types::alloc!(arena, [[uncatch]]);
+                        let body_code = comp_body(&body, arena, env, &uncatch_code)?;
+                        let catch_code = types::alloc!(arena, [[catch, body_code]; code]);
+                        comp(&tag, arena, env, &catch_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "throw" => {
+                        let nil = types::alloc!(arena, []);
+                        let [tag, value] = cdr_ptr.borrow().extract_args::<2, 2>(sym, &nil)?;
+                        let throw = arena.alloc_symbol("throw");
+                        let throw_code = types::alloc!(arena, [[throw]; code]);
+                        let value_code = comp(&Rc::downgrade(&value), arena, env, &throw_code)?;
+                        comp(&Rc::downgrade(&tag), arena, env, &value_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "unwind-protect" => {
+                        let protected = cdr_ptr.borrow().car_weak()?;
+                        let cleanup = cdr_ptr.borrow().cdr()?.borrow().to_vec()?;
+                        let protect = arena.alloc_symbol("protect");
+                        let unprotect = arena.alloc_symbol("unprotect");
+                        let resume = arena.alloc_symbol("resume");
+                        let pop = arena.alloc_symbol("pop");
+                        let mut cleanup_code = types::alloc!(arena, [[resume]]);
+                        for form in cleanup.iter().rev() {
+                            let pop_code = types::alloc!(arena, [[pop]; cleanup_code]);
+                            cleanup_code = comp(&Rc::downgrade(form), arena, env, &pop_code)?;
+                        }
+                        let unprotect_code = types::alloc!(arena, [[unprotect]; code]);
+                        let protected_code = comp(&protected, arena, env, &unprotect_code)?;
+                        Ok(types::alloc!(
+                            arena,
+                            [[protect, cleanup_code]; protected_code]
+                        ))
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "tagbody" => {
+                        comp_tagbody(&cdr_ptr, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "go" => {
+                        let nil = types::alloc!(arena, []);
+                        let [tag] = cdr_ptr.borrow().extract_args::<1, 1>(sym, &nil)?;
+                        let name = tag_name(&tag, sym)?;
+                        let (i, j, k) = env
+                            .lookup_tag(&name)
+                            .ok_or(types::RuccoRuntimeErr::VoidTag { name })?;
+                        let ld = arena.alloc_symbol("ld");
+                        let go = arena.alloc_symbol("go");
+                        let i = arena.alloc((i as i64).into());
+                        let j = arena.alloc((j as i64).into());
+                        let k = arena.alloc((k as i64).into());
+                        Ok(types::alloc!(arena, [[ld, [i; j]], [go, k]; code]))
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "flet" => {
+                        comp_flet(&cdr_ptr, false, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "labels" => {
+                        comp_flet(&cdr_ptr, true, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if env.lookup_function(sym).is_some() => {
+                        let (i, j) = env
+                            .lookup_function(sym)
+                            .ok_or(types::RuccoRuntimeErr::Unreachable)?;
+                        let ld = arena.alloc_symbol("ld");
+                        let i = arena.alloc((i as i64).into());
+                        let j = arena.alloc((j as i64).into());
+                        let ap_code = comp_ap(arena, code)?;
+                        let args_code = comp_args(&cdr_ptr, arena, env, &ap_code)?;
+                        Ok(types::alloc!(arena, [[ld, [i; j]]; args_code]))
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if env.global.get_macro(sym).is_some() => {
+                        let key = Rc::as_ptr(&exp_ptr);
+                        let exp = match env.expansions.get(&key) {
+                            Some(exp) => exp.clone(),
+                            None => {
+                                let (exp, _) = macroexpand_1(exp, env.global, arena)?;
+                                env.expansions.insert(key, exp.clone());
+                                exp
+                            }
+                        };
+                        comp(&exp, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "defun" => {
+                        let name = cdr_ptr.borrow().car_weak()?;
+                        let gfset = arena.alloc_symbol("gfset");
+                        let pop = arena.alloc_symbol("pop");
+                        let set_code =
+                            types::alloc!(arena, [[gfset, name], [pop], [ldc, name]; code]);
+                        let lambda = block_lambda(&cdr_ptr, arena)?;
+                        comp_lambda(&lambda, Some(&name), arena, env, &set_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "funcall" => {
+                        let fn_ref = cdr_ptr.borrow().car_weak()?;
+                        let args_ptr = cdr_ptr.borrow().cdr()?;
+                        let ap_code = comp_ap(arena, code)?;
+                        let args_code = comp_args(&args_ptr, arena, env, &ap_code)?;
+                        comp(&fn_ref, arena, env, &args_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "apply" => {
+                        let fn_ref = cdr_ptr.borrow().car_weak()?;
+                        let args = cdr_ptr.borrow().cdr()?.borrow().to_vec()?;
+                        if args.is_empty() {
+                            anyhow::bail!(types::RuccoRuntimeErr::WrongNumberOfArguments {
+                                name: sym.to_string(),
+                                expected: types::RuccoArity::new(2, None),
+                                actual: 1,
+                            });
+                        }
+                        let mut res = comp_ap(arena, code)?;
+                        if args.len() > 1 {
+                            let prim = arena.alloc_symbol("prim");
+                            let list_ = arena.alloc_symbol("list*");
+                            let n = arena.alloc((args.len() as i64).into());
+                            res = types::alloc!(arena, [[prim, list_, n]; res]);
+                        }
+                        for arg in args.iter().rev() {
+                            res = comp(&Rc::downgrade(arg), arena, env, &res)?;
+                        }
+                        comp(&fn_ref, arena, env, &res)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "multiple-value-call" => {
+                        let fn_ref = cdr_ptr.borrow().car_weak()?;
+                        let forms = cdr_ptr.borrow().cdr()?.borrow().to_vec()?;
+                        let mut res = comp_ap(arena, code)?;
+                        match forms.len() {
+                            0 => {
+                                let ldc = arena.alloc_symbol("ldc");
+                                let nil = types::alloc!(arena, []);
+                                res = types::alloc!(arena, [[ldc, nil]; res]);
+                            }
+                            1 => (),
+                            n => {
+                                let prim = arena.alloc_symbol("prim");
+                                let append = arena.alloc_symbol("append");
+                                let n = arena.alloc((n as i64).into());
+                                res = types::alloc!(arena, [[prim, append, n]; res]);
+                            }
+                        }
+                        let mvlist = arena.alloc_symbol("mvlist");
+                        for form in forms.iter().rev() {
+                            let mvlist_code = types::alloc!(arena, [[mvlist]; res]);
+                            res = comp(&Rc::downgrade(form), arena, env, &mvlist_code)?;
+                        }
+                        comp(&fn_ref, arena, env, &res)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "multiple-value-bind" => {
+                        comp_multiple_value_bind(&cdr_ptr, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "multiple-value-list" => {
+                        let nil = types::alloc!(arena, []);
+                        let [form] = cdr_ptr.borrow().extract_args::<1, 1>(sym, &nil)?;
+                        let mvlist = arena.alloc_symbol("mvlist");
+                        let mvlist_code = types::alloc!(arena, [[mvlist]; code]);
+                        comp(&Rc::downgrade(&form), arena, env, &mvlist_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "multiple-value-prog1" => {
+                        let first = cdr_ptr.borrow().car_weak()?;
+                        let forms = cdr_ptr.borrow().cdr()?.borrow().to_vec()?;
+                        let prim = arena.alloc_symbol("prim");
+                        let values_list = arena.alloc_symbol("values-list");
+                        let one = arena.alloc(1.into());
+                        let pop = arena.alloc_symbol("pop");
+                        let mut res = types::alloc!(arena, [[prim, values_list, one]; code]);
+                        for form in forms.iter().rev() {
+                            let pop_code = types::alloc!(arena, [[pop]; res]);
+                            res = comp(&Rc::downgrade(form), arena, env, &pop_code)?;
+                        }
+                        let mvlist = arena.alloc_symbol("mvlist");
+                        let mvlist_code = types::alloc!(arena, [[mvlist]; res]);
+                        comp(&first, arena, env, &mvlist_code)
                     }
-                    types::RuccoAtom::Symbol(ref _sym) => unimplemented!(),
-                    _ => unimplemented!(),
+                    types::RuccoAtom::Symbol(ref sym) if sym == "nth-value" => {
+                        let nil = types::alloc!(arena, []);
+                        let [n, form] = cdr_ptr.borrow().extract_args::<2, 2>(sym, &nil)?;
+                        let mvlist = arena.alloc_symbol("mvlist");
+                        let prim = arena.alloc_symbol("prim");
+                        let nth = arena.alloc_symbol("nth");
+                        let two = arena.alloc(2.into());
+                        let nth_code = types::alloc!(arena, [[mvlist], [prim, nth, two]; code]);
+                        let form_code = comp(&Rc::downgrade(&form), arena, env, &nth_code)?;
+                        comp(&Rc::downgrade(&n), arena, env, &form_code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if sym == "let" => {
+                        comp_let(&cdr_ptr, arena, env, code)
+                    }
+                    types::RuccoAtom::Symbol(ref sym) if is_primitive_call(sym, env.global) => {
+                        let prim = arena.alloc_symbol("prim");
+                        let args = cdr_ptr.borrow().to_vec()?;
+                        let n = arena.alloc((args.len() as i64).into());
+                        let mut res = types::alloc!(arena, [[prim, car, n]; code]);
+                        for arg in args.iter().rev() {
+                            res = comp(&Rc::downgrade(arg), arena, env, &res)?;
+                        }
+                        Ok(res)
+                    }
+                    types::RuccoAtom::Symbol(_) => {
+                        let ldgf = arena.alloc_symbol("ldgf");
+                        let ap_code = comp_ap(arena, code)?;
+                        let args_code = comp_args(&cdr_ptr, arena, env, &ap_code)?;
+                        Ok(types::alloc!(arena, [[ldgf, car]; args_code]))
+                    }
+                    _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                        name: "comp".to_string(),
+                        expected: types::RuccoDataType::Function,
+                        actual: types::RuccoActualDataType::from(&*car_ptr.borrow())
+                            .with_datum(&car_ptr),
+                    }),
                 },
-                types::RuccoExp::Cons { .. } => unimplemented!(),
+                _ => {
+                    let ap_code = comp_ap(arena, code)?;
+                    let args_code = comp_args(&cdr_ptr, arena, env, &ap_code)?;
+                    comp(car, arena, env, &args_code)
+                }
             };
             x
         }
+        types::RuccoExp::Closure { .. }
+        | types::RuccoExp::Primitive(_)
+        | types::RuccoExp::Condition { .. }
+        | types::RuccoExp::Structure { .. }
+        | types::RuccoExp::Array { .. }
+        | types::RuccoExp::HashTable { .. } => {
+            let exp_code = types::alloc!(arena, [ldc, exp]);
+            Ok(types::alloc!(arena, [exp_code; code]))
+        }
     };
     x
 }
+
+/// Return true if the continuation only returns from the current function
+fn is_tail(code: &types::RuccoExpRef) -> anyhow::Result<bool> {
+    let code_ptr = code
+        .upgrade()
+        .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+    let code_ = code_ptr.borrow();
+    let types::RuccoExp::Cons { .. } = &*code_ else {
+        return Ok(false);
+    };
+    let inst = code_.car()?;
+    let inst_ = inst.borrow();
+    let types::RuccoExp::Cons { .. } = &*inst_ else {
+        return Ok(false);
+    };
+    let op = inst_.car()?;
+    let is_rtn = op.borrow().as_symbol() == Some("rtn");
+    Ok(is_rtn)
+}
+
+/// Apply the function, reusing the current frame in tail position
+fn comp_ap(
+    arena: &mut types::RuccoArena,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    if is_tail(code)? {
+        let tap = arena.alloc_symbol("tap");
+        Ok(types::alloc!(arena, [[tap]]))
+    } else {
+        let ap = arena.alloc_symbol("ap");
+        Ok(types::alloc!(arena, [[ap]; code]))
+    }
+}
+
+/// Compile forms in sequence, discarding all values but the last
+fn comp_body(
+    body: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let forms = body.borrow().to_vec()?;
+    let Some((last, rest)) = forms.split_last() else {
+        let ldc = arena.alloc_symbol("ldc");
+        let nil = types::alloc!(arena, []);
+        return Ok(types::alloc!(arena, [[ldc, nil]; code]));
+    };
+
+    let pop = arena.alloc_symbol("pop");
+    let mut res = comp(&Rc::downgrade(last), arena, env, code)?;
+    for form in rest.iter().rev() {
+        let pop_code = types::alloc!(arena, [[pop]; res]);
+        res = comp(&Rc::downgrade(form), arena, env, &pop_code)?;
+    }
+    Ok(res)
+}
+
+/// Return whether calls to the name are compiled to its primitive, which
+/// they are until a global function of another definition is given the name
+fn is_primitive_call(name: &str, env: &core::RuccoEnv) -> bool {
+    let Some(p) = primitive::find(name) else {
+        return false;
+    };
+    env.get_function(name)
+        .and_then(|f| f.upgrade())
+        .is_some_and(
+            |f| matches!(&*f.borrow(), types::RuccoExp::Primitive(q) if std::ptr::eq(*q, p)),
+        )
+}
+
+/// Compile arguments left to right and collect them into a list
+fn comp_args(
+    args: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let args = args.borrow().to_vec()?;
+    let args_sym = arena.alloc_symbol("args");
+    let n = arena.alloc((args.len() as i64).into());
+
+    let mut res = types::alloc!(arena, [[args_sym, n]; code]);
+    for arg in args.iter().rev() {
+        res = comp(&Rc::downgrade(arg), arena, env, &res)?;
+    }
+    Ok(res)
+}
+
+/// Section of a lambda list, started by a lambda list keyword
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Required,
+    Optional,
+    Rest,
+    Key,
+    Aux,
+}
+
+/// How a variable of a lambda list frame gets its value when the arguments
+/// do not give one
+enum Init {
+    None,
+    /// Form evaluated when the supplied-p variable at the index is nil
+    Default {
+        form: types::RuccoExpRef,
+        supplied: usize,
+    },
+    /// Form of an &aux variable, always evaluated
+    Aux(types::RuccoExpRef),
+}
+
+/// Variable of the frame a lambda list makes
+struct LambdaVar {
+    /// None for the hidden supplied-p variable of a parameter without one
+    name: Option<String>,
+    init: Init,
+}
+
+fn param_name(param: &types::RuccoExpRefStrong) -> anyhow::Result<String> {
+    match param.borrow().as_symbol() {
+        Some(sym) => Ok(sym.to_string()),
+        None => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: "lambda".to_string(),
+            expected: types::RuccoDataType::Symbol,
+            actual: types::RuccoActualDataType::from(&*param.borrow()).with_datum(param),
+        }),
+    }
+}
+
+/// Parse a lambda list into the variables of the frame, in order
+///
+/// Also return the lambda list normalized for the machine to bind arguments
+/// with: required parameters are symbols, &optional ones are (var svar),
+/// &key ones are (keyword var svar) and the others are symbols, where svar
+/// is nil for a hidden supplied-p variable.  &body is the same as &rest.
+fn parse_lambda_list(
+    params: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+) -> anyhow::Result<(types::RuccoExpRef, Vec<LambdaVar>)> {
+    let nil = types::alloc!(arena, []);
+    let mut section = Section::Required;
+    let mut normalized = Vec::new();
+    let mut vars = Vec::new();
+    for param in params.borrow().to_vec()? {
+        let marker = match param.borrow().as_symbol() {
+            Some("&optional") => Some(("&optional", Section::Optional)),
+            Some("&rest" | "&body") => Some(("&rest", Section::Rest)),
+            Some("&key") => Some(("&key", Section::Key)),
+            Some("&allow-other-keys") => Some(("&allow-other-keys", section)),
+            Some("&aux") => Some(("&aux", Section::Aux)),
+            Some(keyword) if keyword.starts_with('&') => {
+                anyhow::bail!(types::RuccoRuntimeErr::UnknownLambdaListKeyword {
+                    keyword: keyword.to_string(),
+                })
+            }
+            _ => None,
+        };
+        if let Some((marker, next)) = marker {
+            normalized.push(arena.alloc_symbol(marker));
+            section = next;
+            continue;
+        }
+
+        match section {
+            Section::Required | Section::Rest => {
+                vars.push(LambdaVar {
+                    name: Some(param_name(&param)?),
+                    init: Init::None,
+                });
+                normalized.push(Rc::downgrade(&param));
+            }
+            Section::Optional | Section::Key => {
+                let [var, form, svar] = match param.borrow().as_symbol() {
+                    Some(_) => {
+                        let nil = nil
+                            .upgrade()
+                            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                        [param.clone(), nil.clone(), nil]
+                    }
+                    None => param.borrow().extract_args::<1, 3>("lambda", &nil)?,
+                };
+                let (keyword, var) = match (section, var.borrow().as_symbol()) {
+                    (Section::Key, Some(name)) => {
+                        (Some(arena.alloc_symbol(&format!(":{}", name))), var.clone())
+                    }
+                    (Section::Key, None) => {
+                        let [keyword, var] = var.borrow().extract_args::<2, 2>("lambda", &nil)?;
+                        (Some(Rc::downgrade(&keyword)), var)
+                    }
+                    _ => (None, var.clone()),
+                };
+                let init = if form.borrow().is_nil() {
+                    Init::None
+                } else {
+                    Init::Default {
+                        form: Rc::downgrade(&form),
+                        supplied: vars.len() + 1,
+                    }
+                };
+                vars.push(LambdaVar {
+                    name: Some(param_name(&var)?),
+                    init,
+                });
+                vars.push(LambdaVar {
+                    name: if svar.borrow().is_nil() {
+                        None
+                    } else {
+                        Some(param_name(&svar)?)
+                    },
+                    init: Init::None,
+                });
+                let mut spec = vec![Rc::downgrade(&var), Rc::downgrade(&svar)];
+                spec.splice(0..0, keyword);
+                normalized.push(arena.alloc_list(spec));
+            }
+            Section::Aux => {
+                let [var, form] = match param.borrow().as_symbol() {
+                    Some(_) => {
+                        let nil = nil
+                            .upgrade()
+                            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                        [param.clone(), nil]
+                    }
+                    None => param.borrow().extract_args::<1, 2>("lambda", &nil)?,
+                };
+                let init = if form.borrow().is_nil() {
+                    Init::None
+                } else {
+                    Init::Aux(Rc::downgrade(&form))
+                };
+                vars.push(LambdaVar {
+                    name: Some(param_name(&var)?),
+                    init,
+                });
+                normalized.push(Rc::downgrade(&var));
+            }
+        }
+    }
+    Ok((arena.alloc_list(normalized), vars))
+}
+
+/// Compile (params . body) of a lambda expression to a closure
+///
+/// The machine binds the arguments to the frame of the lambda list, then
+/// the body starts by evaluating the defaults of missing optional and
+/// keyword arguments and the &aux forms, each with only the variables
+/// before it in scope.  Special parameters are bound dynamically as they
+/// get their value and unbound before returning, so a body binding them has
+/// no tail calls.  The closure is named by the name of a defun, defmacro,
+/// flet or labels in the errors of its calls.
+fn comp_lambda(
+    lambda: &types::RuccoExpRefStrong,
+    name: Option<&types::RuccoExpRef>,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let params = lambda.borrow().car()?;
+    let body = lambda.borrow().cdr()?;
+
+    let (params, vars) = parse_lambda_list(&params, arena)?;
+    let names = vars
+        .iter()
+        .map(|var| match &var.name {
+            Some(name) if env.global.is_special(name) => Binding::Special(name.clone()),
+            Some(name) => Binding::Variable(name.clone()),
+            None => Binding::Variable(String::new()),
+        })
+        .collect::<Vec<_>>();
+    let n_specials = names
+        .iter()
+        .filter(|binding| matches!(binding, Binding::Special(_)))
+        .count();
+
+    let ldf = arena.alloc_symbol("ldf");
+    let rtn = arena.alloc_symbol("rtn");
+    let mut rtn_code = types::alloc!(arena, [[rtn]]);
+    if n_specials > 0 {
+        let unbind = arena.alloc_symbol("unbind");
+        let n = arena.alloc((n_specials as i64).into());
+        rtn_code = types::alloc!(arena, [[unbind, n]; rtn_code]);
+    }
+
+    env.push_frame(names.clone());
+    let body_code = comp_body(&body, arena, env, &rtn_code);
+    env.pop_frame();
+
+    let mut body_code = body_code?;
+    let ld = arena.alloc_symbol("ld");
+    let lset = arena.alloc_symbol("lset");
+    let pop = arena.alloc_symbol("pop");
+    let bind = arena.alloc_symbol("bind");
+    let sel = arena.alloc_symbol("sel");
+    let join = arena.alloc_symbol("join");
+    let zero = arena.alloc(0.into());
+    for (j, var) in vars.iter().enumerate().rev() {
+        let pos = arena.alloc((j as i64).into());
+        if let Binding::Special(name) = &names[j] {
+            let name = arena.alloc_symbol(name);
+            body_code = types::alloc!(arena, [[ld, [zero; pos]], [bind, name]; body_code]);
+        }
+
+        let (form, set_code) = match &var.init {
+            Init::None => continue,
+            Init::Default { form, .. } => (
+                form,
+                types::alloc!(arena, [[lset, [zero; pos]], [pop], [join]]),
+            ),
+            Init::Aux(form) => (
+                form,
+                types::alloc!(arena, [[lset, [zero; pos]], [pop]; body_code]),
+            ),
+        };
+        let frame = names
+            .iter()
+            .enumerate()
+            .map(|(k, binding)| {
+                if k < j {
+                    binding.clone()
+                } else {
+                    Binding::Variable(String::new())
+                }
+            })
+            .collect();
+        env.push_frame(frame);
+        let form_code = comp(form, arena, env, &set_code);
+        env.pop_frame();
+
+        let form_code = form_code?;
+        body_code = match var.init {
+            Init::Default { supplied, .. } => {
+                let supplied = arena.alloc((supplied as i64).into());
+                types::alloc!(
+                    arena,
+                    [[ld, [zero; supplied]], [sel, [[join]], form_code]; body_code]
+                )
+            }
+            _ => form_code,
+        };
+    }
+
+    match name {
+        Some(name) => Ok(types::alloc!(arena, [[ldf, params, body_code, name]; code])),
+        None => Ok(types::alloc!(arena, [[ldf, params, body_code]; code])),
+    }
+}
+
+/// Proclaim the variable named by a defvar or defparameter special
+fn proclaim_special(name: &types::RuccoExpRefStrong, env: &mut CompEnv) -> anyhow::Result<()> {
+    match name.borrow().as_symbol() {
+        Some(sym) => {
+            env.global.proclaim_special(sym);
+            Ok(())
+        }
+        None => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: "defvar".to_string(),
+            expected: types::RuccoDataType::Symbol,
+            actual: types::RuccoActualDataType::from(&*name.borrow()).with_datum(name),
+        }),
+    }
+}
+
+/// Return the name of a block or go target
+fn tag_name(tag: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<String> {
+    match &*tag.borrow() {
+        types::RuccoExp::Atom(
+            atom @ (types::RuccoAtom::Symbol(_)
+            | types::RuccoAtom::Int(_)
+            | types::RuccoAtom::BigInt(_)),
+        ) => Ok(atom.to_string()),
+        exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Symbol,
+            actual: types::RuccoActualDataType::from(exp).with_datum(tag),
+        }),
+    }
+}
+
+/// Turn (name params . body) of a named function into (params . body) with
+/// the body wrapped in a block of the name
+fn block_lambda(
+    def: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+) -> anyhow::Result<types::RuccoExpRefStrong> {
+    let name = def.borrow().car_weak()?;
+    let lambda = def.borrow().cdr()?;
+    let body = lambda.borrow().cdr()?;
+    let params = lambda.borrow().car_weak()?;
+    let block = arena.alloc_symbol("block");
+    let body = Rc::downgrade(&body);
+    let lambda = types::alloc!(arena, [params, [block, name; body]]);
+    Ok(lambda
+        .upgrade()
+        .ok_or(types::RuccoRuntimeErr::InvalidReference)?)
+}
+
+/// Compile the items of a tagbody
+///
+/// The forms between two tags form a segment falling through to the next
+/// one, go jumps to the segment following its tag.
+fn comp_tagbody(
+    items: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let mut tags = Vec::new();
+    let mut segments = vec![Vec::new()];
+    for item in items.borrow().to_vec()? {
+        if let types::RuccoExp::Atom(_) = &*item.borrow() {
+            tags.push(tag_name(&item, "tagbody")?);
+            segments.push(Vec::new());
+            continue;
+        }
+        segments
+            .last_mut()
+            .ok_or(types::RuccoRuntimeErr::Unreachable)?
+            .push(item.clone());
+    }
+
+    let tagbody = arena.alloc_symbol("tagbody");
+    let uncatch = arena.alloc_symbol("uncatch");
+    let pop = arena.alloc_symbol("pop");
+    let ldc = arena.alloc_symbol("ldc");
+    let nil = arena.alloc_symbol("nil");
+
+    env.push_frame(vec![Binding::Tags(tags)]);
+    let segment_codes = (|| {
+        let mut next = types::alloc!(arena, [[uncatch]]);
+        let mut segment_codes = Vec::with_capacity(segments.len());
+        for segment in segments.iter().rev() {
+            for form in segment.iter().rev() {
+                let pop_code = types::alloc!(arena, [[pop]; next]);
+                next = comp(&Rc::downgrade(form), arena, env, &pop_code)?;
+            }
+            segment_codes.push(next.clone());
+        }
+        segment_codes.reverse();
+        anyhow::Ok(segment_codes)
+    })();
+    env.pop_frame();
+
+    let segment_codes = arena.alloc_list(segment_codes?);
+    Ok(types::alloc!(arena, [[tagbody, segment_codes], [ldc, nil]; code]))
+}
+
+/// Compile (definitions . body) of flet or labels
+///
+/// flet closes the definitions over the enclosing environment and applies
+/// the body to them like a let.  labels first pushes a dummy frame with dum
+/// so the closures capture the frame they are stored in, and rap fills it in
+/// before entering the body.
+fn comp_flet(
+    flet: &types::RuccoExpRefStrong,
+    recursive: bool,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let name = if recursive { "labels" } else { "flet" };
+    let defs = flet.borrow().car()?.borrow().to_vec()?;
+    let body = flet.borrow().cdr()?;
+
+    let names = defs
+        .iter()
+        .map(|def| def.borrow().car())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let frame = names
+        .iter()
+        .map(|fname| match fname.borrow().as_symbol() {
+            Some(sym) => Ok(Binding::Function(sym.to_string())),
+            None => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                name: name.to_string(),
+                expected: types::RuccoDataType::Symbol,
+                actual: types::RuccoActualDataType::from(&*fname.borrow()).with_datum(fname),
+            }),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let ldf = arena.alloc_symbol("ldf");
+    let args = arena.alloc_symbol("args");
+    let rtn = arena.alloc_symbol("rtn");
+    let rtn_code = types::alloc!(arena, [[rtn]]);
+
+    env.push_frame(frame.clone());
+    let body_code = comp_body(&body, arena, env, &rtn_code);
+    env.pop_frame();
+    let body_code = body_code?;
+
+    let n = arena.alloc((defs.len() as i64).into());
+    let ap_code = if recursive {
+        let rap = arena.alloc_symbol("rap");
+        types::alloc!(arena, [[rap]; code])
+    } else {
+        comp_ap(arena, code)?
+    };
+    let args_code = types::alloc!(arena, [[args, n]; ap_code]);
+
+    if recursive {
+        env.push_frame(frame);
+    }
+    let defs_code = defs.iter().rev().try_fold(args_code, |code, def| {
+        let lambda = block_lambda(def, arena)?;
+        comp_lambda(&lambda, Some(&def.borrow().car_weak()?), arena, env, &code)
+    });
+    if recursive {
+        env.pop_frame();
+    }
+    let defs_code = defs_code?;
+
+    let params = arena.alloc_list(names.iter().map(Rc::downgrade).collect());
+    let res = types::alloc!(arena, [[ldf, params, body_code]; defs_code]);
+    if recursive {
+        let dum = arena.alloc_symbol("dum");
+        return Ok(types::alloc!(arena, [[dum]; res]));
+    }
+    Ok(res)
+}
+
+/// Compile the argument of the function special form
+fn comp_function(
+    exp: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    if let Some((i, j)) = exp
+        .borrow()
+        .as_symbol()
+        .and_then(|sym| env.lookup_function(sym))
+    {
+        let ld = arena.alloc_symbol("ld");
+        let i = arena.alloc((i as i64).into());
+        let j = arena.alloc((j as i64).into());
+        return Ok(types::alloc!(arena, [[ld, [i; j]]; code]));
+    }
+    if exp.borrow().as_symbol().is_some() {
+        let ldgf = arena.alloc_symbol("ldgf");
+        let exp = Rc::downgrade(exp);
+        return Ok(types::alloc!(arena, [[ldgf, exp]; code]));
+    }
+
+    let head = exp.borrow().car()?;
+    let is_lambda = head.borrow().as_symbol() == Some("lambda");
+    if !is_lambda {
+        anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: "function".to_string(),
+            expected: types::RuccoDataType::Function,
+            actual: types::RuccoActualDataType::from(&*exp.borrow()).with_datum(exp),
+        });
+    }
+    comp_lambda(&exp.borrow().cdr()?, None, arena, env, code)
+}
+
+/// Return the operator and argument of a (quasiquote x), (unquote x) or
+/// (unquote-splicing x) form
+fn quasiquote_op(
+    exp: &types::RuccoExpRefStrong,
+) -> anyhow::Result<Option<(String, types::RuccoExpRefStrong)>> {
+    let exp_ = exp.borrow();
+    let types::RuccoExp::Cons { .. } = &*exp_ else {
+        return Ok(None);
+    };
+    let op = exp_.car()?;
+    let op = match op.borrow().as_symbol() {
+        Some(sym @ ("quasiquote" | "unquote" | "unquote-splicing")) => sym.to_string(),
+        _ => return Ok(None),
+    };
+    let rest = exp_.cdr()?;
+    let rest_ = rest.borrow();
+    match &*rest_ {
+        types::RuccoExp::Cons { .. } if rest_.cdr()?.borrow().is_nil() => {
+            Ok(Some((op, rest_.car()?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Expand a backquote template into a form building it with list and append
+fn quasiquote(
+    exp: &types::RuccoExpRefStrong,
+    depth: usize,
+    arena: &mut types::RuccoArena,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let quote = arena.alloc_symbol("quote");
+    let list = arena.alloc_symbol("list");
+    if !matches!(&*exp.borrow(), types::RuccoExp::Cons { .. }) {
+        let exp = Rc::downgrade(exp);
+        return Ok(types::alloc!(arena, [quote, exp]));
+    }
+
+    if let Some((op, arg)) = quasiquote_op(exp)? {
+        let inner_depth = match op.as_str() {
+            "unquote" if depth == 0 => return Ok(Rc::downgrade(&arg)),
+            "unquote-splicing" if depth == 0 => {
+                anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "quasiquote".to_string(),
+                    expected: types::RuccoDataType::List,
+                    actual: types::RuccoActualDataType::from(&*exp.borrow()).with_datum(exp),
+                })
+            }
+            "quasiquote" => depth + 1,
+            _ => depth - 1,
+        };
+        let op = arena.alloc_symbol(&op);
+        return match quasiquote_op(&arg)? {
+            // ,@,@x splices the inner list into the outer form
+            Some((inner_op, inner_arg)) if inner_op == "unquote-splicing" && inner_depth == 0 => {
+                let append = arena.alloc_symbol("append");
+                let inner_arg = Rc::downgrade(&inner_arg);
+                Ok(types::alloc!(
+                    arena,
+                    [append, [list, [quote, op]], inner_arg]
+                ))
+            }
+            _ => {
+                let inner = quasiquote(&arg, inner_depth, arena)?;
+                Ok(types::alloc!(arena, [list, [quote, op], inner]))
+            }
+        };
+    }
+
+    let mut segments = Vec::new();
+    let mut items = Vec::new();
+    let mut cur = exp.clone();
+    loop {
+        let next = {
+            let cur_ = cur.borrow();
+            if cur_.is_nil() {
+                break;
+            }
+            if !matches!(&*cur_, types::RuccoExp::Cons { .. }) || quasiquote_op(&cur)?.is_some() {
+                // dotted tail: (a . b) or (a . ,b)
+                if !items.is_empty() {
+                    segments.push(types::alloc!(arena, [list; (arena.alloc_list(items))]));
+                    items = Vec::new();
+                }
+                segments.push(quasiquote(&cur, depth, arena)?);
+                break;
+            }
+
+            let elem = cur_.car()?;
+            match quasiquote_op(&elem)? {
+                Some((op, arg)) if op == "unquote-splicing" && depth == 0 => {
+                    if !items.is_empty() {
+                        segments.push(types::alloc!(arena, [list; (arena.alloc_list(items))]));
+                        items = Vec::new();
+                    }
+                    segments.push(Rc::downgrade(&arg));
+                }
+                _ => items.push(quasiquote(&elem, depth, arena)?),
+            }
+            cur_.cdr()?
+        };
+        cur = next;
+    }
+    if !items.is_empty() {
+        segments.push(types::alloc!(arena, [list; (arena.alloc_list(items))]));
+    }
+
+    if segments.len() == 1 {
+        return Ok(segments.remove(0));
+    }
+    let append = arena.alloc_symbol("append");
+    let segments = arena.alloc_list(segments);
+    Ok(types::alloc!(arena, [append; segments]))
+}
+
+/// Compile assignments to lexical or global variables
+fn comp_setq(
+    args: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let args = args.borrow().to_vec()?;
+    if args.len() % 2 != 0 {
+        anyhow::bail!(types::RuccoRuntimeErr::WrongNumberOfArguments {
+            name: "setq".to_string(),
+            expected: types::RuccoArity::exact(args.len() + 1),
+            actual: args.len(),
+        });
+    }
+    if args.is_empty() {
+        let ldc = arena.alloc_symbol("ldc");
+        let nil = types::alloc!(arena, []);
+        return Ok(types::alloc!(arena, [[ldc, nil]; code]));
+    }
+
+    let pop = arena.alloc_symbol("pop");
+    let mut res = code.clone();
+    for (k, pair) in args.chunks(2).enumerate().rev() {
+        let [var, value] = pair else { unreachable!() };
+        if k != args.len() / 2 - 1 {
+            res = types::alloc!(arena, [[pop]; res]);
+        }
+
+        let name = match var.borrow().as_symbol() {
+            Some(name) => name.to_string(),
+            None => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                name: "setq".to_string(),
+                expected: types::RuccoDataType::Symbol,
+                actual: types::RuccoActualDataType::from(&*var.borrow()).with_datum(var),
+            }),
+        };
+        res = match env.lookup(&name) {
+            Some((i, j)) => {
+                let lset = arena.alloc_symbol("lset");
+                let i = arena.alloc((i as i64).into());
+                let j = arena.alloc((j as i64).into());
+                types::alloc!(arena, [[lset, [i; j]]; res])
+            }
+            None => {
+                let gset = arena.alloc_symbol("gset");
+                let var = Rc::downgrade(var);
+                types::alloc!(arena, [[gset, var]; res])
+            }
+        };
+        res = comp(&Rc::downgrade(value), arena, env, &res)?;
+    }
+    Ok(res)
+}
+
+/// Compile let as an application of a lambda expression
+fn comp_let(
+    let_: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let bindings = let_.borrow().car()?.borrow().to_vec()?;
+    let body = let_.borrow().cdr_weak()?;
+    let nil = types::alloc!(arena, []);
+
+    let mut params = Vec::with_capacity(bindings.len());
+    let mut values = Vec::with_capacity(bindings.len());
+    for binding in bindings {
+        if binding.borrow().as_symbol().is_some() {
+            params.push(Rc::downgrade(&binding));
+            values.push(nil.clone());
+        } else {
+            let [param, value] = binding.borrow().extract_args::<1, 2>("let", &nil)?;
+            params.push(Rc::downgrade(&param));
+            values.push(Rc::downgrade(&value));
+        }
+    }
+
+    let lambda = arena.alloc_symbol("lambda");
+    let params = arena.alloc_list(params);
+    let values = arena.alloc_list(values);
+    let exp = types::alloc!(arena, [[lambda, params; body]; values]);
+    comp(&exp, arena, env, code)
+}
+
+/// Compile multiple-value-bind as a lambda applied to the values of the form
+///
+/// Missing values are nil and extra values are ignored.
+fn comp_multiple_value_bind(
+    mvb: &types::RuccoExpRefStrong,
+    arena: &mut types::RuccoArena,
+    env: &mut CompEnv,
+    code: &types::RuccoExpRef,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let vars = mvb.borrow().car()?;
+    let rest = mvb.borrow().cdr()?;
+    let form = rest.borrow().car_weak()?;
+    let body = rest.borrow().cdr_weak()?;
+
+    let mvargs = arena.alloc_symbol("mvargs");
+    let n = arena.alloc((vars.borrow().to_vec()?.len() as i64).into());
+    let ap_code = comp_ap(arena, code)?;
+    let mvargs_code = types::alloc!(arena, [[mvargs, n]; ap_code]);
+    let form_code = comp(&form, arena, env, &mvargs_code)?;
+    let lambda = arena.alloc((Rc::downgrade(&vars), body).into());
+    let lambda_ptr = lambda
+        .upgrade()
+        .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+    comp_lambda(&lambda_ptr, None, arena, env, &form_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_str(input: &str) -> String {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let exp = core::read(input, env, arena).unwrap();
+        let code = compile(&exp, env, arena).unwrap();
+        let code_ptr = code.upgrade().unwrap();
+        let res = code_ptr.borrow().to_string();
+        res
+    }
+
+    #[test]
+    fn test_compile_atom() {
+        assert_eq!(compile_str("1"), "((ldc 1) (stop))");
+        assert_eq!(compile_str("nil"), "((ldc nil) (stop))");
+        assert_eq!(compile_str("'a"), "((ldc a) (stop))");
+    }
+
+    #[test]
+    fn test_compile_symbol() {
+        assert_eq!(compile_str(":key"), "((ldc :key) (stop))");
+        assert_eq!(compile_str("x"), "((ldg x) (stop))");
+
+        let arena = &mut types::RuccoArena::default();
+        let genv = &mut core::default_env(arena);
+        let exp = core::read("(if x y z)", genv, arena).unwrap();
+        let stop = arena.alloc_symbol("stop");
+        let code = types::alloc!(arena, [[stop]]);
+        let env = &mut CompEnv::new(genv);
+        env.push_frame(vec![
+            Binding::Variable("x".to_string()),
+            Binding::Variable("y".to_string()),
+        ]);
+        env.push_frame(vec![Binding::Variable("y".to_string())]);
+        let code = comp(&exp, arena, env, &code).unwrap();
+        assert_eq!(
+            code.upgrade().unwrap().borrow().to_string(),
+            "((ld (1 . 0)) (sel ((ld (0 . 0)) (join)) ((ldg z) (join))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_lambda() {
+        assert_eq!(
+            compile_str("(lambda (x y) y)"),
+            "((ldf (x y) ((ld (0 . 1)) (rtn))) (stop))"
+        );
+        assert_eq!(
+            compile_str("(lambda (x) (lambda (y) x 'a))"),
+            "((ldf (x) ((ldf (y) ((ld (1 . 0)) (pop) (ldc a) (rtn))) (rtn))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_call() {
+        assert_eq!(
+            compile_str("((lambda (x) x) 1)"),
+            "((ldf (x) ((ld (0 . 0)) (rtn))) (ldc 1) (args 1) (ap) (stop))"
+        );
+        assert_eq!(
+            compile_str("(f 1 2)"),
+            "((ldgf f) (ldc 1) (ldc 2) (args 2) (ap) (stop))"
+        );
+        assert_eq!(compile_str("(funcall f)"), "((ldg f) (args 0) (ap) (stop))");
+    }
+
+    #[test]
+    fn test_compile_tail_call() {
+        assert_eq!(
+            compile_str("(lambda (f) (funcall f))"),
+            "((ldf (f) ((ld (0 . 0)) (args 0) (tap))) (stop))"
+        );
+        assert_eq!(
+            compile_str("(lambda (x) (if x (f x) (g)) (h))"),
+            "((ldf (x) ((ld (0 . 0)) (sel ((ldgf f) (ld (0 . 0)) (args 1) (ap) (join)) ((ldgf g) (args 0) (ap) (join))) (pop) (ldgf h) (args 0) (tap))) (stop))"
+        );
+        assert_eq!(
+            compile_str("(lambda (x) (if x (f x) x))"),
+            "((ldf (x) ((ld (0 . 0)) (selr ((ldgf f) (ld (0 . 0)) (args 1) (tap)) ((ld (0 . 0)) (rtn))))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_setq() {
+        assert_eq!(
+            compile_str("(setq x 1 y 2)"),
+            "((ldc 1) (gset x) (pop) (ldc 2) (gset y) (stop))"
+        );
+        assert_eq!(
+            compile_str("(lambda (x) (setq x 1))"),
+            "((ldf (x) ((ldc 1) (lset (0 . 0)) (rtn))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_primitive() {
+        assert_eq!(
+            compile_str("(car (cons 1 nil))"),
+            "((ldc 1) (ldc nil) (prim cons 2) (prim car 1) (stop))"
+        );
+        assert_eq!(
+            compile_str("(lambda (x) (+ x 1))"),
+            "((ldf (x) ((ld (0 . 0)) (ldc 1) (prim + 2) (rtn))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_defvar() {
+        assert_eq!(
+            compile_str("(defvar x 1)"),
+            "((ldc x) (prim boundp 1) (sel ((join)) ((ldc 1) (gset x) (pop) (join))) (ldc x) (stop))"
+        );
+        assert_eq!(
+            compile_str("(defparameter x 1)"),
+            "((ldc 1) (gset x) (pop) (ldc x) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_macro() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        core::rep("(defmacro my-if (c a b) (list 'if c b a))", env, arena).unwrap();
+        let exp = core::read("(my-if x 1 2)", env, arena).unwrap();
+        let code = compile(&exp, env, arena).unwrap();
+        assert_eq!(
+            code.upgrade().unwrap().borrow().to_string(),
+            "((ldg x) (sel ((ldc 2) (join)) ((ldc 1) (join))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_quasiquote() {
+        assert_eq!(
+            compile_str("`(a ,b)"),
+            "((ldc a) (ldg b) (prim list 2) (stop))"
+        );
+        assert_eq!(
+            compile_str("`(,@a b . c)"),
+            "((ldg a) (ldc b) (prim list 1) (ldc c) (prim append 3) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_if() {
+        assert_eq!(
+            compile_str("(if t 1 2)"),
+            "((ldc t) (sel ((ldc 1) (join)) ((ldc 2) (join))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_flet() {
+        assert_eq!(
+            compile_str("(flet ((f (x) x)) (f 1))"),
+            "((ldf (f) ((ld (0 . 0)) (ldc 1) (args 1) (tap))) (ldf (x) ((ld (0 . 0)) (rtn)) f) (args 1) (ap) (stop))"
+        );
+        assert_eq!(
+            compile_str("(flet ((f (x) (f x))) (function f))"),
+            "((ldf (f) ((ld (0 . 0)) (rtn))) (ldf (x) ((ldgf f) (ld (0 . 0)) (args 1) (tap)) f) (args 1) (ap) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_labels() {
+        assert_eq!(
+            compile_str("(labels ((f (x) (f x))) (f 1))"),
+            "((dum) (ldf (f) ((ld (0 . 0)) (ldc 1) (args 1) (tap))) (ldf (x) ((ld (1 . 0)) (ld (0 . 0)) (args 1) (tap)) f) (args 1) (rap) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_special() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        core::rep("(defvar *x* 1)", env, arena).unwrap();
+        let exp = core::read("(lambda (y *x*) (f y))", env, arena).unwrap();
+        let code = compile(&exp, env, arena).unwrap();
+        assert_eq!(
+            code.upgrade().unwrap().borrow().to_string(),
+            "((ldf (y *x*) ((ld (0 . 1)) (bind *x*) (ldgf f) (ld (0 . 0)) (args 1) (ap) (unbind 1) (rtn))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_block() {
+        assert_eq!(
+            compile_str("(block b 1 (return-from b 2))"),
+            "((block ((ldc 1) (pop) (ld (0 . 0)) (ldc 2) (throw) (uncatch))) (stop))"
+        );
+        assert_eq!(
+            compile_str("(catch 'a (throw 'a 1))"),
+            "((ldc a) (catch ((ldc a) (ldc 1) (throw) (uncatch))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_tagbody() {
+        assert_eq!(
+            compile_str("(tagbody (f) a (go a))"),
+            "((tagbody (((ldgf f) (args 0) (ap) (pop) (ld (0 . 0)) (go 1) (pop) (uncatch)) ((ld (0 . 0)) (go 1) (pop) (uncatch)))) (ldc nil) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_unwind_protect() {
+        assert_eq!(
+            compile_str("(unwind-protect (f) (g))"),
+            "((protect ((ldgf g) (args 0) (ap) (pop) (resume))) (ldgf f) (args 0) (ap) (unprotect) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_apply() {
+        assert_eq!(
+            compile_str("(apply f a l)"),
+            "((ldg f) (ldg a) (ldg l) (prim list* 2) (ap) (stop))"
+        );
+        assert_eq!(
+            compile_str("(lambda (x &rest r) r)"),
+            "((ldf (x &rest r) ((ld (0 . 1)) (rtn))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_lambda_list() {
+        assert_eq!(
+            compile_str("(lambda (a &optional (b a)) b)"),
+            "((ldf (a &optional (b nil)) ((ld (0 . 2)) (sel ((join)) ((ld (0 . 0)) (lset (0 . 1)) (pop) (join))) (ld (0 . 1)) (rtn))) (stop))"
+        );
+        assert_eq!(
+            compile_str("(lambda (&body b &key ((:k x) nil x-p) &aux y) y)"),
+            "((ldf (&rest b &key (:k x x-p) &aux y) ((ld (0 . 3)) (rtn))) (stop))"
+        );
+    }
+
+    #[test]
+    fn test_compile_multiple_values() {
+        assert_eq!(
+            compile_str("(multiple-value-bind (a b) x b)"),
+            "((ldf (a b) ((ld (0 . 1)) (rtn))) (ldg x) (mvargs 2) (ap) (stop))"
+        );
+        assert_eq!(
+            compile_str("(multiple-value-call f x y)"),
+            "((ldg f) (ldg x) (mvlist) (ldg y) (mvlist) (prim append 2) (ap) (stop))"
+        );
+        assert_eq!(
+            compile_str("(nth-value 1 x)"),
+            "((ldc 1) (ldg x) (mvlist) (prim nth 2) (stop))"
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use std::rc::Rc;
+
+use crate::core;
+use crate::types;
+use crate::vm;
+
+/// Slot of a condition class
+pub struct Slot {
+    pub name: String,
+    pub initarg: Option<String>,
+    /// Function of no arguments computing the default value
+    pub initform: Option<types::RuccoExpRef>,
+}
+
+/// Condition class defined by define-condition
+pub struct ConditionClass {
+    pub parents: Vec<String>,
+    pub slots: Vec<Slot>,
+}
+
+/// Return whether the class is the parent class or inherits from it
+pub fn is_subclass(env: &core::RuccoEnv, class: &str, parent: &str) -> bool {
+    class == parent
+        || env.get_condition_class(class).is_some_and(|c| {
+            c.parents
+                .iter()
+                .any(|class| is_subclass(env, class, parent))
+        })
+}
+
+/// Collect the slots of the class and its superclasses, most specific first
+fn collect_slots<'a>(
+    env: &'a core::RuccoEnv,
+    class: &str,
+    slots: &mut Vec<&'a Slot>,
+) -> anyhow::Result<()> {
+    let c = env
+        .get_condition_class(class)
+        .ok_or_else(|| types::RuccoRuntimeErr::VoidClass {
+            name: class.to_string(),
+        })?;
+    for slot in &c.slots {
+        if slots.iter().all(|s| s.name != slot.name) {
+            slots.push(slot);
+        }
+    }
+    for parent in &c.parents {
+        collect_slots(env, parent, slots)?;
+    }
+    Ok(())
+}
+
+/// Make a condition of the class, initargs is a property list
+pub fn make_condition(
+    vm: &mut vm::Vm,
+    class: &str,
+    initargs: &[types::RuccoExpRefStrong],
+) -> anyhow::Result<types::RuccoExpRef> {
+    if !initargs.len().is_multiple_of(2) {
+        anyhow::bail!(types::RuccoRuntimeErr::OddNumberOfKeywordArguments {
+            name: "make-condition".to_string(),
+        });
+    }
+
+    let mut slots = Vec::new();
+    collect_slots(vm.env, class, &mut slots)?;
+    let slots = slots
+        .iter()
+        .map(|slot| {
+            (
+                slot.name.clone(),
+                slot.initarg.clone(),
+                slot.initform.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let nil = vm.arena.alloc_symbol("nil");
+    let mut values = Vec::with_capacity(slots.len());
+    for (name, initarg, initform) in slots {
+        let arg = initargs
+            .chunks(2)
+            .find(|pair| initarg.is_some() && pair[0].borrow().as_symbol() == initarg.as_deref());
+        let value = match (arg, initform) {
+            (Some(pair), _) => Rc::downgrade(&pair[1]),
+            (None, Some(initform)) => vm::call(&initform, &nil, vm.env, vm.arena)?,
+            (None, None) => nil.clone(),
+        };
+        values.push((name, value));
+    }
+
+    Ok(vm.arena.alloc(types::RuccoExp::Condition {
+        class: class.to_string(),
+        slots: values,
+    }))
+}
+
+/// Return the report of a condition, the class followed by the format
+/// control applied to the format arguments for a simple condition, or by the
+/// slot values for the others
+pub fn report(env: &core::RuccoEnv, condition: &types::RuccoExp) -> String {
+    let fmt = env.float_format();
+    let types::RuccoExp::Condition { class, slots } = condition else {
+        return condition.printed(fmt).to_string();
+    };
+    let slot = |name: &str| {
+        slots
+            .iter()
+            .find(|(slot, _)| slot == name)
+            .and_then(|(_, value)| value.upgrade())
+    };
+    if is_subclass(env, class, "simple-condition") {
+        if let (Some(control), Some(arguments)) = (slot("format-control"), slot("format-arguments"))
+        {
+            if let (types::RuccoExp::Atom(types::RuccoAtom::String(control)), Ok(arguments)) =
+                (&*control.borrow(), arguments.borrow().to_vec())
+            {
+                return format!("{}: {}", class, format_control(env, control, &arguments));
+            }
+        }
+    }
+    let values = slots
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = value.upgrade()?;
+            let value = value.borrow().printed(fmt).to_string();
+            Some(format!("{} {}", name, value))
+        })
+        .collect::<Vec<_>>();
+    match values.as_slice() {
+        [] => class.clone(),
+        values => format!("{}: {}", class, values.join(", ")),
+    }
+}
+
+/// Apply a format control to its arguments
+///
+/// The directives are ~A and ~D, printing an argument as princ does, ~S
+/// printing it as prin1 does, ~% and ~~, the others are left as they are.
+fn format_control(
+    env: &core::RuccoEnv,
+    control: &str,
+    arguments: &[types::RuccoExpRefStrong],
+) -> String {
+    let fmt = env.float_format();
+    let mut arguments = arguments.iter();
+    let mut res = String::new();
+    let mut chars = control.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(d @ ('a' | 'A' | 'd' | 'D' | 's' | 'S')) => match arguments.next() {
+                Some(arg) if d.eq_ignore_ascii_case(&'s') => {
+                    res.push_str(&arg.borrow().printed(fmt).to_string())
+                }
+                Some(arg) => res.push_str(&format!("{:#}", arg.borrow().printed(fmt))),
+                None => res.extend(['~', d]),
+            },
+            Some('%') => res.push('\n'),
+            Some('~') => res.push('~'),
+            Some(d) => res.extend(['~', d]),
+            None => res.push('~'),
+        }
+    }
+    res
+}
+
+/// Convert a runtime error into the condition Lisp handlers see
+///
+/// Return None for errors that are internal to the machine.
+pub fn from_error(
+    vm: &mut vm::Vm,
+    err: &anyhow::Error,
+) -> anyhow::Result<Option<types::RuccoExpRef>> {
+    let Some(err) = err.downcast_ref::<types::RuccoRuntimeErr>() else {
+        return Ok(None);
+    };
+
+    let mut initargs = Vec::new();
+    let mut initarg = |vm: &mut vm::Vm, key: &str, value: types::RuccoExpRef| {
+        initargs.push(vm.arena.alloc_symbol(key));
+        initargs.push(value);
+    };
+    let class = match err {
+        types::RuccoRuntimeErr::VoidVariable { name } => {
+            let name = vm.arena.alloc_symbol(name);
+            initarg(vm, ":name", name);
+            "unbound-variable"
+        }
+        types::RuccoRuntimeErr::VoidFunction { name } => {
+            let name = vm.arena.alloc_symbol(name);
+            initarg(vm, ":name", name);
+            "undefined-function"
+        }
+        types::RuccoRuntimeErr::WrongTypeArgument { actual, .. }
+        | types::RuccoRuntimeErr::WrongStructureTypeArgument { actual, .. } => {
+            // only the printed form is left of a datum taken from the contents
+            // of an object, as by the methods of RuccoExp
+            let datum = actual
+                .datum
+                .and_then(|address| vm.arena.lookup(address))
+                .unwrap_or_else(|| {
+                    vm.arena
+                        .alloc(types::RuccoAtom::String(actual.value.clone()).into())
+                });
+            let expected = match err {
+                types::RuccoRuntimeErr::WrongTypeArgument { expected, .. } => expected.name(),
+                types::RuccoRuntimeErr::WrongStructureTypeArgument { expected, .. } => expected,
+                _ => unreachable!(),
+            };
+            let expected = vm.arena.alloc_symbol(expected);
+            initarg(vm, ":datum", datum);
+            initarg(vm, ":expected-type", expected);
+            "type-error"
+        }
+        types::RuccoRuntimeErr::DivisionByZero { name, operands }
+        | types::RuccoRuntimeErr::FloatingPointOverflow { name, operands } => {
+            let name = vm.arena.alloc_symbol(name);
+            let operands = operands
+                .iter()
+                .map(|operand| vm.arena.alloc(operand.clone().into()))
+                .collect();
+            let operands = vm.arena.alloc_list(operands);
+            initarg(vm, ":operation", name);
+            initarg(vm, ":operands", operands);
+            match err {
+                types::RuccoRuntimeErr::DivisionByZero { .. } => "division-by-zero",
+                _ => "floating-point-overflow",
+            }
+        }
+        types::RuccoRuntimeErr::IndexOutOfRange { index, length, .. } => {
+            let datum = vm.arena.alloc((*index as i64).into());
+            let (integer, zero) = (vm.arena.alloc_symbol("integer"), vm.arena.alloc(0.into()));
+            let length = vm.arena.alloc((*length as i64).into());
+            let expected = types::alloc!(vm.arena, [integer, zero, [length]]);
+            initarg(vm, ":datum", datum);
+            initarg(vm, ":expected-type", expected);
+            "type-error"
+        }
+        types::RuccoRuntimeErr::InvalidBoundingIndices {
+            start, end, length, ..
+        } => {
+            // the end is checked against the length, the start against the end
+            let (index, bound) = if end > length {
+                (*end, *length)
+            } else {
+                (*start, *end)
+            };
+            let datum = vm.arena.alloc((index as i64).into());
+            let (integer, zero) = (vm.arena.alloc_symbol("integer"), vm.arena.alloc(0.into()));
+            let bound = vm.arena.alloc((bound as i64).into());
+            let expected = types::alloc!(vm.arena, [integer, zero, bound]);
+            initarg(vm, ":datum", datum);
+            initarg(vm, ":expected-type", expected);
+            "type-error"
+        }
+        types::RuccoRuntimeErr::IntegerOverflow { name } => {
+            let name = vm.arena.alloc_symbol(name);
+            initarg(vm, ":operation", name);
+            "arithmetic-error"
+        }
+        types::RuccoRuntimeErr::WrongNumberOfArguments { .. }
+        | types::RuccoRuntimeErr::UnknownKeywordArgument { .. }
+        | types::RuccoRuntimeErr::OddNumberOfKeywordArguments { .. }
+        | types::RuccoRuntimeErr::UnknownLambdaListKeyword { .. }
+        | types::RuccoRuntimeErr::VoidBlock { .. }
+        | types::RuccoRuntimeErr::VoidTag { .. }
+        | types::RuccoRuntimeErr::NoCatch { .. }
+        | types::RuccoRuntimeErr::VoidSlot { .. }
+        | types::RuccoRuntimeErr::VoidClass { .. }
+        | types::RuccoRuntimeErr::ReadOnlySlot { .. }
+        | types::RuccoRuntimeErr::MalformedArrayContents => {
+            // the message is the control string, with tildes escaped
+            let control = err.to_string().replace('~', "~~");
+            let control = vm.arena.alloc(types::RuccoAtom::String(control).into());
+            let arguments = vm.arena.alloc_symbol("nil");
+            initarg(vm, ":format-control", control);
+            initarg(vm, ":format-arguments", arguments);
+            match err {
+                types::RuccoRuntimeErr::WrongNumberOfArguments { .. }
+                | types::RuccoRuntimeErr::UnknownKeywordArgument { .. }
+                | types::RuccoRuntimeErr::OddNumberOfKeywordArguments { .. }
+                | types::RuccoRuntimeErr::UnknownLambdaListKeyword { .. } => "simple-program-error",
+                types::RuccoRuntimeErr::VoidBlock { .. }
+                | types::RuccoRuntimeErr::VoidTag { .. }
+                | types::RuccoRuntimeErr::NoCatch { .. } => "simple-control-error",
+                _ => "simple-error",
+            }
+        }
+        types::RuccoRuntimeErr::UnhandledCondition { .. }
+        | types::RuccoRuntimeErr::VoidFrame { .. }
+        | types::RuccoRuntimeErr::InvalidReference
+        | types::RuccoRuntimeErr::InvalidInstruction { .. }
+        | types::RuccoRuntimeErr::Unreachable => return Ok(None),
+    };
+    if vm.env.get_condition_class(class).is_none() {
+        return Ok(None);
+    }
+
+    let initargs = initargs
+        .iter()
+        .map(|arg| {
+            arg.upgrade()
+                .ok_or(types::RuccoRuntimeErr::InvalidReference)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(make_condition(vm, class, &initargs)?))
+}
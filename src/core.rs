@@ -1,33 +1,956 @@
 use crate::compiler;
+use crate::condition;
+use crate::primitive;
 use crate::reader;
+use crate::structure;
 use crate::types;
+use crate::vm;
 
-pub type RuccoEnv = std::collections::HashMap<String, String>;
+/// Global environment
+///
+/// Variables and functions live in separate namespaces, values are stored in
+/// the arena.  Variables proclaimed special by defvar and defparameter are
+/// bound dynamically: the VM saves their global value on binding and restores
+/// it on exit.
+#[derive(Default)]
+pub struct RuccoEnv {
+    variables: std::collections::HashMap<String, types::RuccoExpRef>,
+    specials: std::collections::HashSet<String>,
+    functions: std::collections::HashMap<String, types::RuccoExpRef>,
+    macros: std::collections::HashMap<String, types::RuccoExpRef>,
+    conditions: std::collections::HashMap<String, condition::ConditionClass>,
+    structures: std::collections::HashMap<String, structure::StructureClass>,
+    gensym_counter: usize,
+}
+
+impl RuccoEnv {
+    pub fn get_variable(&self, name: &str) -> Option<&types::RuccoExpRef> {
+        self.variables.get(name)
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: &types::RuccoExpRef) {
+        self.variables.insert(name.to_string(), value.clone());
+    }
+
+    pub fn remove_variable(&mut self, name: &str) -> Option<types::RuccoExpRef> {
+        self.variables.remove(name)
+    }
+
+    pub fn is_special(&self, name: &str) -> bool {
+        self.specials.contains(name)
+    }
+
+    pub fn proclaim_special(&mut self, name: &str) {
+        self.specials.insert(name.to_string());
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<&types::RuccoExpRef> {
+        self.functions.get(name)
+    }
+
+    /// Return the name of the global function bound to the value
+    pub fn function_name(&self, value: &types::RuccoExpRef) -> Option<&str> {
+        self.functions
+            .iter()
+            .find(|(_, f)| f.ptr_eq(value))
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn set_function(&mut self, name: &str, value: &types::RuccoExpRef) {
+        self.macros.remove(name);
+        self.functions.insert(name.to_string(), value.clone());
+    }
+
+    pub fn get_macro(&self, name: &str) -> Option<&types::RuccoExpRef> {
+        self.macros.get(name)
+    }
+
+    pub fn set_macro(&mut self, name: &str, value: &types::RuccoExpRef) {
+        self.functions.remove(name);
+        self.macros.insert(name.to_string(), value.clone());
+    }
+
+    pub fn get_condition_class(&self, name: &str) -> Option<&condition::ConditionClass> {
+        self.conditions.get(name)
+    }
+
+    pub fn set_condition_class(&mut self, name: &str, class: condition::ConditionClass) {
+        self.conditions.insert(name.to_string(), class);
+    }
+
+    pub fn get_structure_class(&self, name: &str) -> Option<&structure::StructureClass> {
+        self.structures.get(name)
+    }
+
+    pub fn set_structure_class(&mut self, name: &str, class: structure::StructureClass) {
+        self.structures.insert(name.to_string(), class);
+    }
+
+    /// Return the format of floats read without an exponent marker, as
+    /// given by *read-default-float-format*
+    pub fn float_format(&self) -> types::FloatFormat {
+        let double = self
+            .get_variable("*read-default-float-format*")
+            .and_then(|format| format.upgrade())
+            .is_some_and(|format| {
+                matches!(
+                    format.borrow().as_symbol(),
+                    Some("double-float" | "long-float")
+                )
+            });
+        if double {
+            types::FloatFormat::Double
+        } else {
+            types::FloatFormat::Single
+        }
+    }
+
+    /// Allocate a fresh uninterned symbol
+    pub fn gensym(&mut self, arena: &mut types::RuccoArena) -> types::RuccoExpRef {
+        self.gensym_counter += 1;
+        arena.alloc(types::RuccoExp::new_symbol(format!(
+            "#:g{}",
+            self.gensym_counter
+        )))
+    }
+}
 
-pub fn read(buf: &str, arena: &mut types::RuccoArena) -> anyhow::Result<types::RuccoExpRef> {
-    let mut reader = reader::Reader::new(buf, arena);
+/// Lisp definitions loaded into every default environment
+const PRELUDE: &str = include_str!("prelude.lisp");
+
+pub fn default_env(arena: &mut types::RuccoArena) -> RuccoEnv {
+    let mut env = RuccoEnv::default();
+    for p in primitive::PRIMITIVES {
+        let f = arena.alloc(types::RuccoExp::Primitive(p));
+        env.set_function(p.name, &f);
+    }
+    load(PRELUDE, &mut env, arena).expect("prelude must load");
+    env
+}
+
+pub fn read(
+    buf: &str,
+    env: &mut RuccoEnv,
+    arena: &mut types::RuccoArena,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let mut reader = reader::Reader::new(buf, env, arena);
     reader.read()
 }
 
+/// Evaluate every form in the buffer in order
+pub fn load(buf: &str, env: &mut RuccoEnv, arena: &mut types::RuccoArena) -> anyhow::Result<()> {
+    let mut input = buf;
+    loop {
+        let mut reader = reader::Reader::new(input, env, arena);
+        if reader.is_eof() {
+            return Ok(());
+        }
+        let exp = reader.read()?;
+        input = &input[input.len() - reader.rest().len()..];
+        eval(&exp, env, arena)?;
+    }
+}
+
 pub fn eval(
     exp: &types::RuccoExpRef,
     env: &mut RuccoEnv,
     arena: &mut types::RuccoArena,
 ) -> anyhow::Result<types::RuccoExpRef> {
-    compiler::compile(exp, arena)
+    let values = eval_toplevel(exp, env, arena, &mut None)?;
+    Ok(values
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| arena.alloc_symbol("nil")))
+}
+
+/// Evaluate the form as a top-level form and return its values
+///
+/// The subforms of a top-level progn are processed as top-level forms in
+/// turn, so that a macro defined by one is expanded in the next.
+fn eval_toplevel(
+    exp: &types::RuccoExpRef,
+    env: &mut RuccoEnv,
+    arena: &mut types::RuccoArena,
+    debugger: &mut Option<vm::Debugger>,
+) -> anyhow::Result<Vec<types::RuccoExpRef>> {
+    let (exp, _) = compiler::macroexpand(exp, env, arena)?;
+    let exp_ptr = exp
+        .upgrade()
+        .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+    let is_progn = match &*exp_ptr.borrow() {
+        types::RuccoExp::Cons { car, .. } => car
+            .upgrade()
+            .is_some_and(|car| car.borrow().as_symbol() == Some("progn")),
+        _ => false,
+    };
+    if is_progn {
+        let mut values = vec![arena.alloc_symbol("nil")];
+        for form in exp_ptr.borrow().cdr()?.borrow().to_vec()? {
+            values = eval_toplevel(&std::rc::Rc::downgrade(&form), env, arena, debugger)?;
+        }
+        return Ok(values);
+    }
+
+    let code = compiler::compile(&exp, env, arena)?;
+    let mut vm = vm::Vm::new(&code, env, arena);
+    if let Some(debugger) = debugger {
+        vm.set_debugger(Box::new(|vm, err| debugger(vm, err)));
+    }
+    vm.run_values()
 }
 
 pub fn print(buf: &str) -> String {
     buf.to_string()
 }
 
+/// Print every value on its own line
+fn print_values(values: &[types::RuccoExpRef], env: &RuccoEnv) -> anyhow::Result<String> {
+    let values = values
+        .iter()
+        .map(|value| {
+            Ok(value
+                .upgrade()
+                .ok_or(types::RuccoRuntimeErr::InvalidReference)?
+                .borrow()
+                .printed(env.float_format())
+                .to_string())
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(print(&values.join("\n")))
+}
+
 pub fn rep(buf: &str, env: &mut RuccoEnv, arena: &mut types::RuccoArena) -> anyhow::Result<String> {
-    let exp = read(buf, arena)?;
-    let exp = eval(&exp, env, arena)?;
-    Ok(print(
-        &exp.upgrade()
-            .ok_or(types::RuccoRuntimeErr::InvalidReference)?
-            .borrow()
-            .to_string(),
-    ))
+    let exp = read(buf, env, arena)?;
+    let values = eval_toplevel(&exp, env, arena, &mut None)?;
+    print_values(&values, env)
+}
+
+/// Read, evaluate and print, entering the debugger on an unhandled error
+pub fn rep_with_debugger(
+    buf: &str,
+    env: &mut RuccoEnv,
+    arena: &mut types::RuccoArena,
+    debugger: vm::Debugger,
+) -> anyhow::Result<String> {
+    let exp = read(buf, env, arena)?;
+    let values = eval_toplevel(&exp, env, arena, &mut Some(debugger))?;
+    print_values(&values, env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rep_multiple_values() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(values 1 2)", env, arena).unwrap(), "1\n2");
+        assert_eq!(rep("(values)", env, arena).unwrap(), "");
+        assert_eq!(
+            rep(
+                "(multiple-value-bind (q r) (floor 17 5) (list q r))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(3 2)"
+        );
+        assert_eq!(
+            rep(
+                "(multiple-value-bind (a b c) (values 1 2) (list a b c))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(1 2 nil)"
+        );
+        // only forms in tail position pass extra values on
+        assert_eq!(
+            rep("(multiple-value-list (progn (values 1 2) 3))", env, arena).unwrap(),
+            "(3)"
+        );
+        assert_eq!(rep("(let ((x (values 1 2))) x)", env, arena).unwrap(), "1");
+        rep("(defun f (x) (if x (values 1 2) 3))", env, arena).unwrap();
+        assert_eq!(
+            rep("(multiple-value-list (f t))", env, arena).unwrap(),
+            "(1 2)"
+        );
+        assert_eq!(
+            rep(
+                "(multiple-value-call (function list) (f t) (f nil) (values))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(1 2 3)"
+        );
+        assert_eq!(
+            rep(
+                "(multiple-value-list (block nil (return (values 1 2))))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(1 2)"
+        );
+        assert_eq!(
+            rep(
+                "(multiple-value-list (unwind-protect (f t) (values 3 4)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(1 2)"
+        );
+        assert_eq!(
+            rep("(multiple-value-prog1 (values 1 2) 3)", env, arena).unwrap(),
+            "1\n2"
+        );
+        assert_eq!(rep("(nth-value 1 (floor 7 2))", env, arena).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_rep_read_default_float_format() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(list 1.5 1.5d0)", env, arena).unwrap(), "(1.5 1.5d0)");
+        assert_eq!(
+            rep(
+                "(setq *read-default-float-format* 'double-float)",
+                env,
+                arena
+            )
+            .unwrap(),
+            "double-float"
+        );
+        assert_eq!(rep("(list 1.5 1.5f0)", env, arena).unwrap(), "(1.5 1.5f0)");
+        assert_eq!(rep("(typep 1.5 'double-float)", env, arena).unwrap(), "t");
+        assert_eq!(
+            rep("(prin1-to-string 1.5f0)", env, arena).unwrap(),
+            "\"1.5f0\""
+        );
+
+        // the format belongs to the environment
+        let other = &mut default_env(arena);
+        assert_eq!(
+            rep("(list 1.5 1.5d0)", other, arena).unwrap(),
+            "(1.5 1.5d0)"
+        );
+        assert_eq!(rep("(typep 1.5 'single-float)", other, arena).unwrap(), "t");
+    }
+
+    #[test]
+    fn test_rep_defvar() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(defvar x 1)", env, arena).unwrap(), "x");
+        assert_eq!(rep("(defvar x 2)", env, arena).unwrap(), "x");
+        assert_eq!(rep("x", env, arena).unwrap(), "1");
+        assert_eq!(rep("(defparameter x 3)", env, arena).unwrap(), "x");
+        assert_eq!(rep("(symbol-value 'x)", env, arena).unwrap(), "3");
+        assert_eq!(rep("(defvar y)", env, arena).unwrap(), "y");
+        assert_eq!(rep("(boundp 'y)", env, arena).unwrap(), "nil");
+    }
+
+    #[test]
+    fn test_rep_setq() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(setq x 1 y 'a)", env, arena).unwrap(), "a");
+        assert_eq!(rep("x", env, arena).unwrap(), "1");
+        assert_eq!(rep("(let ((x 2)) (setq x 3) x)", env, arena).unwrap(), "3");
+        assert_eq!(rep("x", env, arena).unwrap(), "1");
+        assert_eq!(
+            rep(
+                "(funcall (let ((n 0)) (lambda () (setq n 't))))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "t"
+        );
+    }
+
+    #[test]
+    fn test_rep_setf() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(defvar x (list 1 2 3))", env, arena).unwrap(), "x");
+        assert_eq!(
+            rep("(setf (car x) 'a (cdr (cdr x)) nil)", env, arena).unwrap(),
+            "nil"
+        );
+        assert_eq!(rep("x", env, arena).unwrap(), "(a 2)");
+        assert_eq!(
+            rep("(defmacro second-cell (l) `(cdr ,l))", env, arena).unwrap(),
+            "second-cell"
+        );
+        assert_eq!(
+            rep("(setf (second-cell x) '(b))", env, arena).unwrap(),
+            "(b)"
+        );
+        assert_eq!(rep("(setf y 1)", env, arena).unwrap(), "1");
+        assert!(rep("(setf (foo) 1)", env, arena).is_err());
+    }
+
+    #[test]
+    fn test_rep_makunbound() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(boundp 'x)", env, arena).unwrap(), "nil");
+        assert_eq!(rep("(setq x 1)", env, arena).unwrap(), "1");
+        assert_eq!(rep("(boundp 'x)", env, arena).unwrap(), "t");
+        assert_eq!(rep("(makunbound 'x)", env, arena).unwrap(), "x");
+        assert_eq!(rep("(boundp 'x)", env, arena).unwrap(), "nil");
+        let err = rep("x", env, arena).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::VoidVariable {
+                name: "x".to_string()
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_rep_defun() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(defun f (x) (g x))", env, arena).unwrap(), "f");
+        assert_eq!(rep("(defun g (x) (if x 'a 'b))", env, arena).unwrap(), "g");
+        assert_eq!(rep("(f nil)", env, arena).unwrap(), "b");
+
+        // a primitive redefined by defun is no longer called inline
+        assert_eq!(rep("(abs -1)", env, arena).unwrap(), "1");
+        assert_eq!(
+            rep("(defun abs (x) (list 'abs x))", env, arena).unwrap(),
+            "abs"
+        );
+        assert_eq!(rep("(abs -1)", env, arena).unwrap(), "(abs -1)");
+    }
+
+    #[test]
+    fn test_rep_defmacro() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(
+            rep("(defmacro my-unless (c x) (list 'if c nil x))", env, arena).unwrap(),
+            "my-unless"
+        );
+        assert_eq!(rep("(my-unless nil 1)", env, arena).unwrap(), "1");
+        assert_eq!(rep("(my-unless t 1)", env, arena).unwrap(), "nil");
+        assert_eq!(
+            rep(
+                "(defmacro my-when (c x) (list 'my-unless (list 'not c) x))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "my-when"
+        );
+        assert_eq!(
+            rep("(macroexpand-1 '(my-when t 1))", env, arena).unwrap(),
+            "(my-unless (not t) 1)"
+        );
+        assert_eq!(
+            rep("(macroexpand '(my-when t 1))", env, arena).unwrap(),
+            "(if (not t) nil 1)"
+        );
+        assert_eq!(
+            rep("(macroexpand '(car x))", env, arena).unwrap(),
+            "(car x)"
+        );
+        assert_eq!(
+            rep("(defun f (x) (my-when x 'a))", env, arena).unwrap(),
+            "f"
+        );
+        assert_eq!(rep("(f t)", env, arena).unwrap(), "a");
+        assert_eq!(
+            rep("(progn (defmacro m () ''m) (progn (m)))", env, arena).unwrap(),
+            "m"
+        );
+        assert_eq!(
+            rep("(progn (defmacro m2 () '(values 1 2)) (m2))", env, arena).unwrap(),
+            "1\n2"
+        );
+    }
+
+    #[test]
+    fn test_rep_flet() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(
+            rep("(defun f (x) (list 'global x))", env, arena).unwrap(),
+            "f"
+        );
+        assert_eq!(
+            rep("(flet ((f (x) (list 'local x))) (f 1))", env, arena).unwrap(),
+            "(local 1)"
+        );
+        assert_eq!(
+            rep("(flet ((f (x) (f x))) (f 1))", env, arena).unwrap(),
+            "(global 1)"
+        );
+        assert_eq!(
+            rep("(flet ((car (x) x)) (car 1))", env, arena).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            rep("(let ((f 2)) (flet ((f (x) (+ x f))) (f f)))", env, arena).unwrap(),
+            "4"
+        );
+        assert_eq!(
+            rep(
+                "(labels ((fact (n) (if (= n 0) 1 (* n (fact (- n 1)))))) (fact 5))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "120"
+        );
+    }
+
+    #[test]
+    fn test_rep_special() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(defvar *x* 1)", env, arena).unwrap(), "*x*");
+        assert_eq!(rep("(defun get-x () *x*)", env, arena).unwrap(), "get-x");
+        assert_eq!(rep("(let ((*x* 2)) (get-x))", env, arena).unwrap(), "2");
+        assert_eq!(rep("*x*", env, arena).unwrap(), "1");
+        assert_eq!(rep("(defun f (*x*) (get-x))", env, arena).unwrap(), "f");
+        assert_eq!(rep("(list (f 3) *x*)", env, arena).unwrap(), "(3 1)");
+        assert_eq!(
+            rep("(let ((*x* 4)) (setq *x* 5) (get-x))", env, arena).unwrap(),
+            "5"
+        );
+        assert_eq!(rep("*x*", env, arena).unwrap(), "1");
+        assert!(rep("(let ((*x* 6)) (car 1))", env, arena).is_err());
+        assert_eq!(rep("*x*", env, arena).unwrap(), "1");
+        assert_eq!(
+            rep("(let ((y 7)) (defun g () y))", env, arena).unwrap(),
+            "g"
+        );
+        assert_eq!(rep("(let ((y 8)) (g))", env, arena).unwrap(), "7");
+    }
+
+    #[test]
+    fn test_rep_non_local_exit() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(
+            rep("(defun find-it (x lst) (labels ((walk (l) (if l (if (eq (car l) x) (return-from find-it l) (walk (cdr l)))))) (walk lst)))", env, arena)
+                .unwrap(),
+            "find-it"
+        );
+        assert_eq!(rep("(find-it 'b '(a b c))", env, arena).unwrap(), "(b c)");
+        assert_eq!(rep("(find-it 'd '(a b c))", env, arena).unwrap(), "nil");
+        assert_eq!(
+            rep("(defun thrower () (throw 'done 42))", env, arena).unwrap(),
+            "thrower"
+        );
+        assert_eq!(rep("(catch 'done (thrower) 1)", env, arena).unwrap(), "42");
+        assert!(rep("(thrower)", env, arena).is_err());
+
+        assert_eq!(rep("(defvar *x* 1)", env, arena).unwrap(), "*x*");
+        assert_eq!(
+            rep("(catch 'done (let ((*x* 2)) (thrower)))", env, arena).unwrap(),
+            "42"
+        );
+        assert_eq!(rep("*x*", env, arena).unwrap(), "1");
+        assert_eq!(
+            rep("(block b (let ((*x* 3)) (tagbody (let ((*x* 4)) (go out)) out) (return-from b *x*)))", env, arena)
+                .unwrap(),
+            "3"
+        );
+        assert_eq!(rep("*x*", env, arena).unwrap(), "1");
+
+        assert_eq!(
+            rep(
+                "(defun escape () (block b (lambda () (return-from b 1))))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "escape"
+        );
+        assert!(rep("(funcall (escape))", env, arena).is_err());
+        assert!(rep("(return-from nowhere 1)", env, arena).is_err());
+
+        assert_eq!(
+            rep("(defmacro bail (v) `(return-from f ,v))", env, arena).unwrap(),
+            "bail"
+        );
+        assert_eq!(
+            rep("(defun f (x) (if x (bail x)) 2)", env, arena).unwrap(),
+            "f"
+        );
+        assert_eq!(rep("(f 1)", env, arena).unwrap(), "1");
+        assert_eq!(rep("(f nil)", env, arena).unwrap(), "2");
+        assert_eq!(
+            rep(
+                "(defun loop-down (n) (if (= n 0) 'done (loop-down (- n 1))))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "loop-down"
+        );
+        assert_eq!(rep("(loop-down 100000)", env, arena).unwrap(), "done");
+
+        // blocks made by macros are expanded once however deep they nest
+        assert_eq!(
+            rep("(defvar *expansions* 0)", env, arena).unwrap(),
+            "*expansions*"
+        );
+        assert_eq!(
+            rep(
+                "(defmacro nest (k)
+                   (setq *expansions* (+ *expansions* 1))
+                   (if (= k 0) 1 (list 'block 'b (list 'nest (- k 1)))))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "nest"
+        );
+        assert_eq!(rep("(nest 16)", env, arena).unwrap(), "1");
+        assert_eq!(rep("*expansions*", env, arena).unwrap(), "17");
+    }
+
+    #[test]
+    fn test_rep_unwind_protect() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(defvar *log* nil)", env, arena).unwrap(), "*log*");
+        assert_eq!(rep("(defvar *x* 1)", env, arena).unwrap(), "*x*");
+        assert_eq!(
+            rep("(defun f () (return-from f 1) 2)", env, arena).unwrap(),
+            "f"
+        );
+        assert_eq!(
+            rep(
+                "(defun g () (unwind-protect (f) (setq *log* (cons 'g *log*))))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "g"
+        );
+        assert_eq!(rep("(g)", env, arena).unwrap(), "1");
+        assert_eq!(rep("*log*", env, arena).unwrap(), "(g)");
+
+        let err = rep(
+            "(let ((*x* 2)) (unwind-protect (car 1) (setq *log* (cons *x* *log*))))",
+            env,
+            arena,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongTypeArgument { .. })
+        ));
+        assert_eq!(rep("*log*", env, arena).unwrap(), "(2 g)");
+        assert_eq!(rep("*x*", env, arena).unwrap(), "1");
+
+        let err = rep("(unwind-protect (car 1) (cdr 2))", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongTypeArgument { name, .. }) if name == "cdr"
+        ));
+    }
+
+    #[test]
+    fn test_rep_handler_case() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(
+            rep("(handler-case (error 'type-error :datum 1 :expected-type 'list) (type-error (c) (list (type-error-datum c) (type-error-expected-type c))))", env, arena).unwrap(),
+            "(1 list)"
+        );
+        assert_eq!(
+            rep(
+                "(handler-case (car 1) (error (c) (type-error-expected-type c)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "list"
+        );
+        assert_eq!(
+            rep(
+                "(handler-case unbound (unbound-variable (c) (cell-error-name c)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "unbound"
+        );
+        assert_eq!(
+            rep(
+                "(handler-case (undefined) (undefined-function (c) (cell-error-name c)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "undefined"
+        );
+        assert_eq!(
+            rep(
+                "(handler-case ((lambda (x) x)) (program-error () 'program))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "program"
+        );
+        assert_eq!(
+            rep("(handler-case (handler-case (/ 1 0) (type-error () 'inner)) (arithmetic-error () 'outer))", env, arena)
+                .unwrap(),
+            "outer"
+        );
+        assert_eq!(
+            rep("(handler-case 1 (error () 2))", env, arena).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            rep("(let ((v (vector 1 2))) (handler-case (car v) (type-error (c) (eq v (type-error-datum c)))))", env, arena).unwrap(),
+            "t"
+        );
+        assert_eq!(
+            rep("(let ((f (lambda () 1))) (handler-case (+ f 1) (type-error (c) (eq f (type-error-datum c)))))", env, arena).unwrap(),
+            "t"
+        );
+        assert_eq!(
+            rep(
+                "(handler-case (car 1 2) (program-error (c) (simple-condition-format-control c)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "\"car: wrong number of arguments, 2 given, 1 expected\""
+        );
+        assert_eq!(
+            rep("(handler-case (/ 1 0) (division-by-zero (c) (list (arithmetic-error-operation c) (arithmetic-error-operands c))))", env, arena).unwrap(),
+            "(/ (1 0))"
+        );
+        assert_eq!(
+            rep("(handler-case (error \"boom ~a\" 1) (simple-error (c) (list (simple-condition-format-control c) (simple-condition-format-arguments c))))", env, arena).unwrap(),
+            "(\"boom ~a\" (1))"
+        );
+        assert_eq!(
+            rep("(handler-case (warn \"hey\") (simple-warning (c) (simple-condition-format-control c)))", env, arena).unwrap(),
+            "\"hey\""
+        );
+
+        let err = rep("(error 'control-error)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::UnhandledCondition { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rep_handler_bind() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(rep("(defvar *log* nil)", env, arena).unwrap(), "*log*");
+        assert_eq!(
+            rep("(handler-case (handler-bind ((error (lambda (c) (setq *log* (cons 'inner *log*))))) (handler-bind ((error (lambda (c) (setq *log* (cons 'outer *log*))))) (car 1))) (error () *log*))", env, arena).unwrap(),
+            "(inner outer)"
+        );
+
+        let err = rep(
+            "(handler-bind ((error (lambda (c) c))) (car 1))",
+            env,
+            arena,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongTypeArgument { .. })
+        ));
+        assert_eq!(rep("*handler-clusters*", env, arena).unwrap(), "nil");
+        assert_eq!(rep("(signal 'condition)", env, arena).unwrap(), "nil");
+    }
+
+    #[test]
+    fn test_rep_restart_case() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(
+            rep(
+                "(restart-case (invoke-restart 'add 1 2) (add (a b) (+ a b)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "3"
+        );
+        assert_eq!(
+            rep("(handler-bind ((error (lambda (c) (invoke-restart 'use-value 42)))) (restart-case (error 'simple-error) (use-value (v) v)))", env, arena).unwrap(),
+            "42"
+        );
+        assert!(rep("(restart-case (missing) (retry () 'a))", env, arena).is_err());
+        assert_eq!(
+            rep("(handler-bind ((warning (lambda (c) (invoke-restart 'muffle-warning)))) (warn 'warning) 'done)", env, arena).unwrap(),
+            "done"
+        );
+        assert_eq!(rep("(compute-restarts)", env, arena).unwrap(), "nil");
+        assert!(rep("(invoke-restart 'missing)", env, arena).is_err());
+    }
+
+    #[test]
+    fn test_rep_define_condition() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(
+            rep("(define-condition my-error (error) ((code :initarg :code :initform (+ 1 2) :reader my-error-code) extra))", env, arena).unwrap(),
+            "my-error"
+        );
+        assert_eq!(
+            rep("(make-condition 'my-error :code 1)", env, arena).unwrap(),
+            "#<my-error (code 1) (extra nil)>"
+        );
+        assert_eq!(
+            rep(
+                "(handler-case (error 'my-error) (error (c) (my-error-code c)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "3"
+        );
+        assert_eq!(
+            rep(
+                "(list (typep (make-condition 'my-error) 'serious-condition) (typep 1 'condition))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(t nil)"
+        );
+    }
+
+    #[test]
+    fn test_rep_defstruct() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut default_env(arena);
+        assert_eq!(
+            rep(
+                "(defstruct point (x (+ 1 2)) (y 0 :read-only t))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "point"
+        );
+        assert_eq!(
+            rep("(defvar p (make-point :y 2))", env, arena).unwrap(),
+            "p"
+        );
+        assert_eq!(rep("p", env, arena).unwrap(), "#S(point :x 3 :y 2)");
+        assert_eq!(
+            rep(
+                "(list (point-x p) (point-y p) (point-p p) (point-p 1))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(3 2 t nil)"
+        );
+        assert_eq!(rep("(setf (point-x p) 10)", env, arena).unwrap(), "10");
+        assert_eq!(
+            rep("(setf (point-y p) 10)", env, arena)
+                .unwrap_err()
+                .to_string(),
+            "slot y is read-only"
+        );
+        assert_eq!(
+            rep(
+                "(let ((q (copy-point p))) (list (eq p q) (equalp p q) q))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "(nil t #S(point :x 10 :y 2))"
+        );
+        assert_eq!(
+            rep("(point-x '#S(point :x 7 :y 2))", env, arena).unwrap(),
+            "7"
+        );
+        assert_eq!(rep("(point-x #S(point :y 1))", env, arena).unwrap(), "3");
+        assert_eq!(
+            rep(
+                "(equalp #S(point :y 2 :x 1) (make-point :x 1 :y 2))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "t"
+        );
+
+        assert_eq!(
+            rep(
+                "(defstruct (point3 (:include point) (:conc-name p3-)) z)",
+                env,
+                arena
+            )
+            .unwrap(),
+            "point3"
+        );
+        assert_eq!(
+            rep("(let ((q (make-point3 :z 5))) (list q (p3-x q) (point-x q) (point-p q) (point3-p p)))", env, arena).unwrap(),
+            "(#S(point3 :x 3 :y 0 :z 5) 3 3 t nil)"
+        );
+        assert_eq!(
+            rep(
+                "(handler-case (p3-z p) (type-error (c) (type-error-expected-type c)))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "point3"
+        );
+
+        assert_eq!(
+            rep("(defstruct (pair (:constructor pair (first &optional second)) (:predicate is-pair)) first second (rest 'x))", env, arena).unwrap(),
+            "pair"
+        );
+        assert_eq!(
+            rep("(list (pair 1) (is-pair (pair 1 2)))", env, arena).unwrap(),
+            "(#S(pair :first 1 :second nil :rest x) t)"
+        );
+
+        // the include options override the initforms of the slots in place
+        assert_eq!(
+            rep(
+                "(defstruct (point4 (:include point (x 4) (y 5))) z)",
+                env,
+                arena
+            )
+            .unwrap(),
+            "point4"
+        );
+        assert_eq!(
+            rep("(make-point4)", env, arena).unwrap(),
+            "#S(point4 :x 4 :y 5 :z nil)"
+        );
+        assert!(rep("(setf (point4-y (make-point4)) 1)", env, arena).is_err());
+        assert!(rep("(defstruct (point5 (:include point)) z (x 4))", env, arena).is_err());
+        assert!(rep("(defstruct point6 x y x)", env, arena).is_err());
+        assert!(rep("(defstruct (point7 (:include point (w 1))))", env, arena).is_err());
+
+        // initforms see the variables around defstruct, not the slots
+        assert_eq!(
+            rep(
+                "(progn (defvar a 1) (defstruct box (a 2) (b a)) (make-box))",
+                env,
+                arena
+            )
+            .unwrap(),
+            "#S(box :a 2 :b 1)"
+        );
+    }
 }
@@ -0,0 +1,244 @@
+use std::hash::Hash as _;
+use std::hash::Hasher as _;
+use std::rc::Rc;
+
+use crate::primitive;
+use crate::types;
+
+/// Number of conses and array elements hashed at most, keys differing only
+/// beyond them fall into the same bucket
+const HASH_DEPTH: usize = 8;
+
+/// Return the hash of the key, keys that are the same under the test have
+/// the same hash
+pub fn hash(key: &types::RuccoExpRefStrong, test: types::HashTest) -> u64 {
+    let mut state = std::collections::hash_map::DefaultHasher::new();
+    let mut depth = HASH_DEPTH;
+    hash_into(key, test, &mut depth, &mut state);
+    state.finish()
+}
+
+fn hash_into(
+    key: &types::RuccoExpRefStrong,
+    test: types::HashTest,
+    depth: &mut usize,
+    state: &mut std::collections::hash_map::DefaultHasher,
+) {
+    let exp = key.borrow();
+    match (&*exp, test) {
+        (types::RuccoExp::Atom(atom), types::HashTest::Equalp) if atom.is_number() => {
+            hash_number(atom, state)
+        }
+        (types::RuccoExp::Atom(types::RuccoAtom::Char(c)), types::HashTest::Equalp) => {
+            c.to_lowercase().for_each(|c| c.hash(state))
+        }
+        (types::RuccoExp::Atom(types::RuccoAtom::String(s)), types::HashTest::Equalp) => s
+            .chars()
+            .flat_map(char::to_lowercase)
+            .for_each(|c| c.hash(state)),
+        // fixnums and characters are immediate values
+        (types::RuccoExp::Atom(types::RuccoAtom::Int(i)), _) => i.hash(state),
+        (types::RuccoExp::Atom(types::RuccoAtom::Char(c)), _) => c.hash(state),
+        (_, types::HashTest::Eq) => Rc::as_ptr(key).hash(state),
+        (types::RuccoExp::Atom(atom), types::HashTest::Eql) if atom.is_number() => {
+            hash_atom(atom, state)
+        }
+        (_, types::HashTest::Eql) => Rc::as_ptr(key).hash(state),
+        (types::RuccoExp::Atom(atom), _) => hash_atom(atom, state),
+        (types::RuccoExp::Cons { car, cdr }, _) => {
+            for e in [car, cdr] {
+                if *depth == 0 {
+                    return;
+                }
+                *depth -= 1;
+                if let Some(e) = e.upgrade() {
+                    hash_into(&e, test, depth, state);
+                }
+            }
+        }
+        (
+            types::RuccoExp::Array {
+                items,
+                fill_pointer,
+                ..
+            },
+            types::HashTest::Equalp,
+        ) => {
+            let items = &items[..fill_pointer.unwrap_or(items.len())];
+            items.len().hash(state);
+            for e in items {
+                if *depth == 0 {
+                    return;
+                }
+                *depth -= 1;
+                if let Some(e) = e.upgrade() {
+                    hash_into(&e, test, depth, state);
+                }
+            }
+        }
+        (types::RuccoExp::Structure { name, .. }, types::HashTest::Equalp) => name.hash(state),
+        // closures are equal when they share their code and environment
+        (types::RuccoExp::Closure { code, env, .. }, _) => {
+            code.as_ptr().hash(state);
+            env.as_ptr().hash(state);
+        }
+        (types::RuccoExp::Primitive(p), _) => std::ptr::from_ref(*p).hash(state),
+        _ => Rc::as_ptr(key).hash(state),
+    }
+}
+
+/// Hash an atom as it is compared by eql and equal
+fn hash_atom(atom: &types::RuccoAtom, state: &mut std::collections::hash_map::DefaultHasher) {
+    std::mem::discriminant(atom).hash(state);
+    match atom {
+        types::RuccoAtom::Int(e) => e.hash(state),
+        types::RuccoAtom::BigInt(e) => e.hash(state),
+        types::RuccoAtom::Ratio(e) => e.hash(state),
+        // zeros of both signs compare equal
+        types::RuccoAtom::SingleFloat(e) => (e + 0.0).to_bits().hash(state),
+        types::RuccoAtom::DoubleFloat(e) => (e + 0.0).to_bits().hash(state),
+        types::RuccoAtom::Complex(real, imag) => {
+            hash_atom(real, state);
+            hash_atom(imag, state);
+        }
+        types::RuccoAtom::Char(e) => e.hash(state),
+        types::RuccoAtom::String(e) | types::RuccoAtom::Symbol(e) => e.hash(state),
+    }
+}
+
+/// Hash a number as it is compared by =, numbers of the same value have the
+/// same hash regardless of their types
+fn hash_number(atom: &types::RuccoAtom, state: &mut std::collections::hash_map::DefaultHasher) {
+    let (real, imag) = match atom {
+        types::RuccoAtom::Complex(real, imag) => (real.to_f64(), imag.to_f64()),
+        _ => (atom.to_f64(), 0.0),
+    };
+    (real + 0.0).to_bits().hash(state);
+    if imag != 0.0 {
+        imag.to_bits().hash(state);
+    }
+}
+
+/// Return whether the keys are the same under the test
+fn is_same(
+    a: &types::RuccoExpRefStrong,
+    b: &types::RuccoExpRefStrong,
+    test: types::HashTest,
+) -> bool {
+    match test {
+        types::HashTest::Eq => primitive::is_eq(a, b),
+        types::HashTest::Eql => primitive::is_eql(a, b),
+        types::HashTest::Equal => *a.borrow() == *b.borrow(),
+        types::HashTest::Equalp => primitive::is_equalp(a, b),
+    }
+}
+
+fn wrong_type(
+    exp: &types::RuccoExp,
+    datum: &types::RuccoExpRefStrong,
+    name: &str,
+) -> types::RuccoRuntimeErr {
+    types::RuccoRuntimeErr::WrongTypeArgument {
+        name: name.to_string(),
+        expected: types::RuccoDataType::HashTable,
+        actual: types::RuccoActualDataType::from(exp).with_datum(datum),
+    }
+}
+
+/// Return the hash of the key in the table and the position and value of
+/// its entry in the bucket, None if it has no entry
+///
+/// The table is only borrowed immutably so that keys referring to the table
+/// itself can be hashed and compared.
+fn find(
+    table: &types::RuccoExpRefStrong,
+    key: &types::RuccoExpRefStrong,
+    name: &str,
+) -> anyhow::Result<(u64, Option<(usize, types::RuccoExpRef)>)> {
+    let table_ = table.borrow();
+    let types::RuccoExp::HashTable { test, buckets } = &*table_ else {
+        anyhow::bail!(wrong_type(&table_, table, name));
+    };
+    let hash = hash(key, *test);
+    let entry = buckets.get(&hash).and_then(|bucket| {
+        bucket.iter().enumerate().find_map(|(index, (k, v))| {
+            let k = k.upgrade()?;
+            is_same(&k, key, *test).then(|| (index, v.clone()))
+        })
+    });
+    Ok((hash, entry))
+}
+
+/// Return the value of the key in the table, None if it has no entry
+pub fn get(
+    table: &types::RuccoExpRefStrong,
+    key: &types::RuccoExpRefStrong,
+) -> anyhow::Result<Option<types::RuccoExpRef>> {
+    let (_, entry) = find(table, key, "gethash")?;
+    Ok(entry.map(|(_, value)| value))
+}
+
+/// Set the value of the key in the table
+pub fn put(
+    table: &types::RuccoExpRefStrong,
+    key: &types::RuccoExpRefStrong,
+    value: &types::RuccoExpRefStrong,
+) -> anyhow::Result<()> {
+    let (hash, entry) = find(table, key, "gethash")?;
+    if let types::RuccoExp::HashTable { buckets, .. } = &mut *table.borrow_mut() {
+        let bucket = buckets.entry(hash).or_default();
+        match entry {
+            Some((index, _)) => bucket[index].1 = Rc::downgrade(value),
+            None => bucket.push((Rc::downgrade(key), Rc::downgrade(value))),
+        }
+    }
+    Ok(())
+}
+
+/// Remove the entry of the key from the table, return whether there was one
+pub fn remove(
+    table: &types::RuccoExpRefStrong,
+    key: &types::RuccoExpRefStrong,
+) -> anyhow::Result<bool> {
+    let (hash, entry) = find(table, key, "remhash")?;
+    let Some((index, _)) = entry else {
+        return Ok(false);
+    };
+    if let types::RuccoExp::HashTable { buckets, .. } = &mut *table.borrow_mut() {
+        if let Some(bucket) = buckets.get_mut(&hash) {
+            bucket.remove(index);
+            if bucket.is_empty() {
+                buckets.remove(&hash);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Return the keys and values of the entries in the table
+pub fn entries(
+    table: &types::RuccoExpRefStrong,
+    name: &str,
+) -> anyhow::Result<Vec<(types::RuccoExpRef, types::RuccoExpRef)>> {
+    match &*table.borrow() {
+        types::RuccoExp::HashTable { buckets, .. } => {
+            Ok(buckets.values().flatten().cloned().collect())
+        }
+        exp => anyhow::bail!(wrong_type(exp, table, name)),
+    }
+}
+
+pub fn clear(table: &types::RuccoExpRefStrong) -> anyhow::Result<()> {
+    match &mut *table.borrow_mut() {
+        types::RuccoExp::HashTable { buckets, .. } => buckets.clear(),
+        exp => anyhow::bail!(wrong_type(exp, table, "clrhash")),
+    }
+    Ok(())
+}
+
+pub fn test(table: &types::RuccoExpRefStrong) -> anyhow::Result<types::HashTest> {
+    match &*table.borrow() {
+        types::RuccoExp::HashTable { test, .. } => Ok(*test),
+        exp => anyhow::bail!(wrong_type(exp, table, "hash-table-test")),
+    }
+}
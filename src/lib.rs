@@ -1,4 +1,9 @@
 pub mod compiler;
+pub mod condition;
 pub mod core;
+pub mod hash_table;
+pub mod primitive;
 pub mod reader;
+pub mod structure;
 pub mod types;
+pub mod vm;
@@ -2,6 +2,149 @@ use anyhow::Context as _;
 
 use rucco::core;
 use rucco::types;
+use rucco::vm;
+
+const DEBUGGER_HELP: &str = "\
+:help              show this help
+:restarts          list the restarts
+:backtrace         show the function calls, innermost first
+:locals N          show the parameters of frame N
+:eval N EXP        evaluate EXP with the parameters of frame N in scope
+:abort             return to the top level
+N ARG...           invoke restart N with the values of ARG...
+EXP                evaluate EXP in the global environment";
+
+fn to_string(vm: &vm::Vm, exp: &types::RuccoExpRef) -> String {
+    exp.upgrade().map_or_else(String::new, |exp| {
+        exp.borrow().printed(vm.float_format()).to_string()
+    })
+}
+
+fn print_restarts(restarts: &[vm::Restart]) {
+    eprintln!("Restarts:");
+    for (i, restart) in restarts.iter().enumerate() {
+        match &restart.report {
+            Some(report) => eprintln!("  {}: [{}] {}", i, restart.name, report),
+            None => eprintln!(
+                "  {}: [{}] Invoke the {} restart",
+                i, restart.name, restart.name
+            ),
+        }
+    }
+    eprintln!("  {}: [abort] Return to the top level", restarts.len());
+}
+
+fn print_backtrace(vm: &vm::Vm) -> anyhow::Result<()> {
+    eprintln!("Backtrace:");
+    for (i, frame) in vm.backtrace()?.iter().enumerate() {
+        let args = frame
+            .locals
+            .iter()
+            .map(|(_, value)| format!(" {}", to_string(vm, value)))
+            .collect::<String>();
+        eprintln!("  {}: ({}{})", i, frame.name, args);
+    }
+    Ok(())
+}
+
+fn print_locals(vm: &vm::Vm, k: usize) -> anyhow::Result<()> {
+    let backtrace = vm.backtrace()?;
+    let frame = backtrace
+        .get(k)
+        .ok_or(types::RuccoRuntimeErr::VoidFrame { index: k })?;
+    for (name, value) in &frame.locals {
+        eprintln!("  {} = {}", name, to_string(vm, value));
+    }
+    Ok(())
+}
+
+/// Run a command of the break loop, return the action once one is chosen
+fn debugger_command(
+    vm: &mut vm::Vm,
+    restarts: &[vm::Restart],
+    line: &str,
+) -> anyhow::Result<Option<vm::DebuggerAction>> {
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let frame_index = |rest: &str| {
+        let (k, rest) = rest
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((rest.trim(), ""));
+        k.parse::<usize>()
+            .map(|k| (k, rest.to_string()))
+            .with_context(|| format!("Invalid frame number: {}", k))
+    };
+    match command {
+        ":help" => eprintln!("{}", DEBUGGER_HELP),
+        ":restarts" => print_restarts(restarts),
+        ":backtrace" => print_backtrace(vm)?,
+        ":locals" => print_locals(vm, frame_index(rest)?.0)?,
+        ":eval" => {
+            let (k, rest) = frame_index(rest)?;
+            let exp = vm.read(&rest)?;
+            let res = vm.eval_in_frame(k, &exp)?;
+            println!("{}", to_string(vm, &res));
+        }
+        ":abort" => return Ok(Some(vm::DebuggerAction::Abort)),
+        _ => match command.parse::<usize>() {
+            Ok(n) if n == restarts.len() => return Ok(Some(vm::DebuggerAction::Abort)),
+            Ok(n) => {
+                let restart = restarts
+                    .get(n)
+                    .map(|restart| &restart.restart)
+                    .with_context(|| format!("Invalid restart number: {}", n))?;
+                let args = vm.read(&format!("({})", rest))?;
+                let args_ptr = args
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let args = args_ptr
+                    .borrow()
+                    .to_vec()?
+                    .iter()
+                    .map(|arg| vm.eval(&std::rc::Rc::downgrade(arg)))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                return Ok(Some(vm::DebuggerAction::Restart {
+                    restart: restart.clone(),
+                    args,
+                }));
+            }
+            Err(_) => {
+                let exp = vm.read(line)?;
+                let res = vm.eval(&exp)?;
+                println!("{}", to_string(vm, &res));
+            }
+        },
+    }
+    Ok(None)
+}
+
+/// Break loop entered on an error no handler took
+fn debugger(
+    rl: &mut rustyline::Editor<()>,
+    vm: &mut vm::Vm,
+    err: &anyhow::Error,
+) -> vm::DebuggerAction {
+    let restarts = vm.restarts().unwrap_or_default();
+    eprintln!("Unhandled error: {}", vm.describe_error(err));
+    print_restarts(&restarts);
+    eprintln!("Type :help for the commands of the debugger.");
+    loop {
+        let line = match rl.readline("debug> ") {
+            Ok(line) => line,
+            Err(_) => return vm::DebuggerAction::Abort,
+        };
+        rl.add_history_entry(line.as_str());
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match debugger_command(vm, &restarts, line) {
+            Ok(Some(action)) => return action,
+            Ok(None) => (),
+            Err(e) => eprintln!("{}", vm.describe_error(&e)),
+        }
+    }
+}
 
 fn repl() -> anyhow::Result<()> {
     let mut rl = rustyline::Editor::<()>::new()?;
@@ -14,15 +157,23 @@ fn repl() -> anyhow::Result<()> {
     _ = rl.load_history(history_file_path);
 
     let mut arena = types::RuccoArena::default();
-    // let mut env = core::default_env();
-    let mut env = std::collections::HashMap::new();
+    let mut env = core::default_env(&mut arena);
 
     loop {
         let line = rl.readline("rucco> ");
         match &line {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                let res = core::rep(line, &mut env, &mut arena);
+                let mut debugged = false;
+                let res = core::rep_with_debugger(
+                    line,
+                    &mut env,
+                    &mut arena,
+                    Box::new(|vm, err| {
+                        debugged = true;
+                        debugger(&mut rl, vm, err)
+                    }),
+                );
 
                 match res {
                     Ok(res) => println!("{}", res),
@@ -30,7 +181,11 @@ fn repl() -> anyhow::Result<()> {
                         if let Some(types::RuccoReplErr::EmptyInput) = e.downcast_ref() {
                             break;
                         };
-                        eprintln!("{:#?}", e);
+                        // errors of the reader and the compiler never reach
+                        // the debugger
+                        if !debugged {
+                            eprintln!("Error: {:#}", e);
+                        }
                     }
                 }
             }
@@ -0,0 +1,488 @@
+;;; prelude.lisp --- definitions loaded into every default environment
+
+;;; Binding
+
+(defmacro let* (bindings &rest body)
+  (if (cdr bindings)
+      `(let (,(car bindings)) (let* ,(cdr bindings) ,@body))
+      `(let ,bindings ,@body)))
+
+;;; Conditions
+
+;; Handlers and restarts are lists of clusters, one cluster per
+;; handler-bind or restart-case, the innermost first.
+(defvar *handler-clusters* nil)
+(defvar *restart-clusters* nil)
+
+;; Slots are a name or (name . options) with :initarg, :initform, :reader
+;; and :accessor, the class options are ignored.
+(defmacro define-condition (name parents slots &rest options)
+  (let ((specs nil)
+        (readers nil))
+    (labels ((walk-options (slot-name options initarg initform)
+               (if options
+                   (let ((key (car options))
+                         (value (car (cdr options))))
+                     (if (null (cdr options))
+                         (error 'simple-program-error
+                                :format-control "define-condition: odd number of slot options"
+                                :format-arguments nil))
+                     (if (eq key :initarg)
+                         (setq initarg value))
+                     (if (eq key :initform)
+                         (setq initform `(lambda () ,value)))
+                     (if (if (eq key :reader) t (eq key :accessor))
+                         (setq readers
+                               (append readers
+                                       (list `(defun ,value (condition)
+                                                (slot-value condition ',slot-name))))))
+                     (walk-options slot-name (cdr (cdr options)) initarg initform))
+                   (setq specs
+                         (append specs
+                                 (list `(list ',slot-name ',initarg ,initform))))))
+             (walk (slots)
+               (if slots
+                   (let ((slot (car slots)))
+                     (if (symbolp slot)
+                         (walk-options slot nil nil nil)
+                         (walk-options (car slot) (cdr slot) nil nil))
+                     (walk (cdr slots))))))
+      (walk slots))
+    `(progn
+       (%define-condition ',name ',parents (list ,@specs))
+       ,@readers
+       ',name)))
+
+(defmacro handler-bind (bindings &rest body)
+  (let ((handlers nil))
+    (labels ((walk (bindings)
+               (if bindings
+                   (progn
+                     (setq handlers
+                           (append handlers
+                                   (list `(cons ',(car (car bindings))
+                                                ,(car (cdr (car bindings)))))))
+                     (walk (cdr bindings))))))
+      (walk bindings))
+    `(let ((*handler-clusters* (cons (list ,@handlers) *handler-clusters*)))
+       ,@body)))
+
+;; Each handler stores the condition and jumps to the tag of its clause,
+;; leaving the dynamic extent of the handlers before the clause runs.
+(defmacro handler-case (form &rest clauses)
+  (let ((block-name (gensym))
+        (c (gensym))
+        (x (gensym))
+        (bindings nil)
+        (items nil))
+    (labels ((walk (clauses)
+               (if clauses
+                   (let ((type (car (car clauses)))
+                         (var (car (car (cdr (car clauses)))))
+                         (body (cdr (cdr (car clauses))))
+                         (tag (gensym)))
+                     (setq bindings
+                           (append bindings
+                                   (list `(,type (lambda (,x) (setq ,c ,x) (go ,tag))))))
+                     (setq items
+                           (append items
+                                   (list tag
+                                         `(return-from ,block-name
+                                            ,(if var
+                                                 `(let ((,var ,c)) ,@body)
+                                                 `(progn ,@body))))))
+                     (walk (cdr clauses))))))
+      (walk clauses))
+    `(block ,block-name
+       (let ((,c nil))
+         (tagbody
+           (return-from ,block-name (handler-bind ,bindings ,form))
+           ,@items)))))
+
+;; Restarts are (name function report) lists, the function stores its
+;; arguments and jumps to the tag of its clause like handler-case.  A clause
+;; is (name lambda-list [:report string] . body).
+(defmacro restart-case (form &rest clauses)
+  (let ((block-name (gensym))
+        (a (gensym))
+        (x (gensym))
+        (restarts nil)
+        (items nil))
+    (labels ((walk (clauses)
+               (if clauses
+                   (let ((name (car (car clauses)))
+                         (params (car (cdr (car clauses))))
+                         (body (cdr (cdr (car clauses))))
+                         (report nil)
+                         (tag (gensym)))
+                     (if (eq (car body) :report)
+                         (progn
+                           (setq report (car (cdr body)))
+                           (setq body (cdr (cdr body)))))
+                     (setq restarts
+                           (append restarts
+                                   (list `(list ',name
+                                                (lambda (&rest ,x) (setq ,a ,x) (go ,tag))
+                                                ,report))))
+                     (setq items
+                           (append items
+                                   (list tag
+                                         `(return-from ,block-name
+                                            (apply (lambda ,params ,@body) ,a)))))
+                     (walk (cdr clauses))))))
+      (walk clauses))
+    `(block ,block-name
+       (let ((,a nil))
+         (tagbody
+           (return-from ,block-name
+             (let ((*restart-clusters* (cons (list ,@restarts) *restart-clusters*)))
+               ,form))
+           ,@items)))))
+
+(define-condition condition () ())
+(define-condition warning (condition) ())
+(define-condition serious-condition (condition) ())
+(define-condition error (serious-condition) ())
+
+(define-condition simple-condition (condition)
+  ((format-control :initarg :format-control
+                   :reader simple-condition-format-control)
+   (format-arguments :initarg :format-arguments
+                     :reader simple-condition-format-arguments)))
+(define-condition simple-error (simple-condition error) ())
+(define-condition simple-warning (simple-condition warning) ())
+
+(define-condition type-error (error)
+  ((datum :initarg :datum :reader type-error-datum)
+   (expected-type :initarg :expected-type :reader type-error-expected-type)))
+(define-condition program-error (error) ())
+(define-condition control-error (error) ())
+(define-condition simple-program-error (simple-condition program-error) ())
+(define-condition simple-control-error (simple-condition control-error) ())
+
+(define-condition cell-error (error)
+  ((name :initarg :name :reader cell-error-name)))
+(define-condition unbound-variable (cell-error) ())
+(define-condition undefined-function (cell-error) ())
+
+(define-condition arithmetic-error (error)
+  ((operation :initarg :operation :reader arithmetic-error-operation)
+   (operands :initarg :operands :reader arithmetic-error-operands)))
+(define-condition division-by-zero (arithmetic-error) ())
+(define-condition floating-point-overflow (arithmetic-error) ())
+
+;; A string datum is the format control of a condition of the default
+;; class, a symbol names the class of the condition to make.
+(defun %coerce-to-condition (datum arguments default-class)
+  (if (typep datum 'condition)
+      datum
+      (if (stringp datum)
+          (make-condition default-class
+                          :format-control datum
+                          :format-arguments arguments)
+          (apply 'make-condition datum arguments))))
+
+;; Each handler runs with only the clusters outside its own one active.
+(defun %signal (condition)
+  (labels ((run (handlers)
+             (if handlers
+                 (progn
+                   (if (typep condition (car (car handlers)))
+                       (funcall (cdr (car handlers)) condition))
+                   (run (cdr handlers)))))
+           (walk (clusters)
+             (if clusters
+                 (progn
+                   (let ((*handler-clusters* (cdr clusters)))
+                     (run (car clusters)))
+                   (walk (cdr clusters))))))
+    (walk *handler-clusters*)))
+
+(defun signal (datum &rest arguments)
+  (%signal (%coerce-to-condition datum arguments 'simple-condition))
+  nil)
+
+(defun error (datum &rest arguments)
+  (let ((condition (%coerce-to-condition datum arguments 'simple-error)))
+    (%signal condition)
+    (%unhandled-error condition)))
+
+(defun warn (datum &rest arguments)
+  (let ((condition (%coerce-to-condition datum arguments 'simple-warning)))
+    (restart-case
+        (progn
+          (%signal condition)
+          (%print-warning condition))
+      (muffle-warning () :report "Ignore the warning" nil))
+    nil))
+
+;;; Restarts
+
+(defun compute-restarts ()
+  (apply 'append *restart-clusters*))
+
+(defun restart-name (restart)
+  (car restart))
+
+(defun find-restart (identifier)
+  (labels ((walk (restarts)
+             (if restarts
+                 (if (if (eq (car restarts) identifier)
+                         t
+                         (eq (restart-name (car restarts)) identifier))
+                     (car restarts)
+                     (walk (cdr restarts))))))
+    (walk (compute-restarts))))
+
+(defun invoke-restart (identifier &rest arguments)
+  (let ((restart (find-restart identifier)))
+    (if restart
+        (apply (car (cdr restart)) arguments)
+        (error 'control-error))))
+
+;;; Reader
+
+;; Format of floats read without an exponent marker, either single-float
+;; or double-float.
+(defvar *read-default-float-format* 'single-float)
+
+;;; Places
+
+;; Alist from an accessor to the function updating its place, which is
+;; called with the arguments of the accessor followed by the new value and
+;; returns the new value.
+(defvar *setf-functions* nil)
+
+(defmacro defsetf (access-fn update-fn)
+  `(progn
+     (setq *setf-functions*
+           (cons (cons ',access-fn ',update-fn) *setf-functions*))
+     ',access-fn))
+
+(defun %setf-function (access-fn)
+  (labels ((walk (functions)
+             (if functions
+                 (if (eq (car (car functions)) access-fn)
+                     (cdr (car functions))
+                     (walk (cdr functions))))))
+    (walk *setf-functions*)))
+
+;; Places whose accessor has no update function are macroexpanded once and
+;; tried again.
+(defmacro setf (&rest pairs)
+  (if (cdr (cdr pairs))
+      `(progn
+         (setf ,(car pairs) ,(car (cdr pairs)))
+         (setf ,@(cdr (cdr pairs))))
+      (let ((place (car pairs))
+            (value (car (cdr pairs))))
+        (if (symbolp place)
+            `(setq ,place ,value)
+            (let ((update-fn (%setf-function (car place))))
+              (if update-fn
+                  `(,update-fn ,@(cdr place) ,value)
+                  (let ((expansion (macroexpand-1 place)))
+                    (if (eq expansion place)
+                        (error 'undefined-function
+                               :name (list 'setf (car place)))
+                        `(setf ,expansion ,value)))))))))
+
+(defun %set-car (cons value)
+  (rplaca cons value)
+  value)
+
+(defun %set-cdr (cons value)
+  (rplacd cons value)
+  value)
+
+(defsetf car %set-car)
+(defsetf cdr %set-cdr)
+(defsetf aref %set-aref)
+(defsetf row-major-aref %set-row-major-aref)
+(defsetf gethash %set-gethash)
+
+;;; Structures
+
+;; Return the symbol named by a string designator, nil for nil.
+(defun %designated-symbol (designator)
+  (if (stringp designator)
+      (intern designator)
+      designator))
+
+;; Return (name initform read-only) of a slot description, a name or
+;; (name initform . options) with :read-only.
+(defun %slot-spec (description)
+  (if (symbolp description)
+      (list description nil nil)
+      (labels ((read-only (options)
+                 (if options
+                     (if (eq (car options) :read-only)
+                         (car (cdr options))
+                         (read-only (cdr (cdr options)))))))
+        (list (car description)
+              (car (cdr description))
+              (read-only (cdr (cdr description)))))))
+
+(defun %find-slot-spec (name specs)
+  (if specs
+      (if (eq (car (car specs)) name)
+          (car specs)
+          (%find-slot-spec name (cdr specs)))))
+
+;; Replace the spec of the same slot, keeping its place.
+(defun %replace-slot-spec (spec specs)
+  (if specs
+      (cons (if (eq (car (car specs)) (car spec))
+                spec
+                (car specs))
+            (%replace-slot-spec spec (cdr specs)))))
+
+;; The options are :conc-name, :constructor, :copier, :predicate and
+;; :include, whose slot descriptions override the included slots in place.
+;; The slots of the included type come first, a read-only one stays
+;; read-only.
+(defmacro defstruct (name-and-options &rest slot-descriptions)
+  (let ((name (if (consp name-and-options)
+                  (car name-and-options)
+                  name-and-options))
+        (conc-name nil)
+        (constructors nil)
+        (default-constructor t)
+        (copier nil)
+        (predicate nil)
+        (include nil)
+        (specs nil)
+        (forms nil))
+    (setq conc-name (concatenate 'string (string name) "-"))
+    (setq copier (intern (concatenate 'string "copy-" (string name))))
+    (setq predicate (intern (concatenate 'string (string name) "-p")))
+    (labels ((walk-options (options)
+               (if options
+                   (let ((key (if (consp (car options)) (car (car options)) (car options)))
+                         (args (if (consp (car options)) (cdr (car options)) nil)))
+                     (if (eq key :conc-name)
+                         (setq conc-name (if (car args) (string (car args)) "")))
+                     (if (eq key :constructor)
+                         (if args
+                             (progn
+                               (setq default-constructor nil)
+                               (if (car args)
+                                   (setq constructors
+                                         (append constructors
+                                                 (list (cons (%designated-symbol (car args))
+                                                             (cdr args)))))))))
+                     (if (eq key :copier)
+                         (if args
+                             (setq copier (%designated-symbol (car args)))))
+                     (if (eq key :predicate)
+                         (if args
+                             (setq predicate (%designated-symbol (car args)))))
+                     (if (eq key :include)
+                         (progn
+                           (setq include (car args))
+                           (setq specs (%structure-slots include))
+                           (walk-overrides (cdr args))))
+                     (walk-options (cdr options)))))
+             (walk-overrides (descriptions)
+               (if descriptions
+                   (let ((spec (%slot-spec (car descriptions)))
+                         (parent nil))
+                     (setq parent (%find-slot-spec (car spec) specs))
+                     (if (null parent)
+                         (error 'simple-program-error
+                                :format-control "defstruct: no slot ~S in the included type ~S"
+                                :format-arguments (list (car spec) include)))
+                     (if (car (cdr (cdr parent)))
+                         (setq spec (list (car spec) (car (cdr spec)) t)))
+                     (setq specs (%replace-slot-spec spec specs))
+                     (walk-overrides (cdr descriptions)))))
+             (walk-slots (descriptions)
+               (if descriptions
+                   (progn
+                     ;; a string is documentation
+                     (if (not (stringp (car descriptions)))
+                         (let ((spec (%slot-spec (car descriptions))))
+                           (if (%find-slot-spec (car spec) specs)
+                               (error 'simple-program-error
+                                      :format-control "defstruct: duplicate slot ~S"
+                                      :format-arguments (list (car spec))))
+                           (setq specs (append specs (list spec)))))
+                     (walk-slots (cdr descriptions)))))
+             (keyword (spec)
+               (intern (concatenate 'string ":" (string (car spec)))))
+             (param-p (slot params)
+               (if params
+                   (if (eq slot (if (consp (car params)) (car (car params)) (car params)))
+                       t
+                       (param-p slot (cdr params)))))
+             ;; slots missing from a lambda list are initialized by their
+             ;; initform
+             (boa-initargs (params specs)
+               (if specs
+                   (append (list (keyword (car specs))
+                                 (if (param-p (car (car specs)) params)
+                                     (car (car specs))
+                                     (car (cdr (car specs)))))
+                           (boa-initargs params (cdr specs)))))
+             ;; the arguments are bound to fresh variables, so that the
+             ;; initforms do not see the slots before them
+             (make-default-constructor (constructor)
+               (let ((keys nil)
+                     (initargs nil))
+                 (labels ((walk (specs)
+                            (if specs
+                                (let ((var (gensym))
+                                      (supplied (gensym)))
+                                  (setq keys
+                                        (append keys
+                                                (list `((,(keyword (car specs)) ,var) nil ,supplied))))
+                                  (setq initargs
+                                        (append initargs
+                                                (list (keyword (car specs))
+                                                      `(if ,supplied ,var ,(car (cdr (car specs)))))))
+                                  (walk (cdr specs))))))
+                   (walk specs))
+                 `(defun ,constructor (&key ,@keys)
+                    (%make-structure ',name ,@initargs))))
+             (walk-constructors (constructors)
+               (if constructors
+                   (let ((constructor (car constructors)))
+                     (setq forms
+                           (append forms
+                                   (list
+                                    (if (cdr constructor)
+                                        (let ((params (car (cdr constructor))))
+                                          `(defun ,(car constructor) ,params
+                                             (%make-structure ',name ,@(boa-initargs params specs))))
+                                        (make-default-constructor (car constructor))))))
+                     (walk-constructors (cdr constructors)))))
+             (walk-accessors (specs)
+               (if specs
+                   (let ((slot (car (car specs)))
+                         (accessor (intern (concatenate 'string conc-name (string (car (car specs)))))))
+                     (setq forms
+                           (append forms
+                                   (list `(defun ,accessor (object)
+                                            (%structure-ref object ',name ',slot))
+                                         `(defsetf ,accessor
+                                              (lambda (object value)
+                                                (%structure-set object ',name ',slot value))))))
+                     (walk-accessors (cdr specs))))))
+      (walk-options (if (consp name-and-options) (cdr name-and-options) nil))
+      (walk-slots slot-descriptions)
+      (if default-constructor
+          (setq constructors
+                (append constructors
+                        (list (list (intern (concatenate 'string "make-" (string name))))))))
+      (setq forms (list `(%defstruct ',name ',include ',specs)))
+      (walk-constructors constructors)
+      (if predicate
+          (setq forms
+                (append forms
+                        (list `(defun ,predicate (object) (typep object ',name))))))
+      (if copier
+          (setq forms
+                (append forms
+                        (list `(defun ,copier (object) (copy-structure object))))))
+      (walk-accessors specs))
+    `(progn ,@forms ',name)))
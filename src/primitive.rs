@@ -0,0 +1,2690 @@
+use std::rc::Rc;
+
+use num_traits::FromPrimitive as _;
+use num_traits::Zero as _;
+
+use crate::compiler;
+use crate::condition;
+use crate::hash_table;
+use crate::structure;
+use crate::types;
+use crate::vm;
+
+type PrimitiveFn =
+    fn(&mut vm::Vm, &[types::RuccoExpRefStrong]) -> anyhow::Result<types::RuccoExpRef>;
+
+/// Native function callable from compiled code
+pub struct Primitive {
+    pub name: &'static str,
+    pub arity: types::RuccoArity,
+    func: PrimitiveFn,
+}
+
+impl std::fmt::Debug for Primitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Primitive({})", self.name)
+    }
+}
+
+impl Primitive {
+    pub fn call(
+        &self,
+        vm: &mut vm::Vm,
+        args: &[types::RuccoExpRefStrong],
+    ) -> anyhow::Result<types::RuccoExpRef> {
+        if !self.arity.contains(args.len()) {
+            anyhow::bail!(types::RuccoRuntimeErr::WrongNumberOfArguments {
+                name: self.name.to_string(),
+                expected: self.arity,
+                actual: args.len(),
+            });
+        }
+        (self.func)(vm, args)
+    }
+}
+
+pub static PRIMITIVES: &[Primitive] = &[
+    // cons
+    Primitive {
+        name: "car",
+        arity: types::RuccoArity::exact(1),
+        func: |_vm, args| Ok(list_ref(&args[0], "car")?.0),
+    },
+    Primitive {
+        name: "cdr",
+        arity: types::RuccoArity::exact(1),
+        func: |_vm, args| Ok(list_ref(&args[0], "cdr")?.1),
+    },
+    Primitive {
+        name: "cons",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let car = Rc::downgrade(&args[0]);
+            let cdr = Rc::downgrade(&args[1]);
+            Ok(vm.arena.alloc((car, cdr).into()))
+        },
+    },
+    Primitive {
+        name: "list",
+        arity: types::RuccoArity::new(0, None),
+        func: |vm, args| {
+            Ok(vm
+                .arena
+                .alloc_list(args.iter().map(Rc::downgrade).collect()))
+        },
+    },
+    Primitive {
+        name: "append",
+        arity: types::RuccoArity::new(0, None),
+        func: |vm, args| {
+            let Some((last, init)) = args.split_last() else {
+                return Ok(vm.arena.alloc_symbol("nil"));
+            };
+            let mut items = Vec::new();
+            for arg in init {
+                items.extend(arg.borrow().to_vec()?);
+            }
+            Ok(items.iter().rev().fold(Rc::downgrade(last), |cdr, car| {
+                vm.arena.alloc((Rc::downgrade(car), cdr).into())
+            }))
+        },
+    },
+    Primitive {
+        name: "list*",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let (last, init) = args
+                .split_last()
+                .ok_or(types::RuccoRuntimeErr::Unreachable)?;
+            Ok(init.iter().rev().fold(Rc::downgrade(last), |cdr, car| {
+                vm.arena.alloc((Rc::downgrade(car), cdr).into())
+            }))
+        },
+    },
+    Primitive {
+        name: "nth",
+        arity: types::RuccoArity::exact(2),
+        func: |_vm, args| {
+            let mut list = args[1].clone();
+            for _ in 0..to_index(&args[0], "nth")? {
+                let cdr = list_ref(&list, "nth")?.1;
+                list = cdr
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+            }
+            Ok(list_ref(&list, "nth")?.0)
+        },
+    },
+    Primitive {
+        name: "rplaca",
+        arity: types::RuccoArity::exact(2),
+        func: |_vm, args| {
+            args[0]
+                .borrow_mut()
+                .setcar(&Rc::downgrade(&args[1]))
+                .map_err(|err| with_datum(err, &args[0]))?;
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    Primitive {
+        name: "rplacd",
+        arity: types::RuccoArity::exact(2),
+        func: |_vm, args| {
+            args[0]
+                .borrow_mut()
+                .setcdr(&Rc::downgrade(&args[1]))
+                .map_err(|err| with_datum(err, &args[0]))?;
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    // predicates
+    Primitive {
+        name: "atom",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = !matches!(&*args[0].borrow(), types::RuccoExp::Cons { .. });
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "consp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = matches!(&*args[0].borrow(), types::RuccoExp::Cons { .. });
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "symbolp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = args[0].borrow().as_symbol().is_some();
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "numberp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = matches!(&*args[0].borrow(), types::RuccoExp::Atom(atom) if atom.is_number());
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "null",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = args[0].borrow().is_nil();
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "not",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = args[0].borrow().is_nil();
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "eq",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let res = is_eq(&args[0], &args[1]);
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "eql",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let res = is_eql(&args[0], &args[1]);
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "equal",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let res = *args[0].borrow() == *args[1].borrow();
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "equalp",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let res = is_equalp(&args[0], &args[1]);
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "typep",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let type_name = to_symbol(&args[1], "typep")?;
+            let res = typep(vm, &args[0].borrow(), &type_name);
+            Ok(bool_exp(vm, res))
+        },
+    },
+    // multiple values
+    Primitive {
+        name: "values",
+        arity: types::RuccoArity::new(0, None),
+        func: |vm, args| Ok(vm.set_values(args.iter().map(Rc::downgrade).collect())),
+    },
+    Primitive {
+        name: "values-list",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let values = args[0]
+                .borrow()
+                .to_vec()?
+                .iter()
+                .map(Rc::downgrade)
+                .collect();
+            Ok(vm.set_values(values))
+        },
+    },
+    // symbols
+    Primitive {
+        name: "boundp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "boundp")?;
+            let res = vm.env.get_variable(&name).is_some();
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "symbol-value",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "symbol-value")?;
+            Ok(vm
+                .env
+                .get_variable(&name)
+                .ok_or(types::RuccoRuntimeErr::VoidVariable { name })?
+                .clone())
+        },
+    },
+    Primitive {
+        name: "set",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "set")?;
+            let value = Rc::downgrade(&args[1]);
+            vm.env.set_variable(&name, &value);
+            Ok(value)
+        },
+    },
+    Primitive {
+        name: "makunbound",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "makunbound")?;
+            vm.env.remove_variable(&name);
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    Primitive {
+        name: "gensym",
+        arity: types::RuccoArity::exact(0),
+        func: |vm, _args| Ok(vm.env.gensym(vm.arena)),
+    },
+    Primitive {
+        name: "intern",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let name = to_str(&args[0], "intern")?;
+            Ok(vm.arena.alloc_symbol(&name))
+        },
+    },
+    // macros
+    Primitive {
+        name: "macroexpand-1",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let exp = Rc::downgrade(&args[0]);
+            Ok(compiler::macroexpand_1(&exp, vm.env, vm.arena)?.0)
+        },
+    },
+    Primitive {
+        name: "macroexpand",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let exp = Rc::downgrade(&args[0]);
+            Ok(compiler::macroexpand(&exp, vm.env, vm.arena)?.0)
+        },
+    },
+    // conditions
+    Primitive {
+        name: "%define-condition",
+        arity: types::RuccoArity::exact(3),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "define-condition")?;
+            let parents = args[1]
+                .borrow()
+                .to_vec()?
+                .iter()
+                .map(|parent| to_symbol(parent, "define-condition"))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let slots = args[2]
+                .borrow()
+                .to_vec()?
+                .iter()
+                .map(|spec| {
+                    let nil = vm.arena.alloc_symbol("nil");
+                    let [slot, initarg, initform] = spec
+                        .borrow()
+                        .extract_args::<3, 3>("define-condition", &nil)?;
+                    let initarg = match &*initarg.borrow() {
+                        exp if exp.is_nil() => None,
+                        exp => exp.as_symbol().map(str::to_string),
+                    };
+                    let has_initform = !initform.borrow().is_nil();
+                    Ok(condition::Slot {
+                        name: to_symbol(&slot, "define-condition")?,
+                        initarg,
+                        initform: has_initform.then(|| Rc::downgrade(&initform)),
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            vm.env
+                .set_condition_class(&name, condition::ConditionClass { parents, slots });
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    Primitive {
+        name: "make-condition",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let class = to_symbol(&args[0], "make-condition")?;
+            condition::make_condition(vm, &class, &args[1..])
+        },
+    },
+    Primitive {
+        name: "slot-value",
+        arity: types::RuccoArity::exact(2),
+        func: |_vm, args| {
+            let name = to_symbol(&args[1], "slot-value")?;
+            match &*args[0].borrow() {
+                types::RuccoExp::Condition { slots, .. } => Ok(slots
+                    .iter()
+                    .find(|(slot, _)| *slot == name)
+                    .ok_or(types::RuccoRuntimeErr::VoidSlot { name })?
+                    .1
+                    .clone()),
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "slot-value".to_string(),
+                    expected: types::RuccoDataType::Condition,
+                    actual: types::RuccoActualDataType::from(exp).with_datum(&args[0]),
+                }),
+            }
+        },
+    },
+    Primitive {
+        name: "%unhandled-error",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            anyhow::bail!(types::RuccoRuntimeErr::UnhandledCondition {
+                condition: condition::report(vm.env, &args[0].borrow()),
+            })
+        },
+    },
+    Primitive {
+        name: "%print-warning",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            eprintln!("WARNING: {}", condition::report(vm.env, &args[0].borrow()));
+            Ok(vm.arena.alloc_symbol("nil"))
+        },
+    },
+    // strings
+    Primitive {
+        name: "stringp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = matches!(
+                &*args[0].borrow(),
+                types::RuccoExp::Atom(types::RuccoAtom::String(_))
+            );
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "string",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let s = to_string_designator(&args[0], "string")?;
+            Ok(vm.arena.alloc(types::RuccoAtom::String(s).into()))
+        },
+    },
+    Primitive {
+        name: "length",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let len = match &*args[0].borrow() {
+                types::RuccoExp::Atom(types::RuccoAtom::String(s)) => s.chars().count(),
+                exp => exp.sequence_to_vec()?.len(),
+            };
+            Ok(vm.arena.alloc((len as i64).into()))
+        },
+    },
+    Primitive {
+        name: "char",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let s = to_str(&args[0], "char")?;
+            let index = to_index(&args[1], "char")?;
+            let c =
+                s.chars()
+                    .nth(index)
+                    .ok_or_else(|| types::RuccoRuntimeErr::IndexOutOfRange {
+                        name: "char".to_string(),
+                        index,
+                        length: s.chars().count(),
+                    })?;
+            Ok(vm.arena.alloc(types::RuccoAtom::Char(c).into()))
+        },
+    },
+    Primitive {
+        name: "string=",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let res = to_string_designator(&args[0], "string=")?
+                == to_string_designator(&args[1], "string=")?;
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "subseq",
+        arity: types::RuccoArity::new(2, Some(3)),
+        func: |vm, args| {
+            let start = to_index(&args[1], "subseq")?;
+            let end = match args.get(2) {
+                Some(end) if !end.borrow().is_nil() => Some(to_index(end, "subseq")?),
+                _ => None,
+            };
+            let s = match &*args[0].borrow() {
+                types::RuccoExp::Atom(types::RuccoAtom::String(s)) => s.clone(),
+                exp => {
+                    let items = exp.sequence_to_vec()?;
+                    let range = subrange("subseq", start, end, items.len())?;
+                    let items = items[range].iter().map(Rc::downgrade).collect::<Vec<_>>();
+                    if !typep(vm, exp, "vector") {
+                        return Ok(vm.arena.alloc_list(items));
+                    }
+                    return Ok(vm.arena.alloc(types::RuccoExp::Array {
+                        dimensions: vec![items.len()],
+                        items,
+                        adjustable: false,
+                        fill_pointer: None,
+                    }));
+                }
+            };
+            let chars = s.chars().collect::<Vec<_>>();
+            let range = subrange("subseq", start, end, chars.len())?;
+            let s = chars[range].iter().collect();
+            Ok(vm.arena.alloc(types::RuccoAtom::String(s).into()))
+        },
+    },
+    Primitive {
+        name: "concatenate",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let result_type = to_symbol(&args[0], "concatenate")?;
+            match result_type.as_str() {
+                "string" => {
+                    let mut res = String::new();
+                    for arg in &args[1..] {
+                        match &*arg.borrow() {
+                            types::RuccoExp::Atom(types::RuccoAtom::String(s)) => res.push_str(s),
+                            exp => {
+                                for c in exp.sequence_to_vec()? {
+                                    res.push(to_char(&c, "concatenate")?);
+                                }
+                            }
+                        }
+                    }
+                    Ok(vm.arena.alloc(types::RuccoAtom::String(res).into()))
+                }
+                "list" | "vector" | "simple-vector" => {
+                    let mut items = Vec::new();
+                    for arg in &args[1..] {
+                        match &*arg.borrow() {
+                            types::RuccoExp::Atom(types::RuccoAtom::String(s)) => {
+                                for c in s.chars() {
+                                    items.push(vm.arena.alloc(types::RuccoAtom::Char(c).into()));
+                                }
+                            }
+                            exp => items.extend(exp.sequence_to_vec()?.iter().map(Rc::downgrade)),
+                        }
+                    }
+                    if result_type == "list" {
+                        return Ok(vm.arena.alloc_list(items));
+                    }
+                    Ok(vm.arena.alloc(types::RuccoExp::Array {
+                        dimensions: vec![items.len()],
+                        items,
+                        adjustable: false,
+                        fill_pointer: None,
+                    }))
+                }
+                _ => anyhow::bail!(types::RuccoRuntimeErr::VoidClass { name: result_type }),
+            }
+        },
+    },
+    Primitive {
+        name: "string-upcase",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let s = to_string_designator(&args[0], "string-upcase")?
+                .chars()
+                .map(char_upcase)
+                .collect();
+            Ok(vm.arena.alloc(types::RuccoAtom::String(s).into()))
+        },
+    },
+    Primitive {
+        name: "string-downcase",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let s = to_string_designator(&args[0], "string-downcase")?
+                .chars()
+                .map(char_downcase)
+                .collect();
+            Ok(vm.arena.alloc(types::RuccoAtom::String(s).into()))
+        },
+    },
+    Primitive {
+        name: "string-trim",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let bag = match &*args[0].borrow() {
+                types::RuccoExp::Atom(types::RuccoAtom::String(s)) => s.chars().collect(),
+                exp => exp
+                    .to_vec()?
+                    .iter()
+                    .map(|c| to_char(c, "string-trim"))
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            };
+            let s = to_string_designator(&args[1], "string-trim")?;
+            let s = s.trim_matches(|c| bag.contains(&c)).to_string();
+            Ok(vm.arena.alloc(types::RuccoAtom::String(s).into()))
+        },
+    },
+    // characters
+    Primitive {
+        name: "characterp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = matches!(
+                &*args[0].borrow(),
+                types::RuccoExp::Atom(types::RuccoAtom::Char(_))
+            );
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "char-code",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let c = to_char(&args[0], "char-code")?;
+            Ok(vm.arena.alloc((c as i64).into()))
+        },
+    },
+    Primitive {
+        name: "code-char",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let code = to_index(&args[0], "code-char")?;
+            match u32::try_from(code).ok().and_then(char::from_u32) {
+                Some(c) => Ok(vm.arena.alloc(types::RuccoAtom::Char(c).into())),
+                None => Ok(vm.arena.alloc_symbol("nil")),
+            }
+        },
+    },
+    Primitive {
+        name: "char-upcase",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let c = to_char(&args[0], "char-upcase")?;
+            let c = char_upcase(c);
+            Ok(vm.arena.alloc(types::RuccoAtom::Char(c).into()))
+        },
+    },
+    Primitive {
+        name: "char-downcase",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let c = to_char(&args[0], "char-downcase")?;
+            let c = char_downcase(c);
+            Ok(vm.arena.alloc(types::RuccoAtom::Char(c).into()))
+        },
+    },
+    Primitive {
+        name: "alpha-char-p",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let c = to_char(&args[0], "alpha-char-p")?;
+            Ok(bool_exp(vm, c.is_alphabetic()))
+        },
+    },
+    Primitive {
+        name: "alphanumericp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let c = to_char(&args[0], "alphanumericp")?;
+            Ok(bool_exp(vm, c.is_alphabetic() || c.is_ascii_digit()))
+        },
+    },
+    Primitive {
+        name: "upper-case-p",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let c = to_char(&args[0], "upper-case-p")?;
+            Ok(bool_exp(vm, c.is_uppercase()))
+        },
+    },
+    Primitive {
+        name: "lower-case-p",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let c = to_char(&args[0], "lower-case-p")?;
+            Ok(bool_exp(vm, c.is_lowercase()))
+        },
+    },
+    Primitive {
+        name: "digit-char-p",
+        arity: types::RuccoArity::new(1, Some(2)),
+        func: |vm, args| {
+            let c = to_char(&args[0], "digit-char-p")?;
+            let radix = match args.get(1) {
+                Some(radix) => to_index(radix, "digit-char-p")?,
+                None => 10,
+            };
+            if !(2..=36).contains(&radix) {
+                anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "digit-char-p".to_string(),
+                    expected: types::RuccoDataType::Int,
+                    actual: types::RuccoActualDataType::from(&*args[1].borrow())
+                        .with_datum(&args[1]),
+                });
+            }
+            // only the ASCII digits and letters have weights, as numbers are
+            // read from them alone
+            match c.to_digit(radix as u32) {
+                Some(weight) => Ok(vm.arena.alloc(i64::from(weight).into())),
+                None => Ok(vm.arena.alloc_symbol("nil")),
+            }
+        },
+    },
+    Primitive {
+        name: "char=",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_chars(vm, args, "char=", |o| o.is_eq()),
+    },
+    Primitive {
+        name: "char/=",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let chars = args
+                .iter()
+                .map(|arg| to_char(arg, "char/="))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let res = chars
+                .iter()
+                .enumerate()
+                .all(|(i, c)| !chars[i + 1..].contains(c));
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "char<",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_chars(vm, args, "char<", |o| o.is_lt()),
+    },
+    Primitive {
+        name: "char>",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_chars(vm, args, "char>", |o| o.is_gt()),
+    },
+    Primitive {
+        name: "char<=",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_chars(vm, args, "char<=", |o| o.is_le()),
+    },
+    Primitive {
+        name: "char>=",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_chars(vm, args, "char>=", |o| o.is_ge()),
+    },
+    // structures
+    Primitive {
+        name: "%defstruct",
+        arity: types::RuccoArity::exact(3),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "defstruct")?;
+            let include = match &*args[1].borrow() {
+                exp if exp.is_nil() => None,
+                _ => Some(to_symbol(&args[1], "defstruct")?),
+            };
+            let slots = args[2]
+                .borrow()
+                .to_vec()?
+                .iter()
+                .map(|spec| {
+                    let nil = vm.arena.alloc_symbol("nil");
+                    let [slot, initform, read_only] =
+                        spec.borrow().extract_args::<3, 3>("defstruct", &nil)?;
+                    let read_only = !read_only.borrow().is_nil();
+                    Ok(structure::Slot {
+                        name: to_symbol(&slot, "defstruct")?,
+                        initform: Rc::downgrade(&initform),
+                        read_only,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            vm.env
+                .set_structure_class(&name, structure::StructureClass { include, slots });
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    Primitive {
+        name: "%structure-slots",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "structure-slots")?;
+            let class = vm
+                .env
+                .get_structure_class(&name)
+                .ok_or(types::RuccoRuntimeErr::VoidClass { name })?;
+            let slots = class
+                .slots
+                .iter()
+                .map(|slot| (slot.name.clone(), slot.initform.clone(), slot.read_only))
+                .collect::<Vec<_>>();
+            let specs = slots
+                .into_iter()
+                .map(|(slot, initform, read_only)| {
+                    let slot = vm.arena.alloc_symbol(&slot);
+                    let read_only = vm.arena.alloc_symbol(if read_only { "t" } else { "nil" });
+                    types::alloc!(vm.arena, [slot, initform, read_only])
+                })
+                .collect();
+            Ok(vm.arena.alloc_list(specs))
+        },
+    },
+    Primitive {
+        name: "%make-structure",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let name = to_symbol(&args[0], "make-structure")?;
+            structure::make_structure(vm.arena, &name, &args[1..])
+        },
+    },
+    Primitive {
+        name: "%structure-ref",
+        arity: types::RuccoArity::exact(3),
+        func: |vm, args| {
+            let type_name = to_symbol(&args[1], "structure-ref")?;
+            let slot = to_symbol(&args[2], "structure-ref")?;
+            let object = args[0].borrow();
+            match &*object {
+                types::RuccoExp::Structure { name, slots }
+                    if structure::is_subtype(vm.env, name, &type_name) =>
+                {
+                    Ok(slots
+                        .iter()
+                        .find(|(name, _)| *name == slot)
+                        .ok_or(types::RuccoRuntimeErr::VoidSlot { name: slot })?
+                        .1
+                        .clone())
+                }
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongStructureTypeArgument {
+                    name: "structure-ref".to_string(),
+                    expected: type_name,
+                    actual: types::RuccoActualDataType::from(exp).with_datum(&args[0]),
+                }),
+            }
+        },
+    },
+    Primitive {
+        name: "%structure-set",
+        arity: types::RuccoArity::exact(4),
+        func: |vm, args| {
+            let type_name = to_symbol(&args[1], "structure-set")?;
+            let slot = to_symbol(&args[2], "structure-set")?;
+            let mut object = args[0].borrow_mut();
+            match &mut *object {
+                types::RuccoExp::Structure { name, slots }
+                    if structure::is_subtype(vm.env, name, &type_name) =>
+                {
+                    let read_only = vm
+                        .env
+                        .get_structure_class(name)
+                        .and_then(|class| class.slots.iter().find(|s| s.name == slot))
+                        .is_some_and(|slot| slot.read_only);
+                    if read_only {
+                        anyhow::bail!(types::RuccoRuntimeErr::ReadOnlySlot { name: slot });
+                    }
+                    slots
+                        .iter_mut()
+                        .find(|(name, _)| *name == slot)
+                        .ok_or(types::RuccoRuntimeErr::VoidSlot { name: slot })?
+                        .1 = Rc::downgrade(&args[3]);
+                    Ok(Rc::downgrade(&args[3]))
+                }
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongStructureTypeArgument {
+                    name: "structure-set".to_string(),
+                    expected: type_name,
+                    actual: types::RuccoActualDataType::from(&*exp).with_datum(&args[0]),
+                }),
+            }
+        },
+    },
+    Primitive {
+        name: "copy-structure",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let copy = match &*args[0].borrow() {
+                types::RuccoExp::Structure { name, slots } => types::RuccoExp::Structure {
+                    name: name.clone(),
+                    slots: slots.clone(),
+                },
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "copy-structure".to_string(),
+                    expected: types::RuccoDataType::Structure,
+                    actual: types::RuccoActualDataType::from(exp).with_datum(&args[0]),
+                }),
+            };
+            Ok(vm.arena.alloc(copy))
+        },
+    },
+    // arrays
+    Primitive {
+        name: "arrayp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = matches!(&*args[0].borrow(), types::RuccoExp::Array { .. });
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "vectorp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = typep(vm, &args[0].borrow(), "vector");
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "make-array",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let is_list = matches!(&*args[0].borrow(), types::RuccoExp::Cons { .. })
+                || args[0].borrow().is_nil();
+            let dimensions = if is_list {
+                args[0]
+                    .borrow()
+                    .to_vec()?
+                    .iter()
+                    .map(|dimension| to_index(dimension, "make-array"))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            } else {
+                vec![to_index(&args[0], "make-array")?]
+            };
+            if !args[1..].len().is_multiple_of(2) {
+                anyhow::bail!(types::RuccoRuntimeErr::OddNumberOfKeywordArguments {
+                    name: "make-array".to_string(),
+                });
+            }
+
+            let mut initial_element = None;
+            let mut initial_contents = None;
+            let mut adjustable = false;
+            let mut fill_pointer = None;
+            // the leftmost occurrence of a keyword takes precedence
+            for pair in args[1..].chunks(2).rev() {
+                match to_symbol(&pair[0], "make-array")?.as_str() {
+                    ":initial-element" => initial_element = Some(Rc::downgrade(&pair[1])),
+                    ":initial-contents" => initial_contents = Some(pair[1].clone()),
+                    ":adjustable" => adjustable = !pair[1].borrow().is_nil(),
+                    ":fill-pointer" => fill_pointer = Some(pair[1].clone()),
+                    ":element-type" => {}
+                    keyword => anyhow::bail!(types::RuccoRuntimeErr::UnknownKeywordArgument {
+                        name: "make-array".to_string(),
+                        keyword: keyword.to_string(),
+                    }),
+                }
+            }
+
+            let size = dimensions.iter().product();
+            let items = match initial_contents {
+                Some(contents) => {
+                    let (contents_dimensions, items) =
+                        types::RuccoExp::array_contents(&contents, dimensions.len())?;
+                    if contents_dimensions != dimensions {
+                        anyhow::bail!(types::RuccoRuntimeErr::MalformedArrayContents);
+                    }
+                    items
+                }
+                None => {
+                    let element = initial_element.unwrap_or_else(|| vm.arena.alloc_symbol("nil"));
+                    vec![element; size]
+                }
+            };
+            let fill_pointer = match fill_pointer {
+                Some(exp) if exp.borrow().is_nil() => None,
+                Some(_) if dimensions.len() != 1 => {
+                    anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                        name: "make-array".to_string(),
+                        expected: types::RuccoDataType::Vector,
+                        actual: types::RuccoActualDataType::from(&*args[0].borrow())
+                            .with_datum(&args[0]),
+                    })
+                }
+                Some(exp) if exp.borrow().as_symbol() == Some("t") => Some(size),
+                Some(exp) => {
+                    let index = to_index(&exp, "make-array")?;
+                    subrange("make-array", 0, Some(index), size)?;
+                    Some(index)
+                }
+                None => None,
+            };
+            Ok(vm.arena.alloc(types::RuccoExp::Array {
+                dimensions,
+                items,
+                adjustable,
+                fill_pointer,
+            }))
+        },
+    },
+    Primitive {
+        name: "vector",
+        arity: types::RuccoArity::new(0, None),
+        func: |vm, args| {
+            Ok(vm.arena.alloc(types::RuccoExp::Array {
+                dimensions: vec![args.len()],
+                items: args.iter().map(Rc::downgrade).collect(),
+                adjustable: false,
+                fill_pointer: None,
+            }))
+        },
+    },
+    Primitive {
+        name: "aref",
+        arity: types::RuccoArity::new(1, None),
+        func: |_vm, args| {
+            let dimensions = to_dimensions(&args[0], "aref")?;
+            let index = row_major_index("aref", &dimensions, &args[1..])?;
+            array_item(&args[0], "aref", index)
+        },
+    },
+    Primitive {
+        name: "%set-aref",
+        arity: types::RuccoArity::new(2, None),
+        func: |_vm, args| {
+            let (value, subscripts) = args[1..].split_last().expect("arity is at least 2");
+            let dimensions = to_dimensions(&args[0], "aref")?;
+            let index = row_major_index("aref", &dimensions, subscripts)?;
+            set_array_item(&args[0], "aref", index, value)
+        },
+    },
+    Primitive {
+        name: "row-major-aref",
+        arity: types::RuccoArity::exact(2),
+        func: |_vm, args| {
+            let index = to_index(&args[1], "row-major-aref")?;
+            array_item(&args[0], "row-major-aref", index)
+        },
+    },
+    Primitive {
+        name: "%set-row-major-aref",
+        arity: types::RuccoArity::exact(3),
+        func: |_vm, args| {
+            let index = to_index(&args[1], "row-major-aref")?;
+            set_array_item(&args[0], "row-major-aref", index, &args[2])
+        },
+    },
+    Primitive {
+        name: "array-row-major-index",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let dimensions = to_dimensions(&args[0], "array-row-major-index")?;
+            let index = row_major_index("array-row-major-index", &dimensions, &args[1..])?;
+            Ok(vm.arena.alloc((index as i64).into()))
+        },
+    },
+    Primitive {
+        name: "array-dimensions",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let dimensions = to_dimensions(&args[0], "array-dimensions")?
+                .iter()
+                .map(|dimension| vm.arena.alloc((*dimension as i64).into()))
+                .collect();
+            Ok(vm.arena.alloc_list(dimensions))
+        },
+    },
+    Primitive {
+        name: "array-dimension",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let dimensions = to_dimensions(&args[0], "array-dimension")?;
+            let axis = to_index(&args[1], "array-dimension")?;
+            let dimension =
+                dimensions
+                    .get(axis)
+                    .ok_or_else(|| types::RuccoRuntimeErr::IndexOutOfRange {
+                        name: "array-dimension".to_string(),
+                        index: axis,
+                        length: dimensions.len(),
+                    })?;
+            Ok(vm.arena.alloc((*dimension as i64).into()))
+        },
+    },
+    Primitive {
+        name: "array-rank",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let rank = to_dimensions(&args[0], "array-rank")?.len();
+            Ok(vm.arena.alloc((rank as i64).into()))
+        },
+    },
+    Primitive {
+        name: "array-total-size",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let size = to_dimensions(&args[0], "array-total-size")?
+                .iter()
+                .product::<usize>();
+            Ok(vm.arena.alloc((size as i64).into()))
+        },
+    },
+    Primitive {
+        name: "adjustable-array-p",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = match &*args[0].borrow() {
+                types::RuccoExp::Array { adjustable, .. } => *adjustable,
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "adjustable-array-p".to_string(),
+                    expected: types::RuccoDataType::Array,
+                    actual: types::RuccoActualDataType::from(exp).with_datum(&args[0]),
+                }),
+            };
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "array-has-fill-pointer-p",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = match &*args[0].borrow() {
+                types::RuccoExp::Array { fill_pointer, .. } => fill_pointer.is_some(),
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "array-has-fill-pointer-p".to_string(),
+                    expected: types::RuccoDataType::Array,
+                    actual: types::RuccoActualDataType::from(exp).with_datum(&args[0]),
+                }),
+            };
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "fill-pointer",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let fill_pointer = match &*args[0].borrow() {
+                types::RuccoExp::Array {
+                    fill_pointer: Some(fill_pointer),
+                    ..
+                } => *fill_pointer,
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "fill-pointer".to_string(),
+                    expected: types::RuccoDataType::Vector,
+                    actual: types::RuccoActualDataType::from(exp).with_datum(&args[0]),
+                }),
+            };
+            Ok(vm.arena.alloc((fill_pointer as i64).into()))
+        },
+    },
+    Primitive {
+        name: "vector-push",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let index = match &mut *args[1].borrow_mut() {
+                types::RuccoExp::Array {
+                    items,
+                    fill_pointer: Some(fill_pointer),
+                    ..
+                } => {
+                    if *fill_pointer == items.len() {
+                        None
+                    } else {
+                        items[*fill_pointer] = Rc::downgrade(&args[0]);
+                        *fill_pointer += 1;
+                        Some(*fill_pointer - 1)
+                    }
+                }
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "vector-push".to_string(),
+                    expected: types::RuccoDataType::Vector,
+                    actual: types::RuccoActualDataType::from(&*exp).with_datum(&args[1]),
+                }),
+            };
+            match index {
+                Some(index) => Ok(vm.arena.alloc((index as i64).into())),
+                None => Ok(vm.arena.alloc_symbol("nil")),
+            }
+        },
+    },
+    Primitive {
+        name: "vector-push-extend",
+        arity: types::RuccoArity::new(2, Some(3)),
+        func: |vm, args| {
+            let extension = match args.get(2) {
+                Some(extension) => Some(to_index(extension, "vector-push-extend")?),
+                None => None,
+            };
+            let nil = vm.arena.alloc_symbol("nil");
+            let index = match &mut *args[1].borrow_mut() {
+                types::RuccoExp::Array {
+                    dimensions,
+                    items,
+                    adjustable,
+                    fill_pointer: Some(fill_pointer),
+                } if *adjustable || *fill_pointer < items.len() => {
+                    if *fill_pointer == items.len() {
+                        // grow geometrically unless the extension is given
+                        let extension = extension.unwrap_or(items.len()).max(1);
+                        items.resize(items.len() + extension, nil);
+                        dimensions[0] = items.len();
+                    }
+                    items[*fill_pointer] = Rc::downgrade(&args[0]);
+                    *fill_pointer += 1;
+                    *fill_pointer - 1
+                }
+                exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: "vector-push-extend".to_string(),
+                    expected: types::RuccoDataType::Vector,
+                    actual: types::RuccoActualDataType::from(&*exp).with_datum(&args[1]),
+                }),
+            };
+            Ok(vm.arena.alloc((index as i64).into()))
+        },
+    },
+    Primitive {
+        name: "vector-pop",
+        arity: types::RuccoArity::exact(1),
+        func: |_vm, args| match &mut *args[0].borrow_mut() {
+            types::RuccoExp::Array {
+                items,
+                fill_pointer: Some(fill_pointer),
+                ..
+            } if *fill_pointer > 0 => {
+                *fill_pointer -= 1;
+                Ok(items[*fill_pointer].clone())
+            }
+            exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                name: "vector-pop".to_string(),
+                expected: types::RuccoDataType::Vector,
+                actual: types::RuccoActualDataType::from(&*exp).with_datum(&args[0]),
+            }),
+        },
+    },
+    // hash tables
+    Primitive {
+        name: "make-hash-table",
+        arity: types::RuccoArity::new(0, None),
+        func: |vm, args| {
+            if !args.len().is_multiple_of(2) {
+                anyhow::bail!(types::RuccoRuntimeErr::OddNumberOfKeywordArguments {
+                    name: "make-hash-table".to_string(),
+                });
+            }
+            let mut test = types::HashTest::Eql;
+            // the leftmost occurrence of a keyword takes precedence
+            for pair in args.chunks(2).rev() {
+                match to_symbol(&pair[0], "make-hash-table")?.as_str() {
+                    ":test" => {
+                        let function = match &*pair[1].borrow() {
+                            types::RuccoExp::Primitive(p) => Some(p.name.to_string()),
+                            exp => exp.as_symbol().map(str::to_string),
+                        };
+                        test = function
+                            .as_deref()
+                            .and_then(types::HashTest::from_name)
+                            .ok_or_else(|| types::RuccoRuntimeErr::WrongTypeArgument {
+                                name: "make-hash-table".to_string(),
+                                expected: types::RuccoDataType::Function,
+                                actual: types::RuccoActualDataType::from(&*pair[1].borrow())
+                                    .with_datum(&pair[1]),
+                            })?;
+                    }
+                    ":size" | ":rehash-size" | ":rehash-threshold" => {}
+                    keyword => anyhow::bail!(types::RuccoRuntimeErr::UnknownKeywordArgument {
+                        name: "make-hash-table".to_string(),
+                        keyword: keyword.to_string(),
+                    }),
+                }
+            }
+            Ok(vm.arena.alloc(types::RuccoExp::HashTable {
+                test,
+                buckets: std::collections::HashMap::new(),
+            }))
+        },
+    },
+    Primitive {
+        name: "hash-table-p",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = matches!(&*args[0].borrow(), types::RuccoExp::HashTable { .. });
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "gethash",
+        arity: types::RuccoArity::new(2, Some(3)),
+        func: |vm, args| {
+            let values = match hash_table::get(&args[1], &args[0])? {
+                Some(value) => vec![value, vm.arena.alloc_symbol("t")],
+                None => {
+                    let default = match args.get(2) {
+                        Some(default) => Rc::downgrade(default),
+                        None => vm.arena.alloc_symbol("nil"),
+                    };
+                    vec![default, vm.arena.alloc_symbol("nil")]
+                }
+            };
+            Ok(vm.set_values(values))
+        },
+    },
+    Primitive {
+        name: "%set-gethash",
+        arity: types::RuccoArity::new(3, Some(4)),
+        func: |_vm, args| {
+            let value = args.last().expect("arity is at least 3");
+            hash_table::put(&args[1], &args[0], value)?;
+            Ok(Rc::downgrade(value))
+        },
+    },
+    Primitive {
+        name: "remhash",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            let res = hash_table::remove(&args[1], &args[0])?;
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "maphash",
+        arity: types::RuccoArity::exact(2),
+        func: |vm, args| {
+            // the entries are collected first, so the function may update
+            // or remove the entry it is called with
+            let function = Rc::downgrade(&args[0]);
+            for (key, value) in hash_table::entries(&args[1], "maphash")? {
+                let args = vm.arena.alloc_list(vec![key, value]);
+                vm::call(&function, &args, vm.env, vm.arena)?;
+            }
+            Ok(vm.arena.alloc_symbol("nil"))
+        },
+    },
+    Primitive {
+        name: "hash-table-count",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let count = hash_table::entries(&args[0], "hash-table-count")?.len();
+            Ok(vm.arena.alloc((count as i64).into()))
+        },
+    },
+    Primitive {
+        name: "hash-table-test",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let test = hash_table::test(&args[0])?;
+            Ok(vm.arena.alloc_symbol(test.name()))
+        },
+    },
+    Primitive {
+        name: "clrhash",
+        arity: types::RuccoArity::exact(1),
+        func: |_vm, args| {
+            hash_table::clear(&args[0])?;
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    // printer
+    Primitive {
+        name: "prin1",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            print!("{}", args[0].borrow().printed(vm.env.float_format()));
+            std::io::Write::flush(&mut std::io::stdout())?;
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    Primitive {
+        name: "princ",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            print!("{:#}", args[0].borrow().printed(vm.env.float_format()));
+            std::io::Write::flush(&mut std::io::stdout())?;
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    Primitive {
+        name: "print",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            print!("\n{} ", args[0].borrow().printed(vm.env.float_format()));
+            std::io::Write::flush(&mut std::io::stdout())?;
+            Ok(Rc::downgrade(&args[0]))
+        },
+    },
+    Primitive {
+        name: "terpri",
+        arity: types::RuccoArity::exact(0),
+        func: |vm, _args| {
+            println!();
+            Ok(vm.arena.alloc_symbol("nil"))
+        },
+    },
+    Primitive {
+        name: "prin1-to-string",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let s = args[0].borrow().printed(vm.env.float_format()).to_string();
+            Ok(vm.arena.alloc(types::RuccoAtom::String(s).into()))
+        },
+    },
+    Primitive {
+        name: "princ-to-string",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let s = format!("{:#}", args[0].borrow().printed(vm.env.float_format()));
+            Ok(vm.arena.alloc(types::RuccoAtom::String(s).into()))
+        },
+    },
+    // numbers
+    Primitive {
+        name: "+",
+        arity: types::RuccoArity::new(0, None),
+        func: |vm, args| {
+            let res = args.iter().try_fold(types::RuccoAtom::Int(0), |acc, arg| {
+                add(&acc, &to_number(arg, "+")?)
+            })?;
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "-",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let first = to_number(&args[0], "-")?;
+            let res = match args.len() {
+                1 => subtract(&0.into(), &first)?,
+                _ => args[1..]
+                    .iter()
+                    .try_fold(first, |acc, arg| subtract(&acc, &to_number(arg, "-")?))?,
+            };
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "*",
+        arity: types::RuccoArity::new(0, None),
+        func: |vm, args| {
+            let res = args.iter().try_fold(types::RuccoAtom::Int(1), |acc, arg| {
+                multiply(&acc, &to_number(arg, "*")?)
+            })?;
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "/",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let first = to_number(&args[0], "/")?;
+            let res = match args.len() {
+                1 => divide(&1.into(), &first)?,
+                _ => args[1..]
+                    .iter()
+                    .try_fold(first, |acc, arg| divide(&acc, &to_number(arg, "/")?))?,
+            };
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "float",
+        arity: types::RuccoArity::new(1, Some(2)),
+        func: |vm, args| {
+            let x = to_real(&args[0], "float")?;
+            let format = match args.get(1) {
+                Some(prototype) => to_float_format(prototype, "float")?,
+                None => float_format(&x).unwrap_or(types::FloatFormat::Single),
+            };
+            let res =
+                make_float(x.to_f64(), format).ok_or_else(|| float_overflow("float", &x, &x))?;
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "floor",
+        arity: types::RuccoArity::new(1, Some(2)),
+        func: |vm, args| integer_division(vm, args, "floor", true),
+    },
+    Primitive {
+        name: "truncate",
+        arity: types::RuccoArity::new(1, Some(2)),
+        func: |vm, args| integer_division(vm, args, "truncate", false),
+    },
+    Primitive {
+        name: "1+",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = add(&to_number(&args[0], "1+")?, &1.into())?;
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "1-",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = subtract(&to_number(&args[0], "1-")?, &1.into())?;
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "abs",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let x = to_number(&args[0], "abs")?;
+            let res = match &x {
+                types::RuccoAtom::Complex(real, imag) => {
+                    contagion(real.to_f64().hypot(imag.to_f64()), real, imag)
+                        .ok_or_else(|| float_overflow("abs", &x, &x))?
+                }
+                types::RuccoAtom::SingleFloat(e) => types::RuccoAtom::SingleFloat(e.abs()),
+                types::RuccoAtom::DoubleFloat(e) => types::RuccoAtom::DoubleFloat(e.abs()),
+                _ if x.to_f64() < 0.0 => subtract(&0.into(), &x)?,
+                _ => x,
+            };
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    // complex numbers
+    Primitive {
+        name: "complex",
+        arity: types::RuccoArity::new(1, Some(2)),
+        func: |vm, args| {
+            let real = to_real(&args[0], "complex")?;
+            let imag = match args.get(1) {
+                Some(imag) => to_real(imag, "complex")?,
+                None => 0.into(),
+            };
+            Ok(vm
+                .arena
+                .alloc(types::RuccoAtom::new_complex(real, imag).into()))
+        },
+    },
+    Primitive {
+        name: "realpart",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let (real, _) = parts(&to_number(&args[0], "realpart")?);
+            Ok(vm.arena.alloc(real.into()))
+        },
+    },
+    Primitive {
+        name: "imagpart",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let x = to_number(&args[0], "imagpart")?;
+            let imag = match float_format(&x) {
+                Some(types::FloatFormat::Single) => types::RuccoAtom::SingleFloat(0.0),
+                Some(types::FloatFormat::Double) => types::RuccoAtom::DoubleFloat(0.0),
+                None => parts(&x).1,
+            };
+            Ok(vm.arena.alloc(imag.into()))
+        },
+    },
+    Primitive {
+        name: "conjugate",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let (real, imag) = parts(&to_number(&args[0], "conjugate")?);
+            let res = types::RuccoAtom::new_complex(real, subtract(&0.into(), &imag)?);
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "phase",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let x = to_number(&args[0], "phase")?;
+            let (real, imag) = parts(&x);
+            let res = contagion(imag.to_f64().atan2(real.to_f64()), &real, &imag)
+                .ok_or_else(|| float_overflow("phase", &x, &x))?;
+            Ok(vm.arena.alloc(res.into()))
+        },
+    },
+    Primitive {
+        name: "complexp",
+        arity: types::RuccoArity::exact(1),
+        func: |vm, args| {
+            let res = matches!(
+                &*args[0].borrow(),
+                types::RuccoExp::Atom(types::RuccoAtom::Complex(..))
+            );
+            Ok(bool_exp(vm, res))
+        },
+    },
+    // comparison
+    Primitive {
+        name: "=",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| {
+            let nums = args
+                .iter()
+                .map(|arg| to_number(arg, "="))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let res = nums.windows(2).all(|w| is_number_equal(&w[0], &w[1]));
+            Ok(bool_exp(vm, res))
+        },
+    },
+    Primitive {
+        name: "<",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_all(vm, args, "<", |o| o.is_lt()),
+    },
+    Primitive {
+        name: ">",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_all(vm, args, ">", |o| o.is_gt()),
+    },
+    Primitive {
+        name: "<=",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_all(vm, args, "<=", |o| o.is_le()),
+    },
+    Primitive {
+        name: ">=",
+        arity: types::RuccoArity::new(1, None),
+        func: |vm, args| compare_all(vm, args, ">=", |o| o.is_ge()),
+    },
+];
+
+static PRIMITIVE_TABLE: once_cell::sync::Lazy<
+    std::collections::HashMap<&'static str, &'static Primitive>,
+> = once_cell::sync::Lazy::new(|| PRIMITIVES.iter().map(|p| (p.name, p)).collect());
+
+pub fn find(name: &str) -> Option<&'static Primitive> {
+    PRIMITIVE_TABLE.get(name).copied()
+}
+
+fn bool_exp(vm: &mut vm::Vm, b: bool) -> types::RuccoExpRef {
+    vm.arena.alloc_symbol(if b { "t" } else { "nil" })
+}
+
+/// Keep the object a type error raised by a method of its contents is about
+fn with_datum(mut err: anyhow::Error, exp: &types::RuccoExpRefStrong) -> anyhow::Error {
+    if let Some(types::RuccoRuntimeErr::WrongTypeArgument { actual, .. }) = err.downcast_mut() {
+        if actual.datum.is_none() {
+            actual.datum = Some(Rc::as_ptr(exp) as usize);
+        }
+    }
+    err
+}
+
+fn to_symbol(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<String> {
+    let exp_ = exp.borrow();
+    match exp_.as_symbol() {
+        Some(sym) => Ok(sym.to_string()),
+        None => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Symbol,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+fn to_number(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<types::RuccoAtom> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Atom(atom) if atom.is_number() => Ok(atom.clone()),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Number,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+fn to_real(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<types::RuccoAtom> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Atom(atom) if atom.is_real() => Ok(atom.clone()),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Real,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+fn to_float_format(
+    exp: &types::RuccoExpRefStrong,
+    name: &str,
+) -> anyhow::Result<types::FloatFormat> {
+    let exp_ = exp.borrow();
+    let format = match &*exp_ {
+        types::RuccoExp::Atom(atom) => float_format(atom),
+        _ => None,
+    };
+    match format {
+        Some(format) => Ok(format),
+        None => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Float,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+fn to_char(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<char> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Atom(types::RuccoAtom::Char(c)) => Ok(*c),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Char,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+fn to_str(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<String> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Atom(types::RuccoAtom::String(s)) => Ok(s.clone()),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::String,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+/// Return the string named by a string, a symbol or a character
+fn to_string_designator(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<String> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Atom(types::RuccoAtom::String(s) | types::RuccoAtom::Symbol(s)) => {
+            Ok(s.clone())
+        }
+        types::RuccoExp::Atom(types::RuccoAtom::Char(c)) => Ok(c.to_string()),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::String,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+/// Return the range of a subsequence, end defaults to the length
+fn subrange(
+    name: &str,
+    start: usize,
+    end: Option<usize>,
+    length: usize,
+) -> anyhow::Result<std::ops::Range<usize>> {
+    let end = end.unwrap_or(length);
+    if end > length || start > end {
+        anyhow::bail!(types::RuccoRuntimeErr::InvalidBoundingIndices {
+            name: name.to_string(),
+            start,
+            end,
+            length,
+        });
+    }
+    Ok(start..end)
+}
+
+fn to_index(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<usize> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Atom(types::RuccoAtom::Int(i)) if *i >= 0 => Ok(*i as usize),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Int,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+fn to_dimensions(exp: &types::RuccoExpRefStrong, name: &str) -> anyhow::Result<Vec<usize>> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Array { dimensions, .. } => Ok(dimensions.clone()),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Array,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+/// Return the row-major index of the element of an array of the dimensions
+/// at the subscripts
+fn row_major_index(
+    name: &str,
+    dimensions: &[usize],
+    subscripts: &[types::RuccoExpRefStrong],
+) -> anyhow::Result<usize> {
+    if subscripts.len() != dimensions.len() {
+        anyhow::bail!(types::RuccoRuntimeErr::WrongNumberOfArguments {
+            name: name.to_string(),
+            expected: types::RuccoArity::exact(dimensions.len() + 1),
+            actual: subscripts.len() + 1,
+        });
+    }
+    let mut index = 0;
+    for (subscript, &dimension) in subscripts.iter().zip(dimensions) {
+        let subscript = to_index(subscript, name)?;
+        if subscript >= dimension {
+            anyhow::bail!(types::RuccoRuntimeErr::IndexOutOfRange {
+                name: name.to_string(),
+                index: subscript,
+                length: dimension,
+            });
+        }
+        index = index * dimension + subscript;
+    }
+    Ok(index)
+}
+
+/// Return the element of the array at the row-major index
+fn array_item(
+    exp: &types::RuccoExpRefStrong,
+    name: &str,
+    index: usize,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Array { items, .. } => items.get(index).cloned().ok_or_else(|| {
+            types::RuccoRuntimeErr::IndexOutOfRange {
+                name: name.to_string(),
+                index,
+                length: items.len(),
+            }
+            .into()
+        }),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Array,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+/// Set the element of the array at the row-major index, return the value
+fn set_array_item(
+    exp: &types::RuccoExpRefStrong,
+    name: &str,
+    index: usize,
+    value: &types::RuccoExpRefStrong,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let mut exp_ = exp.borrow_mut();
+    match &mut *exp_ {
+        types::RuccoExp::Array { items, .. } => {
+            let length = items.len();
+            let item =
+                items
+                    .get_mut(index)
+                    .ok_or_else(|| types::RuccoRuntimeErr::IndexOutOfRange {
+                        name: name.to_string(),
+                        index,
+                        length,
+                    })?;
+            *item = Rc::downgrade(value);
+            Ok(Rc::downgrade(value))
+        }
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Array,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+/// Return whether the object is of the type named by the symbol
+fn typep(vm: &vm::Vm, exp: &types::RuccoExp, type_name: &str) -> bool {
+    match type_name {
+        "t" => true,
+        "nil" => false,
+        "atom" => !matches!(exp, types::RuccoExp::Cons { .. }),
+        "cons" => matches!(exp, types::RuccoExp::Cons { .. }),
+        "list" => matches!(exp, types::RuccoExp::Cons { .. }) || exp.is_nil(),
+        "null" => exp.is_nil(),
+        "symbol" => exp.as_symbol().is_some(),
+        "keyword" => exp.as_symbol().is_some_and(|sym| sym.starts_with(':')),
+        "number" => matches!(exp, types::RuccoExp::Atom(atom) if atom.is_number()),
+        "real" => matches!(exp, types::RuccoExp::Atom(atom) if atom.is_real()),
+        "complex" => matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::Complex(..))),
+        "rational" => matches!(
+            exp,
+            types::RuccoExp::Atom(
+                types::RuccoAtom::Int(_) | types::RuccoAtom::BigInt(_) | types::RuccoAtom::Ratio(_)
+            )
+        ),
+        "integer" => matches!(
+            exp,
+            types::RuccoExp::Atom(types::RuccoAtom::Int(_) | types::RuccoAtom::BigInt(_))
+        ),
+        "fixnum" => matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::Int(_))),
+        "bignum" => matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::BigInt(_))),
+        "ratio" => matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::Ratio(_))),
+        "string" => matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::String(_))),
+        "character" => matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::Char(_))),
+        "float" => matches!(
+            exp,
+            types::RuccoExp::Atom(
+                types::RuccoAtom::SingleFloat(_) | types::RuccoAtom::DoubleFloat(_)
+            )
+        ),
+        "short-float" | "single-float" => {
+            matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::SingleFloat(_)))
+        }
+        "double-float" | "long-float" => {
+            matches!(exp, types::RuccoExp::Atom(types::RuccoAtom::DoubleFloat(_)))
+        }
+        "structure-object" => matches!(exp, types::RuccoExp::Structure { .. }),
+        "array" => matches!(exp, types::RuccoExp::Array { .. }),
+        "hash-table" => matches!(exp, types::RuccoExp::HashTable { .. }),
+        "vector" => {
+            matches!(exp, types::RuccoExp::Array { dimensions, .. } if dimensions.len() == 1)
+        }
+        "simple-vector" => matches!(
+            exp,
+            types::RuccoExp::Array {
+                dimensions,
+                adjustable: false,
+                fill_pointer: None,
+                ..
+            } if dimensions.len() == 1
+        ),
+        "function" => matches!(
+            exp,
+            types::RuccoExp::Closure { .. } | types::RuccoExp::Primitive(_)
+        ),
+        _ => match exp {
+            types::RuccoExp::Condition { class, .. } => {
+                condition::is_subclass(vm.env, class, type_name)
+            }
+            types::RuccoExp::Structure { name, .. } => {
+                structure::is_subtype(vm.env, name, type_name)
+            }
+            _ => false,
+        },
+    }
+}
+
+/// Return car and cdr of a list, nil for both if the list is empty
+fn list_ref(
+    exp: &types::RuccoExpRefStrong,
+    name: &str,
+) -> anyhow::Result<(types::RuccoExpRef, types::RuccoExpRef)> {
+    let exp_ = exp.borrow();
+    match &*exp_ {
+        types::RuccoExp::Cons { car, cdr } => Ok((car.clone(), cdr.clone())),
+        _ if exp_.is_nil() => Ok((Rc::downgrade(exp), Rc::downgrade(exp))),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::List,
+            actual: types::RuccoActualDataType::from(&*exp_).with_datum(exp),
+        }),
+    }
+}
+
+pub fn is_eq(a: &types::RuccoExpRefStrong, b: &types::RuccoExpRefStrong) -> bool {
+    if Rc::ptr_eq(a, b) {
+        return true;
+    }
+    // fixnums and characters are immediate values
+    match (&*a.borrow(), &*b.borrow()) {
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::Int(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::Int(y)),
+        ) => x == y,
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::Char(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::Char(y)),
+        ) => x == y,
+        _ => false,
+    }
+}
+
+pub fn is_eql(a: &types::RuccoExpRefStrong, b: &types::RuccoExpRefStrong) -> bool {
+    if is_eq(a, b) {
+        return true;
+    }
+    match (&*a.borrow(), &*b.borrow()) {
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::SingleFloat(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::SingleFloat(y)),
+        ) => x == y,
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::DoubleFloat(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::DoubleFloat(y)),
+        ) => x == y,
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::BigInt(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::BigInt(y)),
+        ) => x == y,
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::Ratio(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::Ratio(y)),
+        ) => x == y,
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::Complex(..)),
+            types::RuccoExp::Atom(types::RuccoAtom::Complex(..)),
+        ) => *a.borrow() == *b.borrow(),
+        _ => false,
+    }
+}
+
+/// Return whether the objects are equal ignoring the case of characters,
+/// the types of numbers and the identity of arrays
+pub fn is_equalp(a: &types::RuccoExpRefStrong, b: &types::RuccoExpRefStrong) -> bool {
+    if Rc::ptr_eq(a, b) {
+        return true;
+    }
+    match (&*a.borrow(), &*b.borrow()) {
+        (types::RuccoExp::Atom(x), types::RuccoExp::Atom(y)) if x.is_number() && y.is_number() => {
+            is_number_equal(x, y)
+        }
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::Char(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::Char(y)),
+        ) => x.to_lowercase().eq(y.to_lowercase()),
+        (
+            types::RuccoExp::Atom(types::RuccoAtom::String(x)),
+            types::RuccoExp::Atom(types::RuccoAtom::String(y)),
+        ) => x
+            .chars()
+            .flat_map(char::to_lowercase)
+            .eq(y.chars().flat_map(char::to_lowercase)),
+        (
+            types::RuccoExp::Cons {
+                car: car1,
+                cdr: cdr1,
+            },
+            types::RuccoExp::Cons {
+                car: car2,
+                cdr: cdr2,
+            },
+        ) => is_equalp_ref(car1, car2) && is_equalp_ref(cdr1, cdr2),
+        (
+            types::RuccoExp::Structure {
+                name: name1,
+                slots: slots1,
+            },
+            types::RuccoExp::Structure {
+                name: name2,
+                slots: slots2,
+            },
+        ) => {
+            name1 == name2
+                && slots1.len() == slots2.len()
+                && slots1
+                    .iter()
+                    .zip(slots2)
+                    .all(|((slot1, x), (slot2, y))| slot1 == slot2 && is_equalp_ref(x, y))
+        }
+        (
+            types::RuccoExp::Array {
+                dimensions: dimensions1,
+                items: items1,
+                fill_pointer: fill_pointer1,
+                ..
+            },
+            types::RuccoExp::Array {
+                dimensions: dimensions2,
+                items: items2,
+                fill_pointer: fill_pointer2,
+                ..
+            },
+        ) => {
+            // vectors are compared up to their fill pointers
+            let items1 = &items1[..fill_pointer1.unwrap_or(items1.len())];
+            let items2 = &items2[..fill_pointer2.unwrap_or(items2.len())];
+            dimensions1.len() == dimensions2.len()
+                && (dimensions1.len() == 1 || dimensions1 == dimensions2)
+                && items1.len() == items2.len()
+                && items1.iter().zip(items2).all(|(x, y)| is_equalp_ref(x, y))
+        }
+        (x, y) => x == y,
+    }
+}
+
+fn is_equalp_ref(a: &types::RuccoExpRef, b: &types::RuccoExpRef) -> bool {
+    match (a.upgrade(), b.upgrade()) {
+        (Some(a), Some(b)) => is_equalp(&a, &b),
+        _ => false,
+    }
+}
+
+/// Return whether the numbers are equal as = compares them
+fn is_number_equal(a: &types::RuccoAtom, b: &types::RuccoAtom) -> bool {
+    let ((a_real, a_imag), (b_real, b_imag)) = (parts(a), parts(b));
+    compare(&a_real, &b_real).is_some_and(|o| o.is_eq())
+        && compare(&a_imag, &b_imag).is_some_and(|o| o.is_eq())
+}
+
+/// Return the exact value of a rational number, None if it is not one
+fn to_rational(atom: &types::RuccoAtom) -> Option<num_rational::BigRational> {
+    match atom {
+        types::RuccoAtom::Int(e) => Some(num_bigint::BigInt::from(*e).into()),
+        types::RuccoAtom::BigInt(e) => Some(e.clone().into()),
+        types::RuccoAtom::Ratio(e) => Some(e.clone()),
+        _ => None,
+    }
+}
+
+/// Return the uppercase of the character, the character itself when its
+/// uppercase is not a single character
+fn char_upcase(c: char) -> char {
+    single_char(c.to_uppercase()).unwrap_or(c)
+}
+
+/// Return the lowercase of the character, the character itself when its
+/// lowercase is not a single character
+fn char_downcase(c: char) -> char {
+    single_char(c.to_lowercase()).unwrap_or(c)
+}
+
+/// Apply the operation on fixnums, promoting to bignums on overflow, on
+/// rationals if either number is a ratio, or on floats if either is a float
+fn arith(
+    name: &str,
+    a: &types::RuccoAtom,
+    b: &types::RuccoAtom,
+    int_op: fn(i64, i64) -> Option<i64>,
+    rational_op: fn(
+        num_rational::BigRational,
+        num_rational::BigRational,
+    ) -> num_rational::BigRational,
+    float_op: fn(f64, f64) -> f64,
+) -> anyhow::Result<types::RuccoAtom> {
+    if let (types::RuccoAtom::Int(x), types::RuccoAtom::Int(y)) = (a, b) {
+        if let Some(z) = int_op(*x, *y) {
+            return Ok(z.into());
+        }
+    }
+    match (to_rational(a), to_rational(b)) {
+        (Some(x), Some(y)) => Ok(rational_op(x, y).into()),
+        _ => contagion(float_op(a.to_f64(), b.to_f64()), a, b)
+            .ok_or_else(|| float_overflow(name, a, b).into()),
+    }
+}
+
+/// Return the real and imaginary parts of a number
+fn parts(x: &types::RuccoAtom) -> (types::RuccoAtom, types::RuccoAtom) {
+    match x {
+        types::RuccoAtom::Complex(real, imag) => ((**real).clone(), (**imag).clone()),
+        _ => (x.clone(), 0.into()),
+    }
+}
+
+fn is_complex(a: &types::RuccoAtom, b: &types::RuccoAtom) -> bool {
+    matches!(a, types::RuccoAtom::Complex(..)) || matches!(b, types::RuccoAtom::Complex(..))
+}
+
+fn add(a: &types::RuccoAtom, b: &types::RuccoAtom) -> anyhow::Result<types::RuccoAtom> {
+    if is_complex(a, b) {
+        let ((a_real, a_imag), (b_real, b_imag)) = (parts(a), parts(b));
+        return Ok(types::RuccoAtom::new_complex(
+            add(&a_real, &b_real)?,
+            add(&a_imag, &b_imag)?,
+        ));
+    }
+    arith("+", a, b, i64::checked_add, |a, b| a + b, |a, b| a + b)
+}
+
+fn subtract(a: &types::RuccoAtom, b: &types::RuccoAtom) -> anyhow::Result<types::RuccoAtom> {
+    if is_complex(a, b) {
+        let ((a_real, a_imag), (b_real, b_imag)) = (parts(a), parts(b));
+        return Ok(types::RuccoAtom::new_complex(
+            subtract(&a_real, &b_real)?,
+            subtract(&a_imag, &b_imag)?,
+        ));
+    }
+    arith("-", a, b, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+}
+
+fn multiply(a: &types::RuccoAtom, b: &types::RuccoAtom) -> anyhow::Result<types::RuccoAtom> {
+    if is_complex(a, b) {
+        let ((a_real, a_imag), (b_real, b_imag)) = (parts(a), parts(b));
+        let real = subtract(&multiply(&a_real, &b_real)?, &multiply(&a_imag, &b_imag)?)?;
+        let imag = add(&multiply(&a_real, &b_imag)?, &multiply(&a_imag, &b_real)?)?;
+        return Ok(types::RuccoAtom::new_complex(real, imag));
+    }
+    arith("*", a, b, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+}
+
+fn divide(a: &types::RuccoAtom, b: &types::RuccoAtom) -> anyhow::Result<types::RuccoAtom> {
+    if is_complex(a, b) {
+        let ((a_real, a_imag), (b_real, b_imag)) = (parts(a), parts(b));
+        let norm = add(&multiply(&b_real, &b_real)?, &multiply(&b_imag, &b_imag)?)?;
+        let real = add(&multiply(&a_real, &b_real)?, &multiply(&a_imag, &b_imag)?)?;
+        let imag = subtract(&multiply(&a_imag, &b_real)?, &multiply(&a_real, &b_imag)?)?;
+        if is_number_equal(&norm, &0.into()) {
+            anyhow::bail!(division_by_zero("/", a, b));
+        }
+        return Ok(types::RuccoAtom::new_complex(
+            divide(&real, &norm)?,
+            divide(&imag, &norm)?,
+        ));
+    }
+    if let (types::RuccoAtom::Int(x), types::RuccoAtom::Int(y)) = (a, b) {
+        if x.checked_rem(*y) == Some(0) {
+            return Ok((x / y).into());
+        }
+    }
+    match (to_rational(a), to_rational(b)) {
+        (_, Some(y)) if y.is_zero() => anyhow::bail!(division_by_zero("/", a, b)),
+        (Some(x), Some(y)) => Ok((x / y).into()),
+        // only a float divisor is zero as a float, a rational one too small
+        // for a float overflows the quotient instead
+        (_, None) if b.to_f64() == 0.0 => anyhow::bail!(division_by_zero("/", a, b)),
+        _ => {
+            contagion(a.to_f64() / b.to_f64(), a, b).ok_or_else(|| float_overflow("/", a, b).into())
+        }
+    }
+}
+
+fn float_overflow(
+    name: &str,
+    a: &types::RuccoAtom,
+    b: &types::RuccoAtom,
+) -> types::RuccoRuntimeErr {
+    types::RuccoRuntimeErr::FloatingPointOverflow {
+        name: name.to_string(),
+        operands: vec![a.clone(), b.clone()],
+    }
+}
+
+fn division_by_zero(
+    name: &str,
+    a: &types::RuccoAtom,
+    b: &types::RuccoAtom,
+) -> types::RuccoRuntimeErr {
+    types::RuccoRuntimeErr::DivisionByZero {
+        name: name.to_string(),
+        operands: vec![a.clone(), b.clone()],
+    }
+}
+
+/// Divide to an integer quotient rounded toward negative infinity if floor
+/// is set, toward zero otherwise, returning the quotient and the remainder
+fn integer_division(
+    vm: &mut vm::Vm,
+    args: &[types::RuccoExpRefStrong],
+    name: &str,
+    floor: bool,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let a = to_real(&args[0], name)?;
+    let b = match args.get(1) {
+        Some(arg) => to_real(arg, name)?,
+        None => 1.into(),
+    };
+    let (q, r): (types::RuccoAtom, types::RuccoAtom) = match (to_rational(&a), to_rational(&b)) {
+        (_, Some(y)) if y.is_zero() => anyhow::bail!(division_by_zero(name, &a, &b)),
+        (Some(x), Some(y)) => {
+            let q = if floor {
+                (&x / &y).floor()
+            } else {
+                (&x / &y).trunc()
+            };
+            let r = x - &q * y;
+            (q.into(), r.into())
+        }
+        _ => {
+            let (x, y) = (a.to_f64(), b.to_f64());
+            if y == 0.0 {
+                anyhow::bail!(division_by_zero(name, &a, &b));
+            }
+            let q = if floor {
+                (x / y).floor()
+            } else {
+                (x / y).trunc()
+            };
+            let big_q = num_bigint::BigInt::from_f64(q).ok_or_else(|| {
+                types::RuccoRuntimeErr::IntegerOverflow {
+                    name: name.to_string(),
+                }
+            })?;
+            let r = contagion(x - q * y, &a, &b).ok_or_else(|| float_overflow(name, &a, &b))?;
+            (big_q.into(), r)
+        }
+    };
+    let q = vm.arena.alloc(q.into());
+    let r = vm.arena.alloc(r.into());
+    Ok(vm.set_values(vec![q, r]))
+}
+
+/// Return the format of a float, None if it is not one
+fn float_format(atom: &types::RuccoAtom) -> Option<types::FloatFormat> {
+    match atom {
+        types::RuccoAtom::SingleFloat(_) => Some(types::FloatFormat::Single),
+        types::RuccoAtom::DoubleFloat(_) => Some(types::FloatFormat::Double),
+        _ => None,
+    }
+}
+
+/// Return the float of the format, None if it overflows the format
+fn make_float(z: f64, format: types::FloatFormat) -> Option<types::RuccoAtom> {
+    let res: types::RuccoAtom = match format {
+        types::FloatFormat::Single => (z as f32).into(),
+        types::FloatFormat::Double => z.into(),
+    };
+    res.to_f64().is_finite().then_some(res)
+}
+
+/// Return the float in the format of the wider float of the numbers, single
+/// if neither is a float, None if it overflows the format
+fn contagion(z: f64, a: &types::RuccoAtom, b: &types::RuccoAtom) -> Option<types::RuccoAtom> {
+    let double = Some(types::FloatFormat::Double);
+    if float_format(a) == double || float_format(b) == double {
+        make_float(z, types::FloatFormat::Double)
+    } else {
+        make_float(z, types::FloatFormat::Single)
+    }
+}
+
+/// Return the character if the iterator yields exactly one
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+fn compare_chars(
+    vm: &mut vm::Vm,
+    args: &[types::RuccoExpRefStrong],
+    name: &str,
+    pred: fn(std::cmp::Ordering) -> bool,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let chars = args
+        .iter()
+        .map(|arg| to_char(arg, name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let res = chars.windows(2).all(|w| pred(w[0].cmp(&w[1])));
+    Ok(bool_exp(vm, res))
+}
+
+/// Compare two real numbers, None if they are unordered
+fn compare(a: &types::RuccoAtom, b: &types::RuccoAtom) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (types::RuccoAtom::Int(x), types::RuccoAtom::Int(y)) => Some(x.cmp(y)),
+        (x, y) => match (to_rational(x), to_rational(y)) {
+            (Some(x), Some(y)) => Some(x.cmp(&y)),
+            _ => x.to_f64().partial_cmp(&y.to_f64()),
+        },
+    }
+}
+
+fn compare_all(
+    vm: &mut vm::Vm,
+    args: &[types::RuccoExpRefStrong],
+    name: &str,
+    pred: fn(std::cmp::Ordering) -> bool,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let nums = args
+        .iter()
+        .map(|arg| to_real(arg, name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let res = nums
+        .windows(2)
+        .all(|w| compare(&w[0], &w[1]).is_some_and(pred));
+    Ok(bool_exp(vm, res))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core;
+    use crate::types;
+
+    fn rep(input: &str) -> String {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        core::rep(input, env, arena).unwrap()
+    }
+
+    #[test]
+    fn test_cons() {
+        assert_eq!(rep("(car '(1 2))"), "1");
+        assert_eq!(rep("(cdr '(1 2))"), "(2)");
+        assert_eq!(rep("(car nil)"), "nil");
+        assert_eq!(rep("(cons 1 '(2))"), "(1 2)");
+        assert_eq!(rep("(list 1 (list 2) 3)"), "(1 (2) 3)");
+        assert_eq!(rep("(let ((x (list 1 2))) (rplacd x 3) x)"), "(1 . 3)");
+    }
+
+    #[test]
+    fn test_append() {
+        assert_eq!(rep("(append)"), "nil");
+        assert_eq!(rep("(append '(1) nil '(2 3) 4)"), "(1 2 3 . 4)");
+    }
+
+    #[test]
+    fn test_quasiquote() {
+        assert_eq!(rep("`a"), "a");
+        assert_eq!(rep("(let ((x 1)) `(a ,x))"), "(a 1)");
+        assert_eq!(
+            rep("(let ((x 1) (y '(2 3))) `(a ,x ,@y b ,@y))"),
+            "(a 1 2 3 b 2 3)"
+        );
+        assert_eq!(rep("(let ((x 1)) `(a . ,x))"), "(a . 1)");
+        assert_eq!(rep("(let ((x '(1))) `((,@x) . b))"), "((1) . b)");
+        assert_eq!(
+            rep("(let ((x 1)) `(a `(b ,(c ,x))))"),
+            "(a (quasiquote (b (unquote (c 1)))))"
+        );
+        assert_eq!(
+            rep("(let ((x 'y)) `(a `(b ,,x ,',x)))"),
+            "(a (quasiquote (b (unquote y) (unquote (quote y)))))"
+        );
+    }
+
+    #[test]
+    fn test_predicates() {
+        assert_eq!(rep("(atom 1)"), "t");
+        assert_eq!(rep("(atom '(1))"), "nil");
+        assert_eq!(rep("(eq 'a 'a)"), "t");
+        assert_eq!(rep("(eq '(1) '(1))"), "nil");
+        assert_eq!(rep("(eql 1.5 1.5)"), "t");
+        assert_eq!(rep("(equal '(1 (2)) '(1 (2)))"), "t");
+        assert_eq!(rep("(eql #\\a #\\a)"), "t");
+        assert_eq!(rep("(equalp \"Foo\" \"fOO\")"), "t");
+        assert_eq!(rep("(null nil)"), "t");
+    }
+
+    #[test]
+    fn test_arith() {
+        assert_eq!(rep("(+)"), "0");
+        assert_eq!(rep("(+ 1 2 3)"), "6");
+        assert_eq!(rep("(+ 1 0.5)"), "1.5");
+        assert_eq!(rep("(- 5)"), "-5");
+        assert_eq!(rep("(- 5 1 1)"), "3");
+        assert_eq!(rep("(* 2 3)"), "6");
+        assert_eq!(rep("(/ 6 3)"), "2");
+        assert_eq!(rep("(/ 1 4)"), "1/4");
+        assert_eq!(rep("(/ 1 4.0)"), "0.25");
+        assert_eq!(rep("(< 1 2 3)"), "t");
+        assert_eq!(rep("(< 1 3 2)"), "nil");
+        assert_eq!(rep("(>= 2 2 1.5)"), "t");
+        assert_eq!(rep("(= 1 1.0)"), "t");
+    }
+
+    #[test]
+    fn test_integer_division() {
+        assert_eq!(rep("(floor 7 2)"), "3\n1");
+        assert_eq!(rep("(floor -7 2)"), "-4\n1");
+        assert_eq!(rep("(floor 7 -2)"), "-4\n-1");
+        assert_eq!(rep("(truncate -7 2)"), "-3\n-1");
+        assert_eq!(rep("(floor 2.5)"), "2\n0.5");
+        assert_eq!(rep("(nth 1 '(a b))"), "b");
+        assert_eq!(rep("(values-list '(1 2))"), "1\n2");
+    }
+
+    #[test]
+    fn test_bignum() {
+        assert_eq!(rep("(+ 9223372036854775807 1)"), "9223372036854775808");
+        assert_eq!(rep("(- -9223372036854775808 1)"), "-9223372036854775809");
+        assert_eq!(rep("(* 4294967296 4294967296)"), "18446744073709551616");
+        assert_eq!(
+            rep("(- (+ 9223372036854775807 1) 1)"),
+            "9223372036854775807"
+        );
+        assert_eq!(
+            rep("(typep (- 99999999999999999999 99999999999999999998) 'fixnum)"),
+            "t"
+        );
+        assert_eq!(rep("(/ 100000000000000000000 10)"), "10000000000000000000");
+        assert_eq!(
+            rep("(floor -99999999999999999999 7)"),
+            "-14285714285714285715\n6"
+        );
+        assert_eq!(rep("(< 99999999999999999999 100000000000000000000)"), "t");
+        assert_eq!(rep("(eql 99999999999999999999 99999999999999999999)"), "t");
+    }
+
+    #[test]
+    fn test_ratio() {
+        assert_eq!(rep("(+ 1/3 1/6)"), "1/2");
+        assert_eq!(rep("(* 2/3 3/2)"), "1");
+        assert_eq!(rep("(- 1/2)"), "-1/2");
+        assert_eq!(rep("(/ 2/3)"), "3/2");
+        assert_eq!(rep("(+ 1/4 0.5)"), "0.75");
+        assert_eq!(rep("(/ 99999999999999999999 7)"), "99999999999999999999/7");
+        assert_eq!(rep("(< 1/3 0.34 1/2)"), "t");
+        assert_eq!(rep("(floor -7/2)"), "-4\n1/2");
+        assert_eq!(rep("(eql 1/2 (/ 2 4))"), "t");
+        assert_eq!(rep("(typep 2/4 'ratio)"), "t");
+        assert_eq!(rep("(typep 4/2 'ratio)"), "nil");
+    }
+
+    #[test]
+    fn test_float() {
+        assert_eq!(rep("1.0"), "1.0");
+        assert_eq!(rep("1d0"), "1.0d0");
+        assert_eq!(rep("12345678.0"), "1.2345678e7");
+        assert_eq!(rep("(+ 1 1.5 1d0)"), "3.5d0");
+        assert_eq!(rep("(float 1/3)"), "0.33333334");
+        assert_eq!(rep("(float 1/2 1d0)"), "0.5d0");
+        assert_eq!(rep("(typep 1.0 'single-float)"), "t");
+        assert_eq!(rep("(eql 1.0 1d0)"), "nil");
+        assert_eq!(
+            rep("(handler-case (/ 1 0.0) (division-by-zero (c) (arithmetic-error-operands c)))"),
+            "(1 0.0)"
+        );
+        assert_eq!(
+            rep("(handler-case (/ 1.0 0) (division-by-zero () 'zero))"),
+            "zero"
+        );
+        assert_eq!(
+            rep("(handler-case (* 1.0 1e38 10) (floating-point-overflow (c) (arithmetic-error-operation c)))"),
+            "*"
+        );
+        assert_eq!(
+            rep("(handler-case (float 1d300 1.0) (floating-point-overflow () 'overflow))"),
+            "overflow"
+        );
+        assert_eq!(rep("(* 1d0 1e38 10)"), "9.999999680285692d38");
+
+        let tiny = format!("1/1{}", "0".repeat(400));
+        assert_eq!(
+            rep(&format!("(/ 1 {})", tiny)),
+            format!("1{}", "0".repeat(400))
+        );
+        assert_eq!(
+            rep(&format!(
+                "(handler-case (/ 1.0d0 {}) (floating-point-overflow () 'overflow))",
+                tiny
+            )),
+            "overflow"
+        );
+    }
+
+    #[test]
+    fn test_complex() {
+        assert_eq!(rep("(* #C(0 1) #C(0 1))"), "-1");
+        assert_eq!(rep("(* #C(1 2) #C(3 4))"), "#C(-5 10)");
+        assert_eq!(rep("(/ #C(1 2) #C(3 4))"), "#C(11/25 2/25)");
+        assert_eq!(rep("(+ #C(1 2) 1.5)"), "#C(2.5 2.0)");
+        assert_eq!(rep("(complex 1/2)"), "1/2");
+        assert_eq!(rep("(complex 1.0)"), "#C(1.0 0.0)");
+        assert_eq!(rep("(imagpart 1.5d0)"), "0.0d0");
+        assert_eq!(rep("(conjugate #C(1 2))"), "#C(1 -2)");
+        assert_eq!(rep("(abs #C(3 4))"), "5.0");
+        assert_eq!(rep("(abs -1/2)"), "1/2");
+        assert_eq!(rep("(phase -1)"), "3.1415927");
+        assert_eq!(rep("(= #C(1 2) #C(1.0 2.0))"), "t");
+        assert_eq!(rep("(eql #C(1 2) #C(1 2))"), "t");
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(rep("(length \"héllo\")"), "5");
+        assert_eq!(rep("(char \"abc\" 1)"), "#\\b");
+        assert_eq!(rep("(string= \"abc\" 'abc)"), "t");
+        assert_eq!(rep("(subseq \"hello world\" 6)"), "\"world\"");
+        assert_eq!(rep("(subseq '(a b c d) 1 3)"), "(b c)");
+        assert_eq!(rep("(concatenate 'string \"foo\" \"bar\")"), "\"foobar\"");
+        assert_eq!(rep("(string-upcase \"hello\")"), "\"HELLO\"");
+        assert_eq!(rep("(string-upcase \"straße\")"), "\"STRAßE\"");
+        assert_eq!(rep("(string-downcase \"ÄB\")"), "\"äb\"");
+        assert_eq!(rep("(string-trim \" \" \"  hi  \")"), "\"hi\"");
+        assert_eq!(rep("(equal \"abc\" \"abc\")"), "t");
+        assert_eq!(
+            rep("(handler-case (char \"abc\" 5)
+                   (type-error (c) (list (type-error-datum c) (type-error-expected-type c))))"),
+            "(5 (integer 0 (3)))"
+        );
+        assert_eq!(
+            rep("(handler-case (subseq \"hello\" 3 1)
+                   (type-error (c) (list (type-error-datum c) (type-error-expected-type c))))"),
+            "(3 (integer 0 1))"
+        );
+        assert_eq!(
+            rep("(handler-case (subseq \"hello\" 1 9)
+                   (type-error (c) (list (type-error-datum c) (type-error-expected-type c))))"),
+            "(9 (integer 0 5))"
+        );
+    }
+
+    #[test]
+    fn test_index_out_of_range() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let err = core::rep("(char \"abc\" 5)", env, arena).unwrap_err();
+        assert_eq!(err.to_string(), "char: index 5 out of range for length 3");
+        let err = core::rep("(subseq \"hello\" 3 1)", env, arena).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "subseq: bounding indices 3 and 1 are invalid for length 5"
+        );
+    }
+
+    #[test]
+    fn test_char() {
+        assert_eq!(rep("'(#\\a #\\Space #\\U+7)"), "(#\\a #\\Space #\\U+0007)");
+        assert_eq!(rep("(char-code #\\λ)"), "955");
+        assert_eq!(rep("(code-char 65)"), "#\\A");
+        assert_eq!(rep("(char-upcase #\\ä)"), "#\\Ä");
+        assert_eq!(rep("(char-upcase #\\ß)"), "#\\ß");
+        assert_eq!(rep("(alpha-char-p #\\λ)"), "t");
+        assert_eq!(rep("(digit-char-p #\\f 16)"), "15");
+        assert_eq!(rep("(digit-char-p #\\x)"), "nil");
+        assert_eq!(rep("(digit-char-p #\\٣)"), "nil");
+        assert_eq!(rep("(alphanumericp #\\٣)"), "nil");
+        assert_eq!(rep("(alphanumericp #\\7)"), "t");
+        assert_eq!(rep("(char< #\\a #\\b #\\c)"), "t");
+        assert_eq!(rep("(char/= #\\a #\\b #\\a)"), "nil");
+    }
+
+    #[test]
+    fn test_array() {
+        assert_eq!(rep("(vector 1 \"a\" #\\b)"), "#(1 \"a\" #\\b)");
+        assert_eq!(
+            rep("(make-array '(2 3) :initial-element 0)"),
+            "#2A((0 0 0) (0 0 0))"
+        );
+        assert_eq!(rep("(make-array nil :initial-element 'x)"), "#0Ax");
+        assert_eq!(
+            rep("(let ((a (make-array '(2 2) :initial-contents '((1 2) (3 4))))) (list (aref a 1 0) (row-major-aref a 3) (array-row-major-index a 1 1)))"),
+            "(3 4 3)"
+        );
+        assert_eq!(rep("(array-dimensions (make-array '(2 3 4)))"), "(2 3 4)");
+        assert_eq!(rep("(array-total-size #2A((1 2) (3 4)))"), "4");
+        assert_eq!(
+            rep("(let ((v (make-array 4 :initial-element 0))) (setf (aref v 1) 'a (row-major-aref v 2) 'b) v)"),
+            "#(0 a b 0)"
+        );
+        assert_eq!(
+            rep("(let ((v (make-array 2 :fill-pointer 0))) (list (vector-push 'a v) (vector-push 'b v) (vector-push 'c v) v (length v)))"),
+            "(0 1 nil #(a b) 2)"
+        );
+        assert_eq!(
+            rep("(let ((v (make-array 0 :adjustable t :fill-pointer t))) (vector-push-extend 1 v) (vector-push-extend 2 v) (list v (fill-pointer v) (vector-pop v) v))"),
+            "(#(1) 2 2 #(1))"
+        );
+        assert_eq!(rep("(subseq #(a b c d) 1 3)"), "#(b c)");
+        assert_eq!(rep("(concatenate 'vector '(1) #(2 3))"), "#(1 2 3)");
+        assert_eq!(
+            rep("(list (vectorp #(1)) (vectorp #2A()) (typep #(1) 'simple-vector))"),
+            "(t nil t)"
+        );
+        assert_eq!(
+            rep("(handler-case (aref #(1 2) 2) (error () 'out-of-range))"),
+            "out-of-range"
+        );
+    }
+
+    #[test]
+    fn test_hash_table() {
+        assert_eq!(
+            rep("(let ((h (make-hash-table))) (setf (gethash 'a h) 1 (gethash 2 h) 'b) (list (gethash 'a h) (gethash 2 h) (multiple-value-list (gethash 'c h 0)) (hash-table-count h)))"),
+            "(1 b (0 nil) 2)"
+        );
+        assert_eq!(
+            rep("(let ((h (make-hash-table :test 'eq))) (setf (gethash 1.0 h) 1) (gethash 1.0 h))"),
+            "nil\nnil"
+        );
+        assert_eq!(
+            rep("(let ((h (make-hash-table))) (setf (gethash 1.0 h) 1 (gethash #\\a h) 2) (list (gethash 1.0 h) (gethash 1 h) (gethash #\\a h)))"),
+            "(1 nil 2)"
+        );
+        assert_eq!(
+            rep("(let ((h (make-hash-table :test #'equal))) (setf (gethash (list 1 \"a\") h) 'x) (list (gethash (list 1 \"a\") h) (gethash (list 1 \"A\") h) (hash-table-test h)))"),
+            "(x nil equal)"
+        );
+        assert_eq!(
+            rep("(let ((h (make-hash-table :test 'equalp))) (setf (gethash (list 1 \"a\" #(#\\b)) h) 'x) (list (gethash (list 1.0 \"A\" #(#\\B)) h) (equalp 1 #C(1.0 0.0))))"),
+            "(x t)"
+        );
+        assert_eq!(
+            rep("(let ((h (make-hash-table)) (n 0)) (setf (gethash 1 h) 10 (gethash 2 h) 20 (gethash 3 h) 30) (list (remhash 2 h) (remhash 2 h) (progn (maphash (lambda (k v) (setq n (+ n k v))) h) n) (hash-table-count (clrhash h))))"),
+            "(t nil 44 0)"
+        );
+        assert_eq!(
+            rep("(let ((h (make-hash-table))) (setf (gethash 'a h) 1) h)"),
+            "#<hash-table :test eql :count 1>"
+        );
+    }
+
+    #[test]
+    fn test_print() {
+        assert_eq!(
+            rep("(prin1-to-string '(\"a\\\"b\" 1))"),
+            r#""(\"a\\\"b\" 1)""#
+        );
+        assert_eq!(rep("(princ-to-string '(\"a\\\"b\" 1))"), r#""(a\"b 1)""#);
+    }
+
+    #[test]
+    fn test_funcall_primitive() {
+        assert_eq!(rep("(funcall (function car) '(1 2))"), "1");
+        assert_eq!(
+            rep("(funcall (lambda (f) (funcall f 1 2)) (function +))"),
+            "3"
+        );
+    }
+
+    #[test]
+    fn test_wrong_number_of_arguments() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let err = core::rep("(car 1 2)", env, arena).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::WrongNumberOfArguments {
+                name: "car".to_string(),
+                expected: types::RuccoArity::exact(1),
+                actual: 2
+            }
+            .to_string()
+        );
+        let err = core::rep("(funcall (function cons) 1)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongNumberOfArguments { .. })
+        ));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        core::rep(
+            "(defun count (n acc) (if (= n 0) acc (count (- n 1) (+ acc 1))))",
+            env,
+            arena,
+        )
+        .unwrap();
+        assert_eq!(core::rep("(count 10000 0)", env, arena).unwrap(), "10000");
+        core::rep(
+            "(defun len (l) (if (null l) 0 (+ 1 (len (cdr l)))))",
+            env,
+            arena,
+        )
+        .unwrap();
+        assert_eq!(core::rep("(len '(1 2 3))", env, arena).unwrap(), "3");
+    }
+}
@@ -1,25 +1,61 @@
+use num_traits::Zero as _;
+
+use crate::core;
+use crate::structure;
 use crate::types;
 
+/// Integer, optionally followed by a decimal point
 static INT_PATTERN: once_cell::sync::Lazy<regex::Regex> =
-    once_cell::sync::Lazy::new(|| regex::Regex::new(r"^([+-]?[0-9]+)(?:[ ();]|$)").unwrap());
+    once_cell::sync::Lazy::new(|| regex::Regex::new(r"^([+-]?[0-9]+)\.?(?:[\s();]|$)").unwrap());
+static RATIO_PATTERN: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
+    regex::Regex::new(r"^([+-]?[0-9]+)/([0-9]+)(?:[\s();]|$)").unwrap()
+});
+/// Float with an optional exponent marker and exponent, tried after
+/// INT_PATTERN so that a mantissa without a fraction needs an exponent
 static FLOAT_PATTERN: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
-    regex::Regex::new(r"^([+-]?[0-9]*\.[0-9]+)(?:[ ();]|$)").unwrap()
+    regex::Regex::new(
+        r"^([+-]?(?:[0-9]*\.[0-9]+|[0-9]+\.?[0-9]*))(?:([esfdlESFDL])([+-]?[0-9]+))?(?:[\s();]|$)",
+    )
+    .unwrap()
 });
 static SYMBOL_PATTERN: once_cell::sync::Lazy<regex::Regex> =
-    once_cell::sync::Lazy::new(|| regex::Regex::new(r"^[^ ();]+").unwrap());
+    once_cell::sync::Lazy::new(|| regex::Regex::new(r#"^[^\s();'`,"]+"#).unwrap());
 
 pub struct Reader<'a> {
     input: &'a str,
+    env: &'a mut core::RuccoEnv,
     arena: &'a mut types::RuccoArena,
 }
 
 impl Reader<'_> {
-    pub fn new<'a>(input: &'a str, arena: &'a mut types::RuccoArena) -> Reader {
-        Reader { input, arena }
+    pub fn new<'a>(
+        input: &'a str,
+        env: &'a mut core::RuccoEnv,
+        arena: &'a mut types::RuccoArena,
+    ) -> Reader<'a> {
+        Reader { input, env, arena }
     }
 
+    /// Skip whitespace and line comments
     fn skip_whitespace(&mut self) {
-        self.input = self.input.trim_start();
+        loop {
+            self.input = self.input.trim_start();
+            match self.input.strip_prefix(';') {
+                Some(rest) => self.input = rest.split_once('\n').map_or("", |(_, rest)| rest),
+                None => break,
+            }
+        }
+    }
+
+    /// Return the input not read yet
+    pub fn rest(&self) -> &str {
+        self.input
+    }
+
+    /// Return true if only whitespace and comments are left
+    pub fn is_eof(&mut self) -> bool {
+        self.skip_whitespace();
+        self.input.is_empty()
     }
 
     fn read_atom(&mut self) -> anyhow::Result<types::RuccoExpRef> {
@@ -27,16 +63,59 @@ impl Reader<'_> {
 
         if let Some(m) = INT_PATTERN.captures(self.input) {
             let s = m.get(1).unwrap().as_str();
-            let i = s.parse::<i64>().unwrap();
+            let i = match s.parse::<i64>() {
+                Ok(i) => types::RuccoAtom::Int(i),
+                Err(_) => s.parse::<num_bigint::BigInt>()?.into(),
+            };
             self.input = &self.input[s.len()..];
+            self.input = self.input.strip_prefix('.').unwrap_or(self.input);
 
             return Ok(self.arena.alloc(i.into()));
         }
 
+        if let Some(m) = RATIO_PATTERN.captures(self.input) {
+            let numer = m.get(1).unwrap().as_str().parse::<num_bigint::BigInt>()?;
+            let denom = m.get(2).unwrap();
+            let denom = denom.as_str().parse::<num_bigint::BigInt>()?;
+            if denom.is_zero() {
+                anyhow::bail!(types::RuccoRuntimeErr::DivisionByZero {
+                    name: "/".to_string(),
+                    operands: vec![numer.clone().into(), 0.into()],
+                });
+            }
+            let r: types::RuccoAtom = num_rational::BigRational::new(numer, denom).into();
+            self.input = &self.input[m.get(2).unwrap().end()..];
+
+            return Ok(self.arena.alloc(r.into()));
+        }
+
         if let Some(m) = FLOAT_PATTERN.captures(self.input) {
-            let s = m.get(1).unwrap().as_str();
-            let f = s.parse::<f64>().unwrap();
-            self.input = &self.input[s.len()..];
+            let read_default = self.env.float_format();
+            let format = m.get(2).map_or(read_default, |marker| {
+                types::FloatFormat::from_marker(
+                    marker.as_str().chars().next().unwrap(),
+                    read_default,
+                )
+            });
+            let exponent = m.get(3).map_or("0", |exponent| exponent.as_str());
+            let s = format!("{}e{}", m.get(1).unwrap().as_str(), exponent);
+            let (f, is_finite): (types::RuccoAtom, _) = match format {
+                types::FloatFormat::Single => {
+                    let f = s.parse::<f32>()?;
+                    (f.into(), f.is_finite())
+                }
+                types::FloatFormat::Double => {
+                    let f = s.parse::<f64>()?;
+                    (f.into(), f.is_finite())
+                }
+            };
+            let end = m.get(3).or(m.get(1)).unwrap().end();
+            if !is_finite {
+                anyhow::bail!(types::RuccoReaderErr::FloatOutOfRange {
+                    token: self.input[..end].to_string(),
+                });
+            }
+            self.input = &self.input[end..];
 
             return Ok(self.arena.alloc(f.into()));
         }
@@ -114,6 +193,150 @@ impl Reader<'_> {
         }
     }
 
+    /// Read a string, a backslash escapes the character after it
+    fn read_string(&mut self) -> anyhow::Result<types::RuccoExpRef> {
+        let mut chars = self.input[1..].char_indices(); // skip '"'
+        let mut s = String::new();
+        loop {
+            match chars.next() {
+                None => anyhow::bail!(types::RuccoReaderErr::UnexpectedEof),
+                Some((i, '"')) => {
+                    self.input = &self.input[i + 2..]; // skip '"'
+                    break;
+                }
+                Some((_, '\\')) => match chars.next() {
+                    None => anyhow::bail!(types::RuccoReaderErr::UnexpectedEof),
+                    Some((_, c)) => s.push(c),
+                },
+                Some((_, c)) => s.push(c),
+            }
+        }
+
+        Ok(self.arena.alloc(types::RuccoAtom::String(s).into()))
+    }
+
+    /// Read the object after the dispatching macro character #
+    /// Read the contents of an array of the rank as nested sequences
+    fn read_array(&mut self, rank: usize) -> anyhow::Result<types::RuccoExpRef> {
+        let contents = self.read()?;
+        let contents = contents
+            .upgrade()
+            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+        let (dimensions, items) = types::RuccoExp::array_contents(&contents, rank)?;
+
+        Ok(self.arena.alloc(types::RuccoExp::Array {
+            dimensions,
+            items,
+            adjustable: false,
+            fill_pointer: None,
+        }))
+    }
+
+    fn read_dispatch(&mut self) -> anyhow::Result<types::RuccoExpRef> {
+        let mut chars = self.input[1..].chars(); // skip '#'
+        match chars.next() {
+            None => anyhow::bail!(types::RuccoReaderErr::UnexpectedEof),
+            Some('\'') => {
+                self.input = &self.input[2..]; // skip "#'"
+                let function = self.arena.alloc_symbol("function");
+                let exp = self.read()?;
+
+                Ok(types::alloc!(self.arena, [function, exp]))
+            }
+            Some('C' | 'c') => {
+                self.input = &self.input[2..]; // skip "#C"
+                let nil = self.arena.alloc_symbol("nil");
+                let exp = self.read()?;
+                let exp = exp
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let parts = exp.borrow().extract_args::<2, 2>("complex", &nil)?;
+                let [real, imag] = parts.map(|part| match &*part.borrow() {
+                    types::RuccoExp::Atom(atom) if atom.is_real() => Ok(atom.clone()),
+                    exp => Err(types::RuccoRuntimeErr::WrongTypeArgument {
+                        name: "complex".to_string(),
+                        expected: types::RuccoDataType::Real,
+                        actual: types::RuccoActualDataType::from(exp).with_datum(&part),
+                    }),
+                });
+                let complex = types::RuccoAtom::new_complex(real?, imag?);
+
+                Ok(self.arena.alloc(complex.into()))
+            }
+            Some('\\') => {
+                // the character after the backslash is taken even if it
+                // terminates tokens, a longer token is a character name
+                let rest = &self.input[2..]; // skip "#\\"
+                let first = rest
+                    .chars()
+                    .next()
+                    .ok_or(types::RuccoReaderErr::UnexpectedEof)?;
+                let len = first.len_utf8()
+                    + SYMBOL_PATTERN
+                        .find(&rest[first.len_utf8()..])
+                        .map_or(0, |m| m.end());
+                let token = &rest[..len];
+                let c = if len == first.len_utf8() {
+                    first
+                } else {
+                    types::name_char(token).ok_or_else(|| {
+                        types::RuccoReaderErr::UnknownCharacterName {
+                            name: token.to_string(),
+                        }
+                    })?
+                };
+                self.input = &rest[len..];
+
+                Ok(self.arena.alloc(types::RuccoAtom::Char(c).into()))
+            }
+            Some('(') => {
+                self.input = &self.input[1..]; // skip '#'
+                self.read_array(1)
+            }
+            Some('S' | 's') => {
+                self.input = &self.input[2..]; // skip "#S"
+                let exp = self.read()?;
+                let exp = exp
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let args = exp.borrow().to_vec()?;
+                let Some((name, initargs)) = args.split_first() else {
+                    anyhow::bail!(types::RuccoRuntimeErr::WrongNumberOfArguments {
+                        name: "#S".to_string(),
+                        expected: types::RuccoArity::new(1, None),
+                        actual: 0,
+                    });
+                };
+                let name = name.borrow();
+                let name =
+                    name.as_symbol()
+                        .ok_or_else(|| types::RuccoRuntimeErr::WrongTypeArgument {
+                            name: "#S".to_string(),
+                            expected: types::RuccoDataType::Symbol,
+                            actual: types::RuccoActualDataType::from(&*name),
+                        })?;
+                structure::read_structure(self.env, self.arena, name, initargs)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let digits = &self.input[1..];
+                let len = digits
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(digits.len());
+                match digits[len..].chars().next() {
+                    None => anyhow::bail!(types::RuccoReaderErr::UnexpectedEof),
+                    Some('A' | 'a') => {}
+                    Some(char) => anyhow::bail!(types::RuccoReaderErr::UnexpectedChar { char }),
+                }
+                let rank = digits[..len].parse()?;
+                self.input = &digits[len + 1..]; // skip "#nA"
+                self.read_array(rank)
+            }
+            // uninterned symbols are read as symbols named with the prefix
+            Some(':') => self.read_atom(),
+            Some(char) => anyhow::bail!(types::RuccoReaderErr::UnexpectedChar { char }),
+        }
+    }
+
     pub fn read(&mut self) -> anyhow::Result<types::RuccoExpRef> {
         self.skip_whitespace();
         let c = self
@@ -130,6 +353,28 @@ impl Reader<'_> {
 
                 Ok(types::alloc!(self.arena, [quote, exp]))
             }
+            '`' => {
+                self.input = &self.input[1..]; // skip '`'
+                let quasiquote = self.arena.alloc_symbol("quasiquote");
+                let exp = self.read()?;
+
+                Ok(types::alloc!(self.arena, [quasiquote, exp]))
+            }
+            ',' => {
+                self.input = &self.input[1..]; // skip ','
+                let unquote = match self.input.strip_prefix('@') {
+                    Some(rest) => {
+                        self.input = rest; // skip '@'
+                        self.arena.alloc_symbol("unquote-splicing")
+                    }
+                    None => self.arena.alloc_symbol("unquote"),
+                };
+                let exp = self.read()?;
+
+                Ok(types::alloc!(self.arena, [unquote, exp]))
+            }
+            '"' => self.read_string(),
+            '#' => self.read_dispatch(),
             '(' => self.read_cons(),
             ')' => Err(anyhow::anyhow!(types::RuccoReaderErr::UnexpectedEof)),
             _ => self.read_atom(),
@@ -147,7 +392,8 @@ mod tests {
     fn test_read_atom_0() {
         let input = "";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp = reader.read().unwrap_err();
         assert_eq!(exp.to_string(), RuccoReaderErr::UnexpectedEof.to_string());
     }
@@ -156,7 +402,8 @@ mod tests {
     fn test_read_atom_1() {
         let input = "    ";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp = reader.read().unwrap_err();
         assert_eq!(exp.to_string(), RuccoReaderErr::UnexpectedEof.to_string());
     }
@@ -165,7 +412,8 @@ mod tests {
     fn test_read_atom_2() {
         let input = "42";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow(), 42.into());
@@ -175,27 +423,30 @@ mod tests {
     fn test_read_atom_3() {
         let input = "42.3";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp = reader.read().unwrap();
         let exp_ptr = exp.upgrade().unwrap();
-        assert_eq!(*exp_ptr.borrow(), 42.3.into());
+        assert_eq!(*exp_ptr.borrow(), 42.3f32.into());
     }
 
     #[test]
     fn test_read_atom_4() {
         let input = "   42.3";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp = reader.read().unwrap();
         let exp_ptr = exp.upgrade().unwrap();
-        assert_eq!(*exp_ptr.borrow(), 42.3.into());
+        assert_eq!(*exp_ptr.borrow(), 42.3f32.into());
     }
 
     #[test]
     fn test_read_atom_5() {
         let input = "a";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow(), RuccoExp::new_symbol("a"));
@@ -205,7 +456,8 @@ mod tests {
     fn test_read_atom_6() {
         let input = "   a";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow(), RuccoExp::new_symbol("a"));
@@ -215,17 +467,180 @@ mod tests {
     fn test_read_atom_7() {
         let input = "1+";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow(), RuccoExp::new_symbol("1+"));
     }
 
+    #[test]
+    fn test_read_atom_8() {
+        let input = "-99999999999999999999";
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
+        let exp_ = reader.read().unwrap();
+        let exp_ptr = exp_.upgrade().unwrap();
+        let expected = "-99999999999999999999"
+            .parse::<num_bigint::BigInt>()
+            .unwrap();
+        assert_eq!(
+            *exp_ptr.borrow(),
+            RuccoExp::Atom(types::RuccoAtom::BigInt(expected))
+        );
+    }
+
+    #[test]
+    fn test_read_atom_9() {
+        let input = "-4/6";
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
+        let exp_ = reader.read().unwrap();
+        let exp_ptr = exp_.upgrade().unwrap();
+        assert_eq!(exp_ptr.borrow().to_string(), "-2/3");
+    }
+
+    #[test]
+    fn test_read_float() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut check = |input, expected: RuccoExp| {
+            let exp = Reader::new(input, env, arena).read().unwrap();
+            let exp_ptr = exp.upgrade().unwrap();
+            assert_eq!(*exp_ptr.borrow(), expected);
+        };
+        check("1.5e10", 1.5e10f32.into());
+        check("1d0", 1.0f64.into());
+        check("1.0f0", 1.0f32.into());
+        check("-.5L-3", (-0.5e-3f64).into());
+        check("2.", 2.into());
+    }
+
+    #[test]
+    fn test_read_complex() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut read = |input| {
+            let exp = Reader::new(input, env, arena).read().unwrap();
+            let exp_ptr = exp.upgrade().unwrap();
+            let res = exp_ptr.borrow().to_string();
+            res
+        };
+        assert_eq!(read("#C(1 -2/4)"), "#C(1 -1/2)");
+        assert_eq!(read("#c(1.5 0)"), "#C(1.5 0.0)");
+        assert_eq!(read("#C(1 0)"), "1");
+    }
+
+    #[test]
+    fn test_read_string() {
+        let input = r#"("a \"b\" \\c" d)"#;
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
+        let exp = reader.read().unwrap();
+        let exp_ptr = exp.upgrade().unwrap();
+        let car = exp_ptr.borrow().car_weak_ref().unwrap().upgrade().unwrap();
+        assert_eq!(
+            *car.borrow(),
+            RuccoExp::Atom(types::RuccoAtom::String(r#"a "b" \c"#.to_string()))
+        );
+        assert_eq!(exp_ptr.borrow().to_string(), r#"("a \"b\" \\c" d)"#);
+    }
+
+    #[test]
+    fn test_read_char() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut check = |input, expected: char| {
+            let exp = Reader::new(input, env, arena).read().unwrap();
+            let exp_ptr = exp.upgrade().unwrap();
+            assert_eq!(*exp_ptr.borrow(), types::RuccoAtom::Char(expected).into());
+        };
+        check("#\\a", 'a');
+        check("#\\(", '(');
+        check("#\\space", ' ');
+        check("#\\Newline", '\n');
+        check("#\\Tab", '\t');
+        check("#\\U+1F600", '\u{1F600}');
+
+        let err = Reader::new("#\\Foo", env, arena).read().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            RuccoReaderErr::UnknownCharacterName {
+                name: "Foo".to_string()
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_read_array() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut check = |input, expected| {
+            let exp = Reader::new(input, env, arena).read().unwrap();
+            let exp_ptr = exp.upgrade().unwrap();
+            assert_eq!(exp_ptr.borrow().to_string(), expected);
+        };
+        check("#(1 2 3)", "#(1 2 3)");
+        check("#()", "#()");
+        check("#(a #(b) \"c\")", "#(a #(b) \"c\")");
+        check("#2A((1 2 3) (4 5 6))", "#2A((1 2 3) (4 5 6))");
+        check("#2a(#(1 2) #(3 4))", "#2A((1 2) (3 4))");
+        check("#2A()", "#2A()");
+        check("#0A5", "#0A5");
+
+        let err = Reader::new("#2A((1 2) (3))", env, arena)
+            .read()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::MalformedArrayContents.to_string()
+        );
+    }
+
+    #[test]
+    fn test_read_structure() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        core::rep("(defstruct point x (y (+ 1 2)))", env, arena).unwrap();
+        let exp = Reader::new("#S(point :y \"a\" :x 1)", env, arena)
+            .read()
+            .unwrap();
+        let exp_ptr = exp.upgrade().unwrap();
+        assert_eq!(exp_ptr.borrow().to_string(), "#S(point :x 1 :y \"a\")");
+        let exp = Reader::new("#S(point :x 1)", env, arena).read().unwrap();
+        let exp_ptr = exp.upgrade().unwrap();
+        assert_eq!(exp_ptr.borrow().to_string(), "#S(point :x 1 :y 3)");
+
+        let err = Reader::new("#S(point :z 1)", env, arena)
+            .read()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::VoidSlot {
+                name: "z".to_string()
+            }
+            .to_string()
+        );
+        let err = Reader::new("#S(line :x 1)", env, arena).read().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::VoidClass {
+                name: "line".to_string()
+            }
+            .to_string()
+        );
+    }
+
     #[test]
     fn test_read_cons_1() {
         let input = "(1 2 3)";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow().to_string(), "(1 2 3)".to_string());
@@ -235,7 +650,8 @@ mod tests {
     fn test_read_cons_2() {
         let input = "(1 2 . 3)";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow().to_string(), "(1 2 . 3)".to_string());
@@ -245,7 +661,8 @@ mod tests {
     fn test_read_cons_3() {
         let input = "(1 2 3";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp = reader.read().unwrap_err();
         assert_eq!(exp.to_string(), RuccoReaderErr::UnexpectedEof.to_string());
     }
@@ -254,7 +671,8 @@ mod tests {
     fn test_read_cons_4() {
         let input = "(1 2 . 3";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp = reader.read().unwrap_err();
         assert_eq!(exp.to_string(), RuccoReaderErr::UnexpectedEof.to_string());
     }
@@ -263,7 +681,8 @@ mod tests {
     fn test_read_cons_5() {
         let input = "(1 2 3))";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow().to_string(), "(1 2 3)".to_string());
@@ -273,7 +692,8 @@ mod tests {
     fn test_read_cons_6() {
         let input = "(1 2 . 3))";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow().to_string(), "(1 2 . 3)".to_string());
@@ -283,9 +703,61 @@ mod tests {
     fn test_read_quote() {
         let input = "'a";
         let arena = &mut types::RuccoArena::default();
-        let mut reader = Reader::new(input, arena);
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
         let exp_ = reader.read().unwrap();
         let exp_ptr = exp_.upgrade().unwrap();
         assert_eq!(*exp_ptr.borrow().to_string(), "(quote a)".to_string());
     }
+
+    #[test]
+    fn test_read_function() {
+        let input = "#'car";
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
+        let exp = reader.read().unwrap();
+        let exp_ptr = exp.upgrade().unwrap();
+        assert_eq!(exp_ptr.borrow().to_string(), "(function car)");
+    }
+
+    #[test]
+    fn test_read_quasiquote() {
+        let input = "`(a ,b ,@c)";
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
+        let exp_ = reader.read().unwrap();
+        let exp_ptr = exp_.upgrade().unwrap();
+        assert_eq!(
+            *exp_ptr.borrow().to_string(),
+            "(quasiquote (a (unquote b) (unquote-splicing c)))".to_string()
+        );
+    }
+
+    #[test]
+    fn test_read_quasiquote_nested() {
+        let input = "`(a `(b ,(c ,d)))";
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
+        let exp_ = reader.read().unwrap();
+        let exp_ptr = exp_.upgrade().unwrap();
+        assert_eq!(
+            *exp_ptr.borrow().to_string(),
+            "(quasiquote (a (quasiquote (b (unquote (c (unquote d)))))))".to_string()
+        );
+    }
+
+    #[test]
+    fn test_read_comment() {
+        let input = "; comment\n(a\tb ; inner\n c)\n;; trailing";
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::RuccoEnv::default();
+        let mut reader = Reader::new(input, env, arena);
+        let exp_ = reader.read().unwrap();
+        let exp_ptr = exp_.upgrade().unwrap();
+        assert_eq!(*exp_ptr.borrow().to_string(), "(a b c)".to_string());
+        assert!(reader.is_eof());
+    }
 }
@@ -0,0 +1,112 @@
+use std::rc::Rc;
+
+use crate::core;
+use crate::types;
+
+/// Slot of a structure type
+pub struct Slot {
+    pub name: String,
+    /// Form computing the default value, evaluated by the constructor
+    pub initform: types::RuccoExpRef,
+    pub read_only: bool,
+}
+
+/// Structure type defined by defstruct, the slots include those of the
+/// included type first
+pub struct StructureClass {
+    pub include: Option<String>,
+    pub slots: Vec<Slot>,
+}
+
+/// Return whether the type is the parent type or includes it
+pub fn is_subtype(env: &core::RuccoEnv, name: &str, parent: &str) -> bool {
+    name == parent
+        || env
+            .get_structure_class(name)
+            .and_then(|c| c.include.as_deref())
+            .is_some_and(|include| is_subtype(env, include, parent))
+}
+
+/// Return the slot names and values of a property list of keywords naming
+/// the slots
+fn slot_values(
+    name: &str,
+    initargs: &[types::RuccoExpRefStrong],
+) -> anyhow::Result<Vec<(String, types::RuccoExpRef)>> {
+    if !initargs.len().is_multiple_of(2) {
+        anyhow::bail!(types::RuccoRuntimeErr::OddNumberOfKeywordArguments {
+            name: name.to_string(),
+        });
+    }
+    initargs
+        .chunks(2)
+        .map(|pair| {
+            let key = pair[0].borrow();
+            let key = key
+                .as_symbol()
+                .ok_or_else(|| types::RuccoRuntimeErr::WrongTypeArgument {
+                    name: name.to_string(),
+                    expected: types::RuccoDataType::Symbol,
+                    actual: types::RuccoActualDataType::from(&*key).with_datum(&pair[0]),
+                })?;
+            let slot = key.strip_prefix(':').unwrap_or(key).to_string();
+            Ok((slot, Rc::downgrade(&pair[1])))
+        })
+        .collect()
+}
+
+/// Make a structure of the type, initargs is a property list of keywords
+/// naming the slots
+pub fn make_structure(
+    arena: &mut types::RuccoArena,
+    name: &str,
+    initargs: &[types::RuccoExpRefStrong],
+) -> anyhow::Result<types::RuccoExpRef> {
+    let slots = slot_values(name, initargs)?;
+    Ok(arena.alloc(types::RuccoExp::Structure {
+        name: name.to_string(),
+        slots,
+    }))
+}
+
+/// Make a structure of the type as #S reads it, the slots are those of the
+/// type in order and the ones missing from the initargs are initialized by
+/// evaluating their initform
+pub fn read_structure(
+    env: &mut core::RuccoEnv,
+    arena: &mut types::RuccoArena,
+    name: &str,
+    initargs: &[types::RuccoExpRefStrong],
+) -> anyhow::Result<types::RuccoExpRef> {
+    let given = slot_values(name, initargs)?;
+    let class = env
+        .get_structure_class(name)
+        .ok_or_else(|| types::RuccoRuntimeErr::VoidClass {
+            name: name.to_string(),
+        })?;
+    if let Some((slot, _)) = given
+        .iter()
+        .find(|(slot, _)| !class.slots.iter().any(|s| s.name == *slot))
+    {
+        anyhow::bail!(types::RuccoRuntimeErr::VoidSlot { name: slot.clone() });
+    }
+    let class_slots = class
+        .slots
+        .iter()
+        .map(|slot| (slot.name.clone(), slot.initform.clone()))
+        .collect::<Vec<_>>();
+
+    let mut slots = Vec::with_capacity(class_slots.len());
+    for (slot, initform) in class_slots {
+        // a slot given twice takes its first value
+        let value = match given.iter().find(|(name, _)| *name == slot) {
+            Some((_, value)) => value.clone(),
+            None => core::eval(&initform, env, arena)?,
+        };
+        slots.push((slot, value));
+    }
+    Ok(arena.alloc(types::RuccoExp::Structure {
+        name: name.to_string(),
+        slots,
+    }))
+}
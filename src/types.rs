@@ -5,11 +5,17 @@ mod rucco_exp;
 
 pub(crate) use rucco_arena::alloc;
 pub use rucco_arena::RuccoArena;
+pub use rucco_atom::name_char;
+pub use rucco_atom::FloatFormat;
+pub use rucco_atom::Printed;
 pub use rucco_atom::RuccoAtom;
+pub use rucco_err::RuccoActualDataType;
+pub use rucco_err::RuccoArity;
 pub use rucco_err::RuccoDataType;
 pub use rucco_err::RuccoReaderErr;
 pub use rucco_err::RuccoReplErr;
 pub use rucco_err::RuccoRuntimeErr;
+pub use rucco_exp::HashTest;
 pub use rucco_exp::RuccoExp;
 pub use rucco_exp::RuccoExpRef;
 pub use rucco_exp::RuccoExpRefStrong;
@@ -6,6 +6,9 @@ use std::rc::Rc;
 pub struct RuccoArena {
     arena: Vec<RuccoExpRefStrong>,
     symbols: std::collections::HashMap<String, RuccoExpRef>,
+    /// Positions of the objects by address, indexed up to the newest object
+    /// when one is looked up
+    addresses: std::collections::HashMap<usize, usize>,
 }
 
 impl RuccoArena {
@@ -15,6 +18,28 @@ impl RuccoArena {
         Rc::downgrade(&exp_ref)
     }
 
+    /// Allocate a proper list of the given elements
+    pub fn alloc_list(&mut self, items: Vec<RuccoExpRef>) -> RuccoExpRef {
+        let nil = self.alloc_symbol("nil");
+        items
+            .into_iter()
+            .rev()
+            .fold(nil, |cdr, car| self.alloc((car, cdr).into()))
+    }
+
+    /// Find the object allocated at the address, as recorded in errors
+    ///
+    /// Objects are never freed, so the address cannot have been reused.  The
+    /// objects allocated since the last lookup are indexed first.
+    pub fn lookup(&mut self, address: usize) -> Option<RuccoExpRef> {
+        let indexed = self.addresses.len();
+        for (i, exp) in self.arena.iter().enumerate().skip(indexed) {
+            self.addresses.insert(Rc::as_ptr(exp) as usize, i);
+        }
+        let i = self.addresses.get(&address)?;
+        Some(Rc::downgrade(&self.arena[*i]))
+    }
+
     pub fn alloc_symbol(&mut self, sym: &str) -> RuccoExpRef {
         if let Some(exp) = self.symbols.get(sym) {
             exp.clone()
@@ -57,6 +82,7 @@ impl Default for RuccoArena {
         Self {
             arena: Vec::with_capacity(10000),
             symbols: std::collections::HashMap::new(),
+            addresses: std::collections::HashMap::new(),
         }
     }
 }
@@ -79,6 +105,33 @@ mod tests {
         assert_eq!(e3.upgrade().unwrap().borrow().to_string(), "(3 2 1)");
     }
 
+    #[test]
+    fn test_lookup() {
+        let mut arena = RuccoArena::default();
+        let c1 = arena.alloc(1.into());
+        let address = |e: &RuccoExpRef| e.as_ptr() as usize;
+        assert!(arena.lookup(address(&c1)).unwrap().ptr_eq(&c1));
+
+        // objects allocated after a lookup are found too
+        let c2 = arena.alloc(2.into());
+        assert!(arena.lookup(address(&c2)).unwrap().ptr_eq(&c2));
+        assert!(arena.lookup(address(&c1)).unwrap().ptr_eq(&c1));
+        assert!(arena.lookup(0).is_none());
+    }
+
+    #[test]
+    fn test_alloc_list() {
+        let mut arena = RuccoArena::default();
+        let c1 = arena.alloc(1.into());
+        let c2 = arena.alloc(2.into());
+
+        let e1 = arena.alloc_list(vec![]);
+        assert_eq!(e1.upgrade().unwrap().borrow().to_string(), "nil");
+
+        let e2 = arena.alloc_list(vec![c1, c2]);
+        assert_eq!(e2.upgrade().unwrap().borrow().to_string(), "(1 2)");
+    }
+
     #[test]
     fn test_alloc_macro() {
         let mut arena = RuccoArena::default();
@@ -1,39 +1,285 @@
-#[derive(Debug, PartialEq)]
+use num_traits::ToPrimitive as _;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum RuccoAtom {
     Int(i64),
-    Float(f64),
+    /// Integer out of the range of Int, never one that fits in it
+    BigInt(num_bigint::BigInt),
+    /// Ratio in lowest terms, never one with denominator 1
+    Ratio(num_rational::BigRational),
+    SingleFloat(f32),
+    DoubleFloat(f64),
+    /// Complex with both parts rational or both floats of the same format,
+    /// never one with rational parts and a zero imaginary part
+    Complex(Box<RuccoAtom>, Box<RuccoAtom>),
+    Char(char),
+    String(String),
     Symbol(String),
 }
 
 impl Eq for RuccoAtom {}
 
-impl std::fmt::Display for RuccoAtom {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+/// Format of a float, the value of *read-default-float-format* selects the
+/// one of floats read without an exponent marker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatFormat {
+    Single,
+    Double,
+}
+
+impl FloatFormat {
+    /// Return the format of the exponent marker, the default one for e
+    pub fn from_marker(marker: char, default: Self) -> Self {
+        match marker.to_ascii_lowercase() {
+            's' | 'f' => FloatFormat::Single,
+            'd' | 'l' => FloatFormat::Double,
+            _ => default,
+        }
+    }
+
+    fn type_name(self) -> &'static str {
         match self {
+            FloatFormat::Single => "single-float",
+            FloatFormat::Double => "double-float",
+        }
+    }
+
+    fn marker(self) -> char {
+        match self {
+            FloatFormat::Single => 'f',
+            FloatFormat::Double => 'd',
+        }
+    }
+}
+
+/// Value printed with the format of floats read by default
+///
+/// The Display of atoms and expressions prints with single-float, the
+/// initial value of *read-default-float-format*.
+pub struct Printed<'a, T> {
+    pub value: &'a T,
+    pub read_default: FloatFormat,
+}
+
+/// Write the float so that it reads back as the same float
+///
+/// Floats of the default format are written without an exponent marker in
+/// the range where positional notation is used, the others always with one.
+fn write_float<T>(
+    f: &mut std::fmt::Formatter,
+    e: &T,
+    abs: f64,
+    format: FloatFormat,
+    read_default: FloatFormat,
+) -> std::fmt::Result
+where
+    T: std::fmt::Display + std::fmt::LowerExp,
+{
+    let is_default = format == read_default;
+    // arithmetic signals an error instead of making these, they are written
+    // so that they never read back as symbols
+    if !abs.is_finite() {
+        return write!(f, "#<{} {}>", format.type_name(), e);
+    }
+    if abs == 0.0 || (1e-3..1e7).contains(&abs) {
+        let s = e.to_string();
+        let point = if s.contains('.') { "" } else { ".0" };
+        return if is_default {
+            write!(f, "{}{}", s, point)
+        } else {
+            write!(f, "{}{}{}0", s, point, format.marker())
+        };
+    }
+    let s = format!("{:e}", e);
+    let (mantissa, exponent) = s.split_once('e').unwrap_or((&s, "0"));
+    let point = if mantissa.contains('.') { "" } else { ".0" };
+    let marker = if is_default { 'e' } else { format.marker() };
+    write!(f, "{}{}{}{}", mantissa, point, marker, exponent)
+}
+
+/// Names of characters, the first name of a character is the one printed
+const CHAR_NAMES: &[(&str, char)] = &[
+    ("Null", '\0'),
+    ("Nul", '\0'),
+    ("Backspace", '\x08'),
+    ("Tab", '\t'),
+    ("Newline", '\n'),
+    ("Linefeed", '\n'),
+    ("Page", '\x0c'),
+    ("Return", '\r'),
+    ("Space", ' '),
+    ("Rubout", '\x7f'),
+];
+
+/// Return the character named case-insensitively, names of the form U+XXXX
+/// give the code point in hexadecimal
+pub fn name_char(name: &str) -> Option<char> {
+    if let Some(code) = name.strip_prefix("U+").or_else(|| name.strip_prefix("u+")) {
+        return u32::from_str_radix(code, 16).ok().and_then(char::from_u32);
+    }
+    CHAR_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, c)| *c)
+}
+
+/// Return the name a character is printed with, None for graphic characters
+fn char_name(c: char) -> Option<String> {
+    match CHAR_NAMES.iter().find(|(_, char)| *char == c) {
+        Some((name, _)) => Some(name.to_string()),
+        None if c.is_control() || c.is_whitespace() => Some(format!("U+{:04X}", c as u32)),
+        None => None,
+    }
+}
+
+/// Print the atom as prin1 does, the alternate form `{:#}` prints strings and
+/// characters without escapes as princ does
+impl std::fmt::Display for Printed<'_, RuccoAtom> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let read_default = self.read_default;
+        match self.value {
             RuccoAtom::Int(e) => write!(f, "{}", e),
-            RuccoAtom::Float(e) => write!(f, "{}", e),
+            RuccoAtom::BigInt(e) => write!(f, "{}", e),
+            RuccoAtom::Ratio(e) => write!(f, "{}", e),
+            RuccoAtom::SingleFloat(e) => {
+                write_float(f, e, e.abs().into(), FloatFormat::Single, read_default)
+            }
+            RuccoAtom::DoubleFloat(e) => {
+                write_float(f, e, e.abs(), FloatFormat::Double, read_default)
+            }
+            RuccoAtom::Complex(real, imag) => write!(
+                f,
+                "#C({} {})",
+                real.printed(read_default),
+                imag.printed(read_default)
+            ),
+            RuccoAtom::Char(e) if f.alternate() => write!(f, "{}", e),
+            RuccoAtom::Char(e) => match char_name(*e) {
+                Some(name) => write!(f, "#\\{}", name),
+                None => write!(f, "#\\{}", e),
+            },
+            RuccoAtom::String(e) if f.alternate() => write!(f, "{}", e),
+            RuccoAtom::String(e) => {
+                write!(f, "\"")?;
+                for c in e.chars() {
+                    if c == '"' || c == '\\' {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+                write!(f, "\"")
+            }
             RuccoAtom::Symbol(e) => write!(f, "{}", e),
         }
     }
 }
 
+impl std::fmt::Display for RuccoAtom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.printed(FloatFormat::Single).fmt(f)
+    }
+}
+
 impl std::convert::From<i64> for RuccoAtom {
     fn from(e: i64) -> Self {
         RuccoAtom::Int(e)
     }
 }
 
+/// Demote the integer to Int when it fits
+impl std::convert::From<num_bigint::BigInt> for RuccoAtom {
+    fn from(e: num_bigint::BigInt) -> Self {
+        match i64::try_from(&e) {
+            Ok(i) => RuccoAtom::Int(i),
+            Err(_) => RuccoAtom::BigInt(e),
+        }
+    }
+}
+
+/// Demote the ratio to an integer when its denominator is 1
+impl std::convert::From<num_rational::BigRational> for RuccoAtom {
+    fn from(e: num_rational::BigRational) -> Self {
+        if e.is_integer() {
+            e.to_integer().into()
+        } else {
+            RuccoAtom::Ratio(e)
+        }
+    }
+}
+
+impl std::convert::From<f32> for RuccoAtom {
+    fn from(e: f32) -> Self {
+        RuccoAtom::SingleFloat(e)
+    }
+}
+
 impl std::convert::From<f64> for RuccoAtom {
     fn from(e: f64) -> Self {
-        RuccoAtom::Float(e)
+        RuccoAtom::DoubleFloat(e)
     }
 }
 
 impl RuccoAtom {
+    pub fn printed(&self, read_default: FloatFormat) -> Printed<'_, RuccoAtom> {
+        Printed {
+            value: self,
+            read_default,
+        }
+    }
+
     pub fn new_symbol<T>(e: T) -> Self
     where
         T: Into<String>,
     {
         RuccoAtom::Symbol(e.into())
     }
+
+    /// Return the complex with the parts, the real part itself if both
+    /// parts are rational and the imaginary part is zero
+    ///
+    /// Float parts are converted to the wider format of the two.
+    pub fn new_complex(real: RuccoAtom, imag: RuccoAtom) -> Self {
+        match (&real, &imag) {
+            (RuccoAtom::SingleFloat(_), RuccoAtom::SingleFloat(_))
+            | (RuccoAtom::DoubleFloat(_), RuccoAtom::DoubleFloat(_)) => {
+                RuccoAtom::Complex(Box::new(real), Box::new(imag))
+            }
+            (RuccoAtom::DoubleFloat(_), _) | (_, RuccoAtom::DoubleFloat(_)) => RuccoAtom::Complex(
+                Box::new(real.to_f64().into()),
+                Box::new(imag.to_f64().into()),
+            ),
+            (RuccoAtom::SingleFloat(_), _) | (_, RuccoAtom::SingleFloat(_)) => RuccoAtom::Complex(
+                Box::new((real.to_f64() as f32).into()),
+                Box::new((imag.to_f64() as f32).into()),
+            ),
+            (_, RuccoAtom::Int(0)) => real,
+            _ => RuccoAtom::Complex(Box::new(real), Box::new(imag)),
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        !matches!(
+            self,
+            RuccoAtom::Char(_) | RuccoAtom::String(_) | RuccoAtom::Symbol(_)
+        )
+    }
+
+    pub fn is_real(&self) -> bool {
+        self.is_number() && !matches!(self, RuccoAtom::Complex(..))
+    }
+
+    /// Return the value of a real number as a double, NaN if it is not one
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            RuccoAtom::Int(e) => *e as f64,
+            RuccoAtom::BigInt(e) => e.to_f64().unwrap_or(f64::NAN),
+            RuccoAtom::Ratio(e) => e.to_f64().unwrap_or(f64::NAN),
+            RuccoAtom::SingleFloat(e) => (*e).into(),
+            RuccoAtom::DoubleFloat(e) => *e,
+            RuccoAtom::Complex(..)
+            | RuccoAtom::Char(_)
+            | RuccoAtom::String(_)
+            | RuccoAtom::Symbol(_) => f64::NAN,
+        }
+    }
 }
@@ -1,18 +1,26 @@
+use super::RuccoAtom;
 use super::RuccoExp;
+use super::RuccoExpRefStrong;
 
 #[derive(thiserror::Error, Debug)]
 pub enum RuccoReplErr {
-    #[error("EmptyInput")]
+    #[error("empty input")]
     EmptyInput,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum RuccoReaderErr {
-    #[error("UnexpectedEof")]
+    #[error("unexpected end of input")]
     UnexpectedEof,
 
-    #[error("UnexpectedChar")]
+    #[error("unexpected character {char:?}")]
     UnexpectedChar { char: char },
+
+    #[error("float out of range: {token}")]
+    FloatOutOfRange { token: String },
+
+    #[error("unknown character name {name}")]
+    UnknownCharacterName { name: String },
 }
 
 #[derive(Debug)]
@@ -20,12 +28,26 @@ pub enum RuccoDataType {
     // top level
     Atom,
     Cons,
+    Function,
+    Condition,
+    Structure,
+    Array,
+    HashTable,
 
     // atom
+    Number,
+    Real,
+    Complex,
     Int,
+    Ratio,
     Float,
+    Char,
+    String,
     Symbol,
 
+    // array
+    Vector,
+
     // cons
     List,
     DotList,
@@ -35,10 +57,63 @@ pub enum RuccoDataType {
     T,
 }
 
+impl RuccoDataType {
+    /// Return the name of the type in Lisp
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuccoDataType::Atom => "atom",
+            RuccoDataType::Cons | RuccoDataType::DotList => "cons",
+            RuccoDataType::Function => "function",
+            RuccoDataType::Condition => "condition",
+            RuccoDataType::Structure => "structure-object",
+            RuccoDataType::Array => "array",
+            RuccoDataType::Vector => "vector",
+            RuccoDataType::HashTable => "hash-table",
+            RuccoDataType::Number => "number",
+            RuccoDataType::Real => "real",
+            RuccoDataType::Complex => "complex",
+            RuccoDataType::Int => "integer",
+            RuccoDataType::Ratio => "ratio",
+            RuccoDataType::Float => "float",
+            RuccoDataType::Char => "character",
+            RuccoDataType::String => "string",
+            RuccoDataType::Symbol => "symbol",
+            RuccoDataType::List => "list",
+            RuccoDataType::Nil => "null",
+            RuccoDataType::T => "t",
+        }
+    }
+}
+
+impl std::fmt::Display for RuccoDataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 #[derive(Debug)]
 pub struct RuccoActualDataType {
     pub data_type: Vec<RuccoDataType>,
     pub value: String,
+    /// Address of the offending object, None when only its contents were at
+    /// hand
+    ///
+    /// Errors have to be Send, so the object is found again in the arena by
+    /// RuccoArena::lookup.
+    pub datum: Option<usize>,
+}
+
+impl RuccoActualDataType {
+    /// Keep the object the description was made of
+    ///
+    /// The object is not borrowed so that the description can be made while
+    /// it is borrowed mutably.
+    pub fn with_datum(self, datum: &RuccoExpRefStrong) -> Self {
+        RuccoActualDataType {
+            datum: Some(std::rc::Rc::as_ptr(datum) as usize),
+            ..self
+        }
+    }
 }
 
 impl From<&RuccoExp> for RuccoActualDataType {
@@ -48,49 +123,217 @@ impl From<&RuccoExp> for RuccoActualDataType {
                 super::RuccoAtom::Int(e) => RuccoActualDataType {
                     data_type: vec![RuccoDataType::Atom, RuccoDataType::Int],
                     value: e.to_string(),
+                    datum: None,
                 },
-                super::RuccoAtom::Float(e) => RuccoActualDataType {
-                    data_type: vec![RuccoDataType::Atom, RuccoDataType::Float],
+                super::RuccoAtom::BigInt(e) => RuccoActualDataType {
+                    data_type: vec![RuccoDataType::Atom, RuccoDataType::Int],
                     value: e.to_string(),
+                    datum: None,
+                },
+                super::RuccoAtom::Ratio(e) => RuccoActualDataType {
+                    data_type: vec![RuccoDataType::Atom, RuccoDataType::Ratio],
+                    value: e.to_string(),
+                    datum: None,
+                },
+                super::RuccoAtom::SingleFloat(_) | super::RuccoAtom::DoubleFloat(_) => {
+                    RuccoActualDataType {
+                        data_type: vec![RuccoDataType::Atom, RuccoDataType::Float],
+                        value: atom.to_string(),
+                        datum: None,
+                    }
+                }
+                super::RuccoAtom::Complex(..) => RuccoActualDataType {
+                    data_type: vec![RuccoDataType::Atom, RuccoDataType::Complex],
+                    value: atom.to_string(),
+                    datum: None,
+                },
+                super::RuccoAtom::Char(_) => RuccoActualDataType {
+                    data_type: vec![RuccoDataType::Atom, RuccoDataType::Char],
+                    value: atom.to_string(),
+                    datum: None,
+                },
+                super::RuccoAtom::String(_) => RuccoActualDataType {
+                    data_type: vec![RuccoDataType::Atom, RuccoDataType::String],
+                    value: atom.to_string(),
+                    datum: None,
                 },
                 super::RuccoAtom::Symbol(e) => RuccoActualDataType {
                     data_type: vec![RuccoDataType::Atom, RuccoDataType::Symbol],
                     value: e.to_string(),
+                    datum: None,
                 },
             },
             RuccoExp::Cons { car: _, cdr: _ } => RuccoActualDataType {
                 data_type: vec![RuccoDataType::Cons, RuccoDataType::List],
                 value: exp.to_string(),
+                datum: None,
+            },
+            RuccoExp::Closure { .. } | RuccoExp::Primitive(_) => RuccoActualDataType {
+                data_type: vec![RuccoDataType::Function],
+                value: exp.to_string(),
+                datum: None,
+            },
+            RuccoExp::Condition { .. } => RuccoActualDataType {
+                data_type: vec![RuccoDataType::Condition],
+                value: exp.to_string(),
+                datum: None,
+            },
+            RuccoExp::Array { dimensions, .. } => RuccoActualDataType {
+                data_type: if dimensions.len() == 1 {
+                    vec![RuccoDataType::Array, RuccoDataType::Vector]
+                } else {
+                    vec![RuccoDataType::Array]
+                },
+                value: exp.to_string(),
+                datum: None,
+            },
+            RuccoExp::Structure { .. } => RuccoActualDataType {
+                data_type: vec![RuccoDataType::Structure],
+                value: exp.to_string(),
+                datum: None,
+            },
+            RuccoExp::HashTable { .. } => RuccoActualDataType {
+                data_type: vec![RuccoDataType::HashTable],
+                value: exp.to_string(),
+                datum: None,
             },
         }
     }
 }
 
+/// Number of arguments a function accepts, max is None when unbounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuccoArity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl RuccoArity {
+    pub const fn new(min: usize, max: Option<usize>) -> Self {
+        RuccoArity { min, max }
+    }
+
+    pub const fn exact(n: usize) -> Self {
+        RuccoArity::new(n, Some(n))
+    }
+
+    pub fn contains(&self, n: usize) -> bool {
+        self.min <= n && self.max.is_none_or(|max| n <= max)
+    }
+}
+
+impl std::fmt::Display for RuccoArity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{}..{}", self.min, max),
+            None => write!(f, "{}..", self.min),
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum RuccoRuntimeErr {
-    #[error("VoidVariable")]
+    #[error("unbound variable {name}")]
     VoidVariable { name: String },
 
-    #[error("VoidFunction")]
+    #[error("undefined function {name}")]
     VoidFunction { name: String },
 
-    #[error("InvalidReference")]
+    #[error("no block named {name}")]
+    VoidBlock { name: String },
+
+    #[error("no go tag named {name}")]
+    VoidTag { name: String },
+
+    #[error("no catch for tag {tag}")]
+    NoCatch { tag: String },
+
+    #[error("no slot named {name}")]
+    VoidSlot { name: String },
+
+    #[error("no class named {name}")]
+    VoidClass { name: String },
+
+    #[error("slot {name} is read-only")]
+    ReadOnlySlot { name: String },
+
+    #[error("unhandled condition {condition}")]
+    UnhandledCondition { condition: String },
+
+    #[error("no frame {index}")]
+    VoidFrame { index: usize },
+
+    #[error("invalid reference")]
     InvalidReference,
 
-    #[error("WrongTypeArgument")]
+    #[error("{name}: {} is not of type {expected}", actual.value)]
     WrongTypeArgument {
         name: String,
         expected: RuccoDataType,
         actual: RuccoActualDataType,
     },
 
-    #[error("WrongNumberOfArguments")]
+    /// Argument not of the structure type named expected
+    #[error("{name}: {} is not of type {expected}", actual.value)]
+    WrongStructureTypeArgument {
+        name: String,
+        expected: String,
+        actual: RuccoActualDataType,
+    },
+
+    #[error("{name}: wrong number of arguments, {actual} given, {expected} expected")]
     WrongNumberOfArguments {
         name: String,
-        expected: (usize, usize),
+        expected: RuccoArity,
         actual: usize,
     },
 
-    #[error("Unreachable")]
+    #[error("{name}: unknown keyword argument {keyword}")]
+    UnknownKeywordArgument { name: String, keyword: String },
+
+    #[error("{name}: odd number of keyword arguments")]
+    OddNumberOfKeywordArguments { name: String },
+
+    #[error("unknown lambda list keyword {keyword}")]
+    UnknownLambdaListKeyword { keyword: String },
+
+    #[error("malformed array contents")]
+    MalformedArrayContents,
+
+    #[error("{name}: index {index} out of range for length {length}")]
+    IndexOutOfRange {
+        name: String,
+        index: usize,
+        length: usize,
+    },
+
+    #[error("{name}: bounding indices {start} and {end} are invalid for length {length}")]
+    InvalidBoundingIndices {
+        name: String,
+        start: usize,
+        end: usize,
+        length: usize,
+    },
+
+    #[error("{name}: division by zero")]
+    DivisionByZero {
+        name: String,
+        operands: Vec<RuccoAtom>,
+    },
+
+    #[error("{name}: floating point overflow")]
+    FloatingPointOverflow {
+        name: String,
+        operands: Vec<RuccoAtom>,
+    },
+
+    #[error("{name}: integer overflow")]
+    IntegerOverflow { name: String },
+
+    #[error("invalid instruction {name}")]
+    InvalidInstruction { name: String },
+
+    #[error("unreachable")]
     Unreachable,
 }
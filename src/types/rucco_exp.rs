@@ -1,7 +1,11 @@
 use super::rucco_err::*;
+use super::FloatFormat;
+use super::Printed;
 use super::RuccoAtom;
 
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display as _;
 use std::rc::Rc;
 use std::rc::Weak;
 
@@ -11,34 +15,180 @@ pub type RuccoExpRefStrong = Rc<RefCell<RuccoExp>>;
 #[derive(Debug)]
 pub enum RuccoExp {
     Atom(RuccoAtom),
-    Cons { car: RuccoExpRef, cdr: RuccoExpRef },
+    Cons {
+        car: RuccoExpRef,
+        cdr: RuccoExpRef,
+    },
+    Closure {
+        params: RuccoExpRef,
+        code: RuccoExpRef,
+        env: RuccoExpRef,
+        /// Name given by defun, defmacro, flet or labels, None for lambda
+        name: Option<String>,
+    },
+    Primitive(&'static crate::primitive::Primitive),
+    Condition {
+        class: String,
+        slots: Vec<(String, RuccoExpRef)>,
+    },
+    /// Array with its elements in row-major order, only vectors, the arrays
+    /// of rank 1, have a fill pointer
+    Array {
+        dimensions: Vec<usize>,
+        items: Vec<RuccoExpRef>,
+        adjustable: bool,
+        fill_pointer: Option<usize>,
+    },
+    /// Instance of a structure type defined by defstruct
+    Structure {
+        name: String,
+        slots: Vec<(String, RuccoExpRef)>,
+    },
+    /// Hash table, the entries are bucketed by the hash of their key under
+    /// the test
+    HashTable {
+        test: HashTest,
+        buckets: HashMap<u64, Vec<(RuccoExpRef, RuccoExpRef)>>,
+    },
 }
 
-impl std::fmt::Display for RuccoExp {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+/// Equality test of a hash table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashTest {
+    Eq,
+    Eql,
+    Equal,
+    Equalp,
+}
+
+impl HashTest {
+    /// Return the test named by the function name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "eq" => Some(HashTest::Eq),
+            "eql" => Some(HashTest::Eql),
+            "equal" => Some(HashTest::Equal),
+            "equalp" => Some(HashTest::Equalp),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
         match self {
-            RuccoExp::Atom(e) => write!(f, "{}", e),
-            RuccoExp::Cons { .. } => {
-                let mut lst: Vec<String> = Vec::new();
+            HashTest::Eq => "eq",
+            HashTest::Eql => "eql",
+            HashTest::Equal => "equal",
+            HashTest::Equalp => "equalp",
+        }
+    }
+}
+
+/// Write the elements of an array of the dimensions as nested lists
+fn write_array_items(
+    f: &mut std::fmt::Formatter,
+    dimensions: &[usize],
+    items: &[RuccoExpRef],
+    read_default: FloatFormat,
+) -> std::fmt::Result {
+    let Some((&len, rest)) = dimensions.split_first() else {
+        return match items.first().and_then(|e| e.upgrade()) {
+            Some(e) => e.borrow().printed(read_default).fmt(f),
+            None => write!(f, "nil"),
+        };
+    };
+    let size = rest.iter().product::<usize>();
+    write!(f, "(")?;
+    for i in 0..len {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write_array_items(f, rest, &items[i * size..(i + 1) * size], read_default)?;
+    }
+    write!(f, ")")
+}
 
-                for (car, cdr) in self.cons_iter_ptr().unwrap() {
-                    lst.push(format!("{}", car.borrow()));
+/// Print the expression as prin1 does, the alternate form `{:#}` prints as
+/// princ does
+impl std::fmt::Display for Printed<'_, RuccoExp> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let read_default = self.read_default;
+        match self.value {
+            RuccoExp::Atom(e) => e.printed(read_default).fmt(f),
+            exp @ RuccoExp::Cons { .. } => {
+                write!(f, "(")?;
+                for (i, (car, cdr)) in exp.cons_iter_ptr().unwrap().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    car.borrow().printed(read_default).fmt(f)?;
                     match &*cdr.borrow() {
                         RuccoExp::Atom(RuccoAtom::Symbol(s)) if s == "nil" => {}
-                        RuccoExp::Atom(_) => {
-                            lst.push(".".to_string());
-                            lst.push(format!("{}", cdr.borrow()));
-                        }
                         RuccoExp::Cons { .. } => (),
+                        cdr => {
+                            write!(f, " . ")?;
+                            cdr.printed(read_default).fmt(f)?;
+                        }
                     }
                 }
-
-                write!(f, "({})", lst.join(" "))
+                write!(f, ")")
+            }
+            RuccoExp::Closure { .. } => write!(f, "#<closure>"),
+            RuccoExp::Primitive(p) => write!(f, "#<primitive {}>", p.name),
+            RuccoExp::Condition { class, slots } => {
+                write!(f, "#<{}", class)?;
+                for (name, value) in slots {
+                    match value.upgrade() {
+                        Some(value) => {
+                            write!(f, " ({} ", name)?;
+                            value.borrow().printed(read_default).fmt(f)?;
+                            write!(f, ")")?;
+                        }
+                        None => write!(f, " ({})", name)?,
+                    }
+                }
+                write!(f, ">")
+            }
+            RuccoExp::Array {
+                dimensions,
+                items,
+                fill_pointer,
+                ..
+            } => {
+                if dimensions.len() == 1 {
+                    let end = fill_pointer.unwrap_or(items.len());
+                    write!(f, "#")?;
+                    return write_array_items(f, &[end], &items[..end], read_default);
+                }
+                write!(f, "#{}A", dimensions.len())?;
+                write_array_items(f, dimensions, items, read_default)
             }
+            RuccoExp::Structure { name, slots } => {
+                write!(f, "#S({}", name)?;
+                for (slot, value) in slots {
+                    write!(f, " :{} ", slot)?;
+                    match value.upgrade() {
+                        Some(value) => value.borrow().printed(read_default).fmt(f)?,
+                        None => write!(f, "nil")?,
+                    }
+                }
+                write!(f, ")")
+            }
+            RuccoExp::HashTable { test, buckets } => write!(
+                f,
+                "#<hash-table :test {} :count {}>",
+                test.name(),
+                buckets.values().map(Vec::len).sum::<usize>()
+            ),
         }
     }
 }
 
+impl std::fmt::Display for RuccoExp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.printed(FloatFormat::Single).fmt(f)
+    }
+}
+
 impl PartialEq for RuccoExp {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -71,6 +221,23 @@ impl PartialEq for RuccoExp {
                     false
                 }
             },
+            (
+                RuccoExp::Closure {
+                    code: code1,
+                    env: env1,
+                    ..
+                },
+                RuccoExp::Closure {
+                    code: code2,
+                    env: env2,
+                    ..
+                },
+            ) => code1.ptr_eq(code2) && env1.ptr_eq(env2),
+            (RuccoExp::Primitive(p1), RuccoExp::Primitive(p2)) => std::ptr::eq(*p1, *p2),
+            (RuccoExp::Condition { .. }, RuccoExp::Condition { .. })
+            | (RuccoExp::Structure { .. }, RuccoExp::Structure { .. })
+            | (RuccoExp::Array { .. }, RuccoExp::Array { .. })
+            | (RuccoExp::HashTable { .. }, RuccoExp::HashTable { .. }) => std::ptr::eq(self, other),
             _ => false,
         }
     }
@@ -121,75 +288,106 @@ impl RuccoExp {
     }
 }
 
+/// Predicates
+impl RuccoExp {
+    /// Return true if the value is the symbol nil
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rucco::types::*;
+    ///
+    /// assert!(RuccoExp::new_symbol("nil").is_nil());
+    /// assert!(!RuccoExp::new_symbol("t").is_nil());
+    /// ```
+    pub fn is_nil(&self) -> bool {
+        matches!(self, RuccoExp::Atom(RuccoAtom::Symbol(sym)) if sym == "nil")
+    }
+}
+
 /// Accessors
 impl RuccoExp {
+    pub fn printed(&self, read_default: FloatFormat) -> Printed<'_, RuccoExp> {
+        Printed {
+            value: self,
+            read_default,
+        }
+    }
+
+    pub fn as_symbol(&self) -> Option<&str> {
+        match self {
+            RuccoExp::Atom(RuccoAtom::Symbol(sym)) => Some(sym),
+            _ => None,
+        }
+    }
+
     pub fn car(&self) -> anyhow::Result<RuccoExpRefStrong> {
         match self {
-            RuccoExp::Atom(_) => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
+            RuccoExp::Cons { car, .. } => {
+                Ok(car.upgrade().ok_or(RuccoRuntimeErr::InvalidReference)?)
+            }
+            _ => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "car".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(self)
             })),
-            RuccoExp::Cons { car, .. } => {
-                Ok(car.upgrade().ok_or(RuccoRuntimeErr::InvalidReference)?)
-            }
         }
     }
 
     pub fn car_weak(&self) -> anyhow::Result<RuccoExpRef> {
         match self {
-            RuccoExp::Atom(_) => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
+            RuccoExp::Cons { car, .. } => Ok(car.clone()),
+            _ => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "car".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(self)
             })),
-            RuccoExp::Cons { car, .. } => Ok(car.clone()),
         }
     }
 
     pub fn car_weak_ref(&self) -> anyhow::Result<&RuccoExpRef> {
         match self {
-            RuccoExp::Atom(_) => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
+            RuccoExp::Cons { car, .. } => Ok(car),
+            _ => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "car".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(self)
             })),
-            RuccoExp::Cons { car, .. } => Ok(car),
         }
     }
 
     pub fn cdr(&self) -> anyhow::Result<RuccoExpRefStrong> {
         match self {
-            RuccoExp::Atom(_) => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
+            RuccoExp::Cons { cdr, .. } => {
+                Ok(cdr.upgrade().ok_or(RuccoRuntimeErr::InvalidReference)?)
+            }
+            _ => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "cdr".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(self)
             })),
-            RuccoExp::Cons { cdr, .. } => {
-                Ok(cdr.upgrade().ok_or(RuccoRuntimeErr::InvalidReference)?)
-            }
         }
     }
 
     pub fn cdr_weak(&self) -> anyhow::Result<RuccoExpRef> {
         match self {
-            RuccoExp::Atom(_) => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
+            RuccoExp::Cons { cdr, .. } => Ok(cdr.clone()),
+            _ => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "cdr".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(self)
             })),
-            RuccoExp::Cons { cdr, .. } => Ok(cdr.clone()),
         }
     }
 
     pub fn cdr_weak_ref(&self) -> anyhow::Result<&RuccoExpRef> {
         match self {
-            RuccoExp::Atom(_) => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
+            RuccoExp::Cons { cdr, .. } => Ok(cdr),
+            _ => Err(anyhow::anyhow!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "cdr".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(self)
             })),
-            RuccoExp::Cons { cdr, .. } => Ok(cdr),
         }
     }
 
@@ -203,7 +401,7 @@ impl RuccoExp {
         if !(N <= args.len() && args.len() <= M) {
             anyhow::bail!(RuccoRuntimeErr::WrongNumberOfArguments {
                 name: name.to_string(),
-                expected: (N, M),
+                expected: RuccoArity::new(N, Some(M)),
                 actual: args.len()
             });
         }
@@ -238,14 +436,14 @@ impl Iterator for ConsIter {
 
         let cdr_ptr = cdr.upgrade().expect("valid reference");
         match &*cdr_ptr.borrow() {
-            RuccoExp::Atom(_) => {
-                self.car = None;
-                self.cdr = None;
-            }
             RuccoExp::Cons { car, cdr } => {
                 self.car = Some(car.clone());
                 self.cdr = Some(cdr.clone());
             }
+            _ => {
+                self.car = None;
+                self.cdr = None;
+            }
         }
         Some((car, cdr))
     }
@@ -261,12 +459,12 @@ impl Iterator for Iter {
             let cdr_ptr = cdr.upgrade().ok_or(RuccoRuntimeErr::InvalidReference)?;
             let x = match &*cdr_ptr.borrow() {
                 RuccoExp::Atom(RuccoAtom::Symbol(sym)) if sym == "nil" => Ok(car),
-                RuccoExp::Atom(..) => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
+                RuccoExp::Cons { .. } => Ok(car),
+                _ => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
                     name: "iter".to_string(),
                     expected: RuccoDataType::Cons,
                     actual: RuccoActualDataType::from(&*cdr_ptr.borrow())
                 }),
-                _ => Ok(car),
             };
             x
         })
@@ -276,15 +474,19 @@ impl Iterator for Iter {
 impl RuccoExp {
     pub fn cons_iter(&self) -> anyhow::Result<ConsIter> {
         match self {
-            RuccoExp::Atom(_) => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
-                name: "into_iter".to_string(),
-                expected: RuccoDataType::Cons,
-                actual: RuccoActualDataType::from(self)
-            }),
             RuccoExp::Cons { car, cdr } => Ok(ConsIter {
                 car: Some(car.clone()),
                 cdr: Some(cdr.clone()),
             }),
+            _ if self.is_nil() => Ok(ConsIter {
+                car: None,
+                cdr: None,
+            }),
+            _ => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
+                name: "into_iter".to_string(),
+                expected: RuccoDataType::Cons,
+                actual: RuccoActualDataType::from(self)
+            }),
         }
     }
 
@@ -292,6 +494,52 @@ impl RuccoExp {
         Ok(Iter(self.cons_iter()?))
     }
 
+    /// Collect elements of a proper list, nil is an empty list
+    pub fn to_vec(&self) -> anyhow::Result<Vec<RuccoExpRefStrong>> {
+        self.iter_ptr()?.collect()
+    }
+
+    /// Collect elements of a proper list or the active elements of a vector
+    pub fn sequence_to_vec(&self) -> anyhow::Result<Vec<RuccoExpRefStrong>> {
+        match self {
+            RuccoExp::Array {
+                dimensions,
+                items,
+                fill_pointer,
+                ..
+            } if dimensions.len() == 1 => items[..fill_pointer.unwrap_or(items.len())]
+                .iter()
+                .map(|e| e.upgrade().ok_or(RuccoRuntimeErr::InvalidReference.into()))
+                .collect(),
+            _ => self.to_vec(),
+        }
+    }
+
+    /// Return the dimensions and the row-major elements of the array of the
+    /// rank whose contents are given as nested sequences
+    pub fn array_contents(
+        contents: &RuccoExpRefStrong,
+        rank: usize,
+    ) -> anyhow::Result<(Vec<usize>, Vec<RuccoExpRef>)> {
+        let mut dimensions = Vec::with_capacity(rank);
+        let mut level = vec![contents.clone()];
+        for _ in 0..rank {
+            let mut next = Vec::new();
+            let mut length = None;
+            for exp in level {
+                let items = exp.borrow().sequence_to_vec()?;
+                if length.is_some_and(|length| length != items.len()) {
+                    anyhow::bail!(RuccoRuntimeErr::MalformedArrayContents);
+                }
+                length = Some(items.len());
+                next.extend(items);
+            }
+            dimensions.push(length.unwrap_or(0));
+            level = next;
+        }
+        Ok((dimensions, level.iter().map(Rc::downgrade).collect()))
+    }
+
     pub fn cons_iter_ptr(
         &self,
     ) -> anyhow::Result<impl Iterator<Item = (RuccoExpRefStrong, RuccoExpRefStrong)>> {
@@ -345,7 +593,7 @@ impl RuccoExp {
                 car: ref mut cons_car,
                 ..
             } => *cons_car = car.clone(),
-            RuccoExp::Atom(_) => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
+            _ => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "setcar".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(&*self)
@@ -385,7 +633,7 @@ impl RuccoExp {
                 cdr: ref mut cons_cdr,
                 ..
             } => *cons_cdr = cdr.clone(),
-            RuccoExp::Atom(_) => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
+            _ => anyhow::bail!(RuccoRuntimeErr::WrongTypeArgument {
                 name: "setcdr".to_string(),
                 expected: RuccoDataType::Cons,
                 actual: RuccoActualDataType::from(&*self)
@@ -0,0 +1,1612 @@
+use std::rc::Rc;
+
+use crate::compiler;
+use crate::condition;
+use crate::core;
+use crate::primitive;
+use crate::types;
+
+fn to_usize(exp: &types::RuccoExp, name: &str) -> anyhow::Result<usize> {
+    match exp {
+        types::RuccoExp::Atom(types::RuccoAtom::Int(i)) if *i >= 0 => Ok(*i as usize),
+        _ => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Int,
+            actual: types::RuccoActualDataType::from(exp),
+        }),
+    }
+}
+
+fn to_symbol(exp: &types::RuccoExp, name: &str) -> anyhow::Result<String> {
+    match exp.as_symbol() {
+        Some(sym) => Ok(sym.to_string()),
+        None => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+            name: name.to_string(),
+            expected: types::RuccoDataType::Symbol,
+            actual: types::RuccoActualDataType::from(exp),
+        }),
+    }
+}
+
+fn nthcdr(list: &types::RuccoExpRefStrong, n: usize) -> anyhow::Result<types::RuccoExpRefStrong> {
+    (0..n).try_fold(list.clone(), |lst, _| lst.borrow().cdr())
+}
+
+fn nth(list: &types::RuccoExpRefStrong, n: usize) -> anyhow::Result<types::RuccoExpRefStrong> {
+    list.borrow()
+        .iter_ptr()?
+        .nth(n)
+        .ok_or(types::RuccoRuntimeErr::Unreachable)?
+}
+
+fn is_lambda_list_keyword(param: &types::RuccoExpRefStrong) -> bool {
+    param
+        .borrow()
+        .as_symbol()
+        .is_some_and(|sym| sym.starts_with('&'))
+}
+
+/// Return the names of the variables of a normalized lambda list frame, an
+/// empty name for a hidden supplied-p variable
+fn lambda_list_names(params: &[types::RuccoExpRefStrong]) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut key = false;
+    for param in params {
+        match param.borrow().as_symbol() {
+            Some(sym) if sym.starts_with('&') => key = sym == "&key",
+            Some(sym) => names.push(sym.to_string()),
+            None => {
+                for var in param.borrow().to_vec()?.iter().skip(usize::from(key)) {
+                    let var = var.borrow();
+                    names.push(match var.as_symbol() {
+                        Some(sym) if !var.is_nil() => sym.to_string(),
+                        _ => String::new(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Call the function with the list of arguments on a fresh machine
+pub fn call(
+    f: &types::RuccoExpRef,
+    args: &types::RuccoExpRef,
+    env: &mut core::RuccoEnv,
+    arena: &mut types::RuccoArena,
+) -> anyhow::Result<types::RuccoExpRef> {
+    let ldc = arena.alloc_symbol("ldc");
+    let ap = arena.alloc_symbol("ap");
+    let stop = arena.alloc_symbol("stop");
+    let code = types::alloc!(arena, [[ldc, f], [ldc, args], [ap], [stop]]);
+    Vm::new(&code, env, arena).run()
+}
+
+enum Dump {
+    Join {
+        c: types::RuccoExpRef,
+    },
+    /// Return to the caller, with the function called and the E register it
+    /// was entered with for the backtrace
+    Ret {
+        e: types::RuccoExpRef,
+        c: types::RuccoExpRef,
+        function: types::RuccoExpRef,
+        frame: types::RuccoExpRef,
+    },
+    /// Exit point of a catch or block, with the registers to resume at
+    Catch {
+        tag: types::RuccoExpRef,
+        s: usize,
+        e: types::RuccoExpRef,
+        c: types::RuccoExpRef,
+    },
+    /// Tagbody that go can jump back into
+    Tagbody {
+        tag: types::RuccoExpRef,
+        s: usize,
+        e: types::RuccoExpRef,
+        c: types::RuccoExpRef,
+        body_e: types::RuccoExpRef,
+        segments: types::RuccoExpRef,
+    },
+    /// Cleanup code of an unwind-protect
+    Protect {
+        cleanup: types::RuccoExpRef,
+        s: usize,
+        e: types::RuccoExpRef,
+    },
+    /// Exit to continue once the cleanup code of an unwind-protect is done
+    Resume(Exit),
+    /// Dynamic binding of a special variable, with the value it shadows
+    Binding {
+        name: String,
+        value: Option<types::RuccoExpRef>,
+    },
+}
+
+/// Transfer of control pending while cleanup code runs
+enum Exit {
+    Normal {
+        value: types::RuccoExpRef,
+        values: Option<Vec<types::RuccoExpRef>>,
+        e: types::RuccoExpRef,
+        c: types::RuccoExpRef,
+    },
+    Throw {
+        tag: types::RuccoExpRef,
+        value: types::RuccoExpRef,
+        values: Option<Vec<types::RuccoExpRef>>,
+    },
+    Go {
+        tag: types::RuccoExpRef,
+        k: usize,
+    },
+    Error(anyhow::Error),
+    /// Error whose condition is being signaled, the debugger is entered once
+    /// no handler took it
+    Signaled(anyhow::Error),
+}
+
+/// Choice made by the debugger
+pub enum DebuggerAction {
+    /// Let the error propagate out of the machine
+    Abort,
+    /// Invoke the restart with the arguments
+    Restart {
+        restart: types::RuccoExpRef,
+        args: Vec<types::RuccoExpRef>,
+    },
+}
+
+/// Debugger entered on an error no handler took, before the dump is unwound
+pub type Debugger<'a> = Box<dyn FnMut(&mut Vm, &anyhow::Error) -> DebuggerAction + 'a>;
+
+/// Function call in the backtrace
+pub struct Frame {
+    /// Name of the global function, or lambda
+    pub name: String,
+    /// Parameters with their current values
+    pub locals: Vec<(String, types::RuccoExpRef)>,
+}
+
+/// Restart in effect
+pub struct Restart {
+    pub name: String,
+    /// Description given by :report, None without one
+    pub report: Option<String>,
+    /// The (name function report) list to invoke
+    pub restart: types::RuccoExpRef,
+}
+
+/// SECD virtual machine
+///
+/// - S: stack of intermediate values
+/// - E: environment, a list of frames
+/// - C: code, a list of instructions
+/// - D: dump, saved registers to restore
+pub struct Vm<'a> {
+    s: Vec<types::RuccoExpRef>,
+    e: types::RuccoExpRef,
+    c: types::RuccoExpRef,
+    d: Vec<Dump>,
+    /// All values of the last instruction when it returned multiple values,
+    /// the primary one is also on top of S
+    values: Option<Vec<types::RuccoExpRef>>,
+    /// Set while an error propagates through the cleanup code of
+    /// unwind-protects
+    unwinding: bool,
+    debugger: Option<Debugger<'a>>,
+    pub(crate) env: &'a mut core::RuccoEnv,
+    pub(crate) arena: &'a mut types::RuccoArena,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(
+        code: &types::RuccoExpRef,
+        env: &'a mut core::RuccoEnv,
+        arena: &'a mut types::RuccoArena,
+    ) -> Self {
+        let nil = arena.alloc_symbol("nil");
+        Vm {
+            s: Vec::new(),
+            e: nil,
+            c: code.clone(),
+            d: Vec::new(),
+            values: None,
+            unwinding: false,
+            debugger: None,
+            env,
+            arena,
+        }
+    }
+
+    fn pop(&mut self) -> anyhow::Result<types::RuccoExpRef> {
+        Ok(self.s.pop().ok_or(types::RuccoRuntimeErr::Unreachable)?)
+    }
+
+    /// Apply the function to the arguments
+    ///
+    /// Return the E and C registers to enter a closure with, or None when a
+    /// primitive has already pushed its result.  A symbol designates its
+    /// global function.
+    fn apply(
+        &mut self,
+        f: &types::RuccoExpRef,
+        args: &types::RuccoExpRef,
+    ) -> anyhow::Result<Option<(types::RuccoExpRef, types::RuccoExpRef)>> {
+        let f_ptr = f
+            .upgrade()
+            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+        let args_ptr = args
+            .upgrade()
+            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+        let x = match &*f_ptr.borrow() {
+            types::RuccoExp::Closure {
+                params,
+                code,
+                env,
+                name,
+            } => {
+                let name = name.as_deref().unwrap_or("lambda");
+                let params_ptr = params
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let params = params_ptr.borrow().to_vec()?;
+                let args = args_ptr.borrow().to_vec()?;
+                let frame = if params.iter().any(is_lambda_list_keyword) {
+                    self.bind_lambda_list(name, &params, &args)?
+                } else if params.len() == args.len() {
+                    Rc::downgrade(&args_ptr)
+                } else {
+                    anyhow::bail!(types::RuccoRuntimeErr::WrongNumberOfArguments {
+                        name: name.to_string(),
+                        expected: types::RuccoArity::exact(params.len()),
+                        actual: args.len(),
+                    });
+                };
+                let e = self.arena.alloc((frame, env.clone()).into());
+                Ok(Some((e, code.clone())))
+            }
+            types::RuccoExp::Atom(types::RuccoAtom::Symbol(name)) => {
+                let f = self
+                    .env
+                    .get_function(name)
+                    .ok_or_else(|| types::RuccoRuntimeErr::VoidFunction { name: name.clone() })?
+                    .clone();
+                self.apply(&f, args)
+            }
+            types::RuccoExp::Primitive(p) => {
+                let res = p.call(self, &args_ptr.borrow().to_vec()?)?;
+                self.s.push(res);
+                Ok(None)
+            }
+            exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                name: "ap".to_string(),
+                expected: types::RuccoDataType::Function,
+                actual: types::RuccoActualDataType::from(exp).with_datum(&f_ptr),
+            }),
+        };
+        x
+    }
+
+    /// Make the frame of a normalized lambda list for the arguments
+    ///
+    /// Missing optional and keyword arguments and &aux variables are nil,
+    /// the body computes their values.
+    fn bind_lambda_list(
+        &mut self,
+        name: &str,
+        params: &[types::RuccoExpRefStrong],
+        args: &[types::RuccoExpRefStrong],
+    ) -> anyhow::Result<types::RuccoExpRef> {
+        let nil = self.arena.alloc_symbol("nil");
+        let t = self.arena.alloc_symbol("t");
+        let mut frame = Vec::with_capacity(params.len());
+        let mut section = "&required";
+        let mut positional = 0;
+        let mut unbounded = false;
+        let mut allow_other_keys = false;
+        let mut keys = Vec::new();
+        for param in params {
+            if is_lambda_list_keyword(param) {
+                match param.borrow().as_symbol() {
+                    Some("&optional") => section = "&optional",
+                    Some("&rest") => section = "&rest",
+                    Some("&key") => section = "&key",
+                    Some("&aux") => section = "&aux",
+                    Some("&allow-other-keys") => allow_other_keys = true,
+                    keyword => anyhow::bail!(types::RuccoRuntimeErr::UnknownLambdaListKeyword {
+                        keyword: keyword.unwrap_or_default().to_string(),
+                    }),
+                }
+                continue;
+            }
+            match section {
+                "&required" | "&optional" => {
+                    let arg = args.get(positional).map(Rc::downgrade);
+                    if section == "&optional" {
+                        let supplied = if arg.is_some() { &t } else { &nil };
+                        frame.push(arg.unwrap_or_else(|| nil.clone()));
+                        frame.push(supplied.clone());
+                    } else {
+                        frame.push(arg.unwrap_or_else(|| nil.clone()));
+                    }
+                    positional += 1;
+                }
+                "&rest" => {
+                    unbounded = true;
+                    let rest = args.get(positional..).unwrap_or_default();
+                    let rest = self
+                        .arena
+                        .alloc_list(rest.iter().map(Rc::downgrade).collect());
+                    frame.push(rest);
+                }
+                "&key" => {
+                    unbounded = true;
+                    let keyword = param.borrow().car()?.borrow().to_string();
+                    keys.push((keyword, frame.len()));
+                    frame.push(nil.clone());
+                    frame.push(nil.clone());
+                }
+                _ => frame.push(nil.clone()),
+            }
+        }
+
+        let required = params
+            .iter()
+            .take_while(|param| !is_lambda_list_keyword(param))
+            .count();
+        let expected = types::RuccoArity::new(required, (!unbounded).then_some(positional));
+        if !expected.contains(args.len()) {
+            anyhow::bail!(types::RuccoRuntimeErr::WrongNumberOfArguments {
+                name: name.to_string(),
+                expected,
+                actual: args.len(),
+            });
+        }
+
+        if keys.is_empty() {
+            return Ok(self.arena.alloc_list(frame));
+        }
+        let plist = args.get(positional..).unwrap_or_default();
+        if !plist.len().is_multiple_of(2) {
+            anyhow::bail!(types::RuccoRuntimeErr::OddNumberOfKeywordArguments {
+                name: name.to_string(),
+            });
+        }
+        allow_other_keys |= plist
+            .chunks(2)
+            .find(|pair| pair[0].borrow().as_symbol() == Some(":allow-other-keys"))
+            .is_some_and(|pair| !pair[1].borrow().is_nil());
+        for pair in plist.chunks(2) {
+            let keyword = pair[0].borrow().to_string();
+            match keys.iter().find(|(key, _)| *key == keyword) {
+                // the leftmost occurrence of a keyword wins
+                Some((_, j)) if frame[j + 1].ptr_eq(&nil) => {
+                    frame[*j] = Rc::downgrade(&pair[1]);
+                    frame[j + 1] = t.clone();
+                }
+                Some(_) => (),
+                None if allow_other_keys || keyword == ":allow-other-keys" => (),
+                None => anyhow::bail!(types::RuccoRuntimeErr::UnknownKeywordArgument {
+                    name: name.to_string(),
+                    keyword,
+                }),
+            }
+        }
+        Ok(self.arena.alloc_list(frame))
+    }
+
+    /// Return from the current function to the caller
+    fn ret(&mut self) -> anyhow::Result<()> {
+        match self.d.pop() {
+            Some(Dump::Ret { e, c, .. }) => {
+                self.e = e;
+                self.c = c;
+                Ok(())
+            }
+            _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+        }
+    }
+
+    /// Pop the dump down to the given depth, restoring dynamic bindings
+    fn unwind(&mut self, depth: usize) {
+        while self.d.len() > depth {
+            if let Some(Dump::Binding { name, value }) = self.d.pop() {
+                self.restore_binding(&name, value);
+            }
+        }
+    }
+
+    fn restore_binding(&mut self, name: &str, value: Option<types::RuccoExpRef>) {
+        match value {
+            Some(value) => self.env.set_variable(name, &value),
+            None => {
+                self.env.remove_variable(name);
+            }
+        }
+    }
+
+    /// Push an E frame holding a fresh tag for a block or tagbody
+    fn push_tag_frame(&mut self) -> types::RuccoExpRef {
+        let nil = self.arena.alloc_symbol("nil");
+        let tag = self.arena.alloc((&nil, &nil).into());
+        self.e = types::alloc!(self.arena, [[tag]; (self.e.clone())]);
+        tag
+    }
+
+    /// Find the innermost catch or tagbody frame in the dump for the tag
+    fn find_frame(&self, tag: &types::RuccoExpRef, tagbody: bool) -> anyhow::Result<Option<usize>> {
+        let tag = tag
+            .upgrade()
+            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+        for (i, d) in self.d.iter().enumerate().rev() {
+            let frame_tag = match d {
+                Dump::Catch { tag, .. } if !tagbody => tag,
+                Dump::Tagbody { tag, .. } if tagbody => tag,
+                _ => continue,
+            };
+            let frame_tag = frame_tag
+                .upgrade()
+                .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+            if primitive::is_eq(&tag, &frame_tag) {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return the index of the innermost unwind-protect above the dump depth
+    fn find_protect(&self, depth: usize) -> Option<usize> {
+        self.d[depth..]
+            .iter()
+            .rposition(|d| matches!(d, Dump::Protect { .. }))
+            .map(|p| depth + p)
+    }
+
+    /// Pop the dump down to the unwind-protect at index p and run its
+    /// cleanup code, continuing with the exit afterwards
+    fn cleanup(&mut self, p: usize, exit: Exit) -> anyhow::Result<()> {
+        self.unwind(p + 1);
+        match self.d.pop() {
+            Some(Dump::Protect { cleanup, s, e }) => {
+                self.s.truncate(s);
+                (self.e, self.c) = (e, cleanup);
+                self.d.push(Dump::Resume(exit));
+                Ok(())
+            }
+            _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+        }
+    }
+
+    /// Transfer control to a catch, block or tagbody in the dump
+    ///
+    /// Cleanup code of the unwind-protects in between runs first.
+    fn exit(&mut self, exit: Exit) -> anyhow::Result<()> {
+        let i = match &exit {
+            Exit::Throw { tag, .. } => self.find_frame(tag, false)?.ok_or_else(|| {
+                let tag = tag
+                    .upgrade()
+                    .map_or_else(String::new, |tag| tag.borrow().to_string());
+                types::RuccoRuntimeErr::NoCatch { tag }
+            })?,
+            Exit::Go { tag, k } => {
+                self.find_frame(tag, true)?
+                    .ok_or(types::RuccoRuntimeErr::VoidTag {
+                        name: k.to_string(),
+                    })?
+            }
+            _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+        };
+        if let Some(p) = self.find_protect(i + 1) {
+            return self.cleanup(p, exit);
+        }
+
+        self.unwind(i + 1);
+        match (exit, self.d.last()) {
+            (Exit::Throw { value, values, .. }, Some(Dump::Catch { .. })) => {
+                let Some(Dump::Catch { s, e, c, .. }) = self.d.pop() else {
+                    anyhow::bail!(types::RuccoRuntimeErr::Unreachable);
+                };
+                self.s.truncate(s);
+                self.s.push(value);
+                self.values = values;
+                (self.e, self.c) = (e, c);
+            }
+            (
+                Exit::Go { k, .. },
+                Some(Dump::Tagbody {
+                    s,
+                    body_e,
+                    segments,
+                    ..
+                }),
+            ) => {
+                let segments = segments
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                self.s.truncate(*s);
+                self.e = body_e.clone();
+                self.c = Rc::downgrade(&nth(&segments, k)?);
+            }
+            _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+        }
+        Ok(())
+    }
+
+    /// Signal the condition of an error to the Lisp handlers
+    ///
+    /// The error stays pending in the dump and propagates once %signal
+    /// returns, that is when no handler transferred control.  Return the error
+    /// back if there is nothing to signal.
+    fn signal_error(&mut self, err: anyhow::Error) -> anyhow::Result<Option<anyhow::Error>> {
+        let has_handlers = self
+            .env
+            .get_variable("*handler-clusters*")
+            .and_then(|handlers| handlers.upgrade())
+            .is_some_and(|handlers| !handlers.borrow().is_nil());
+        let signal = self.env.get_function("%signal").cloned();
+        let (true, Some(signal)) = (has_handlers, signal) else {
+            return Ok(Some(err));
+        };
+        let Some(condition) = condition::from_error(self, &err)? else {
+            return Ok(Some(err));
+        };
+
+        let ldc = self.arena.alloc_symbol("ldc");
+        let ap = self.arena.alloc_symbol("ap");
+        let pop = self.arena.alloc_symbol("pop");
+        let resume = self.arena.alloc_symbol("resume");
+        self.c = types::alloc!(
+            self.arena,
+            [[ldc, signal], [ldc, [condition]], [ap], [pop], [resume]]
+        );
+        self.d.push(Dump::Resume(Exit::Signaled(err)));
+        Ok(None)
+    }
+
+    /// Enter the debugger with an error no handler took
+    ///
+    /// When a restart is chosen it is invoked, and the debugger is entered
+    /// again if it returns.  Return the error back to propagate otherwise.
+    fn debug(&mut self, err: anyhow::Error) -> anyhow::Result<()> {
+        let Some(mut debugger) = self.debugger.take() else {
+            return Err(err);
+        };
+        let action = debugger(self, &err);
+        self.debugger = Some(debugger);
+        let DebuggerAction::Restart { restart, args } = action else {
+            return Err(err);
+        };
+
+        let invoke = self
+            .env
+            .get_function("invoke-restart")
+            .ok_or_else(|| types::RuccoRuntimeErr::VoidFunction {
+                name: "invoke-restart".to_string(),
+            })?
+            .clone();
+        let args = self
+            .arena
+            .alloc_list(std::iter::once(restart).chain(args).collect());
+        let ldc = self.arena.alloc_symbol("ldc");
+        let ap = self.arena.alloc_symbol("ap");
+        let pop = self.arena.alloc_symbol("pop");
+        let resume = self.arena.alloc_symbol("resume");
+        self.c = types::alloc!(
+            self.arena,
+            [[ldc, invoke], [ldc, args], [ap], [pop], [resume]]
+        );
+        self.d.push(Dump::Resume(Exit::Signaled(err)));
+        Ok(())
+    }
+
+    pub fn set_debugger(&mut self, debugger: Debugger<'a>) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Return the restarts in effect, innermost first
+    pub fn restarts(&self) -> anyhow::Result<Vec<Restart>> {
+        let Some(clusters) = self.env.get_variable("*restart-clusters*") else {
+            return Ok(Vec::new());
+        };
+        let clusters = clusters
+            .upgrade()
+            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+        let mut restarts = Vec::new();
+        for cluster in clusters.borrow().to_vec()? {
+            for restart in cluster.borrow().to_vec()? {
+                let items = restart.borrow().to_vec()?;
+                let report = match items.get(2).map(|report| report.borrow()) {
+                    Some(report) => match &*report {
+                        types::RuccoExp::Atom(types::RuccoAtom::String(s)) => Some(s.clone()),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                restarts.push(Restart {
+                    name: restart.borrow().car()?.borrow().to_string(),
+                    report,
+                    restart: Rc::downgrade(&restart),
+                });
+            }
+        }
+        Ok(restarts)
+    }
+
+    /// Return the function called and its E register for each frame of the
+    /// backtrace, innermost first
+    fn frames(&self) -> impl Iterator<Item = (&types::RuccoExpRef, &types::RuccoExpRef)> {
+        self.d.iter().rev().filter_map(|d| match d {
+            Dump::Ret {
+                function, frame, ..
+            } => Some((function, frame)),
+            _ => None,
+        })
+    }
+
+    /// Return the name of the function and the names of its parameters
+    fn describe_function(
+        &self,
+        function: &types::RuccoExpRef,
+    ) -> anyhow::Result<(String, Vec<String>)> {
+        let f_ptr = function
+            .upgrade()
+            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+        let (name, closure) = match f_ptr.borrow().as_symbol() {
+            Some(name) => (Some(name.to_string()), self.env.get_function(name).cloned()),
+            None => (
+                self.env.function_name(function).map(str::to_string),
+                Some(function.clone()),
+            ),
+        };
+        let Some(closure) = closure.and_then(|closure| closure.upgrade()) else {
+            return Ok((name.unwrap_or_else(|| "lambda".to_string()), Vec::new()));
+        };
+        let mut name = name;
+        let params = match &*closure.borrow() {
+            types::RuccoExp::Closure {
+                params,
+                name: closure_name,
+                ..
+            } => {
+                name = name.or_else(|| closure_name.clone());
+                let params = params
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?
+                    .borrow()
+                    .to_vec()?;
+                lambda_list_names(&params)?
+            }
+            _ => Vec::new(),
+        };
+        Ok((name.unwrap_or_else(|| "lambda".to_string()), params))
+    }
+
+    /// Return the function calls in the dump, innermost first
+    pub fn backtrace(&self) -> anyhow::Result<Vec<Frame>> {
+        let mut frames = Vec::new();
+        for (function, frame) in self.frames() {
+            let (name, params) = self.describe_function(function)?;
+            let frame = frame
+                .upgrade()
+                .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+            let values = frame.borrow().car()?.borrow().to_vec()?;
+            let locals = params
+                .into_iter()
+                .zip(values.iter().map(Rc::downgrade))
+                .filter(|(name, _)| !name.is_empty())
+                .collect();
+            frames.push(Frame { name, locals });
+        }
+        Ok(frames)
+    }
+
+    /// Evaluate the expression with the parameters of the k-th frame of the
+    /// backtrace in scope
+    ///
+    /// Only the parameters of the function itself are visible, variables it
+    /// closes over are not.  setq on a parameter changes the frame.
+    pub fn eval_in_frame(
+        &mut self,
+        k: usize,
+        exp: &types::RuccoExpRef,
+    ) -> anyhow::Result<types::RuccoExpRef> {
+        let (function, frame) = self
+            .frames()
+            .nth(k)
+            .map(|(function, frame)| (function.clone(), frame.clone()))
+            .ok_or(types::RuccoRuntimeErr::VoidFrame { index: k })?;
+        let (_, params) = self.describe_function(&function)?;
+        let code = compiler::compile_in_frame(exp, &params, self.env, self.arena)?;
+        let mut vm = Vm::new(&code, self.env, self.arena);
+        vm.e = frame;
+        vm.run()
+    }
+
+    /// Read an expression into the arena of the machine
+    pub fn read(&mut self, buf: &str) -> anyhow::Result<types::RuccoExpRef> {
+        core::read(buf, self.env, self.arena)
+    }
+
+    /// Return the format the printer leaves floats of without an exponent
+    /// marker
+    pub fn float_format(&self) -> types::FloatFormat {
+        self.env.float_format()
+    }
+
+    /// Evaluate the expression in the global environment
+    pub fn eval(&mut self, exp: &types::RuccoExpRef) -> anyhow::Result<types::RuccoExpRef> {
+        core::eval(exp, self.env, self.arena)
+    }
+
+    /// Describe an error by its message, preceded by the class of the
+    /// condition Lisp handlers see for it
+    pub fn describe_error(&mut self, err: &anyhow::Error) -> String {
+        if let Some(types::RuccoRuntimeErr::UnhandledCondition { condition }) = err.downcast_ref() {
+            return condition.clone();
+        }
+        let condition = match condition::from_error(self, err) {
+            Ok(Some(condition)) => condition.upgrade(),
+            _ => None,
+        };
+        let class = condition.and_then(|condition| match &*condition.borrow() {
+            types::RuccoExp::Condition { class, .. } => Some(class.clone()),
+            _ => None,
+        });
+        match class {
+            Some(class) => format!("{}: {:#}", class, err),
+            None => format!("{:#}", err),
+        }
+    }
+
+    /// Make the values the result of the current instruction, return the
+    /// primary one to push on S
+    pub(crate) fn set_values(&mut self, values: Vec<types::RuccoExpRef>) -> types::RuccoExpRef {
+        let primary = values
+            .first()
+            .cloned()
+            .unwrap_or_else(|| self.arena.alloc_symbol("nil"));
+        self.values = Some(values);
+        primary
+    }
+
+    /// Run until the machine stops, returning every value of the result
+    pub fn run_values(&mut self) -> anyhow::Result<Vec<types::RuccoExpRef>> {
+        let primary = self.run()?;
+        Ok(self.values.take().unwrap_or_else(|| vec![primary]))
+    }
+
+    /// Run until the machine stops
+    ///
+    /// When an error escapes an instruction, its condition is signaled first
+    /// and the debugger is entered if no handler took it, then the cleanup
+    /// code of the pending unwind-protects runs before the error is returned.
+    pub fn run(&mut self) -> anyhow::Result<types::RuccoExpRef> {
+        loop {
+            let err = match self.step() {
+                Ok(Some(exp)) => return Ok(exp),
+                Ok(None) => continue,
+                Err(err) if std::mem::take(&mut self.unwinding) => err,
+                Err(err) => match self.signal_error(err) {
+                    Ok(None) => continue,
+                    Ok(Some(err)) => match self.debug(err) {
+                        Ok(()) => continue,
+                        Err(err) => err,
+                    },
+                    Err(err) => err,
+                },
+            };
+            match self.find_protect(0) {
+                Some(p) => self.cleanup(p, Exit::Error(err))?,
+                None => {
+                    self.unwind(0);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Execute one instruction, return the result when the machine stops
+    fn step(&mut self) -> anyhow::Result<Option<types::RuccoExpRef>> {
+        let c_ptr = self
+            .c
+            .upgrade()
+            .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+        let inst_ptr = c_ptr.borrow().car()?;
+        self.c = c_ptr.borrow().cdr_weak()?;
+
+        let inst = inst_ptr.borrow();
+        let op_ptr = inst.car()?;
+        let args_ptr = inst.cdr()?;
+        let args = args_ptr.borrow();
+        let nil = types::alloc!(self.arena, []);
+
+        let op = op_ptr.borrow();
+        let op = match &*op {
+            types::RuccoExp::Atom(types::RuccoAtom::Symbol(sym)) => sym.as_str(),
+            _ => anyhow::bail!(types::RuccoRuntimeErr::InvalidInstruction {
+                name: op.to_string()
+            }),
+        };
+
+        // multiple values only survive the instructions passing a result on
+        let values = self.values.take();
+        match op {
+            "ld" => {
+                let [pos] = args.extract_args::<1, 1>(op, &nil)?;
+                let pos = pos.borrow();
+                let i = to_usize(&pos.car()?.borrow(), op)?;
+                let j = to_usize(&pos.cdr()?.borrow(), op)?;
+                let e_ptr = self
+                    .e
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let frame = nth(&e_ptr, i)?;
+                self.s.push(Rc::downgrade(&nth(&frame, j)?));
+            }
+            "ldg" => {
+                let [sym] = args.extract_args::<1, 1>(op, &nil)?;
+                let name = to_symbol(&sym.borrow(), op)?;
+                let value = self
+                    .env
+                    .get_variable(&name)
+                    .ok_or(types::RuccoRuntimeErr::VoidVariable { name })?;
+                self.s.push(value.clone());
+            }
+            "lset" => {
+                let [pos] = args.extract_args::<1, 1>(op, &nil)?;
+                let pos = pos.borrow();
+                let i = to_usize(&pos.car()?.borrow(), op)?;
+                let j = to_usize(&pos.cdr()?.borrow(), op)?;
+                let e_ptr = self
+                    .e
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let frame = nth(&e_ptr, i)?;
+                let value = self.s.last().ok_or(types::RuccoRuntimeErr::Unreachable)?;
+                nthcdr(&frame, j)?.borrow_mut().setcar(value)?;
+            }
+            "gset" => {
+                let [sym] = args.extract_args::<1, 1>(op, &nil)?;
+                let name = to_symbol(&sym.borrow(), op)?;
+                let value = self.s.last().ok_or(types::RuccoRuntimeErr::Unreachable)?;
+                self.env.set_variable(&name, value);
+            }
+            "gmset" => {
+                let [sym] = args.extract_args::<1, 1>(op, &nil)?;
+                let name = to_symbol(&sym.borrow(), op)?;
+                let value = self.s.last().ok_or(types::RuccoRuntimeErr::Unreachable)?;
+                self.env.set_macro(&name, value);
+            }
+            "gfset" => {
+                let [sym] = args.extract_args::<1, 1>(op, &nil)?;
+                let name = to_symbol(&sym.borrow(), op)?;
+                let value = self.s.last().ok_or(types::RuccoRuntimeErr::Unreachable)?;
+                self.env.set_function(&name, value);
+            }
+            "ldc" => {
+                let [exp] = args.extract_args::<1, 1>(op, &nil)?;
+                self.s.push(Rc::downgrade(&exp));
+            }
+            "ldf" => {
+                let [params, code, name] = args.extract_args::<2, 3>(op, &nil)?;
+                let name = name.borrow().as_symbol().map(str::to_string);
+                let closure = self.arena.alloc(types::RuccoExp::Closure {
+                    params: Rc::downgrade(&params),
+                    code: Rc::downgrade(&code),
+                    env: self.e.clone(),
+                    name: name.filter(|name| name != "nil"),
+                });
+                self.s.push(closure);
+            }
+            "ldgf" => {
+                let [sym] = args.extract_args::<1, 1>(op, &nil)?;
+                let name = to_symbol(&sym.borrow(), op)?;
+                let value = self
+                    .env
+                    .get_function(&name)
+                    .ok_or(types::RuccoRuntimeErr::VoidFunction { name })?;
+                self.s.push(value.clone());
+            }
+            "args" => {
+                let [n] = args.extract_args::<1, 1>(op, &nil)?;
+                let n = to_usize(&n.borrow(), op)?;
+                let lst = self.s.split_off(
+                    self.s
+                        .len()
+                        .checked_sub(n)
+                        .ok_or(types::RuccoRuntimeErr::Unreachable)?,
+                );
+                let lst = self.arena.alloc_list(lst);
+                self.s.push(lst);
+            }
+            "ap" => {
+                let args = self.pop()?;
+                let f = self.pop()?;
+                if let Some((e, c)) = self.apply(&f, &args)? {
+                    let frame = e.clone();
+                    let e = std::mem::replace(&mut self.e, e);
+                    let c = std::mem::replace(&mut self.c, c);
+                    self.d.push(Dump::Ret {
+                        e,
+                        c,
+                        function: f,
+                        frame,
+                    });
+                }
+            }
+            "tap" => {
+                let args = self.pop()?;
+                let f = self.pop()?;
+                match self.apply(&f, &args)? {
+                    Some((e, c)) => {
+                        // the caller's frame is replaced in the backtrace too
+                        if let Some(Dump::Ret {
+                            function, frame, ..
+                        }) = self.d.last_mut()
+                        {
+                            (*function, *frame) = (f, e.clone());
+                        }
+                        (self.e, self.c) = (e, c)
+                    }
+                    None => self.ret()?,
+                }
+            }
+            "dum" => {
+                self.e = self.arena.alloc((&nil, &self.e).into());
+            }
+            "rap" => {
+                let args = self.pop()?;
+                let f = self.pop()?;
+                let f_ptr = f
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let (e, c) = match &*f_ptr.borrow() {
+                    types::RuccoExp::Closure { code, env, .. } => (env.clone(), code.clone()),
+                    exp => anyhow::bail!(types::RuccoRuntimeErr::WrongTypeArgument {
+                        name: op.to_string(),
+                        expected: types::RuccoDataType::Function,
+                        actual: types::RuccoActualDataType::from(exp).with_datum(&f_ptr),
+                    }),
+                };
+                let e_ptr = e
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                e_ptr.borrow_mut().setcar(&args)?;
+                let outer = e_ptr.borrow().cdr_weak()?;
+                let c = std::mem::replace(&mut self.c, c);
+                self.e = e.clone();
+                self.d.push(Dump::Ret {
+                    e: outer,
+                    c,
+                    function: f,
+                    frame: e,
+                });
+            }
+            "catch" => {
+                let [body] = args.extract_args::<1, 1>(op, &nil)?;
+                let tag = self.pop()?;
+                let c = std::mem::replace(&mut self.c, Rc::downgrade(&body));
+                self.d.push(Dump::Catch {
+                    tag,
+                    s: self.s.len(),
+                    e: self.e.clone(),
+                    c,
+                });
+            }
+            "block" => {
+                let [body] = args.extract_args::<1, 1>(op, &nil)?;
+                let e = self.e.clone();
+                let tag = self.push_tag_frame();
+                let c = std::mem::replace(&mut self.c, Rc::downgrade(&body));
+                self.d.push(Dump::Catch {
+                    tag,
+                    s: self.s.len(),
+                    e,
+                    c,
+                });
+            }
+            "tagbody" => {
+                let [segments] = args.extract_args::<1, 1>(op, &nil)?;
+                let e = self.e.clone();
+                let tag = self.push_tag_frame();
+                let c = std::mem::replace(&mut self.c, Rc::downgrade(&nth(&segments, 0)?));
+                self.d.push(Dump::Tagbody {
+                    tag,
+                    s: self.s.len(),
+                    e,
+                    c,
+                    body_e: self.e.clone(),
+                    segments: Rc::downgrade(&segments),
+                });
+            }
+            "uncatch" => match self.d.pop() {
+                Some(Dump::Catch { e, c, .. } | Dump::Tagbody { e, c, .. }) => {
+                    (self.e, self.c) = (e, c);
+                    self.values = values;
+                }
+                _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+            },
+            "throw" => {
+                let value = self.pop()?;
+                let tag = self.pop()?;
+                self.exit(Exit::Throw { tag, value, values })?;
+            }
+            "go" => {
+                let [k] = args.extract_args::<1, 1>(op, &nil)?;
+                let k = to_usize(&k.borrow(), op)?;
+                let tag = self.pop()?;
+                self.exit(Exit::Go { tag, k })?;
+            }
+            "protect" => {
+                let [cleanup] = args.extract_args::<1, 1>(op, &nil)?;
+                self.d.push(Dump::Protect {
+                    cleanup: Rc::downgrade(&cleanup),
+                    s: self.s.len(),
+                    e: self.e.clone(),
+                });
+            }
+            "unprotect" => {
+                let value = self.pop()?;
+                let exit = Exit::Normal {
+                    value,
+                    values,
+                    e: self.e.clone(),
+                    c: self.c.clone(),
+                };
+                let p = self
+                    .d
+                    .len()
+                    .checked_sub(1)
+                    .ok_or(types::RuccoRuntimeErr::Unreachable)?;
+                self.cleanup(p, exit)?;
+            }
+            "resume" => match self.d.pop() {
+                Some(Dump::Resume(Exit::Normal {
+                    value,
+                    values,
+                    e,
+                    c,
+                })) => {
+                    self.s.push(value);
+                    self.values = values;
+                    (self.e, self.c) = (e, c);
+                }
+                Some(Dump::Resume(Exit::Error(err))) => {
+                    self.unwinding = true;
+                    return Err(err);
+                }
+                Some(Dump::Resume(Exit::Signaled(err))) => {
+                    if let Err(err) = self.debug(err) {
+                        self.unwinding = true;
+                        return Err(err);
+                    }
+                }
+                Some(Dump::Resume(exit)) => self.exit(exit)?,
+                _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+            },
+            "bind" => {
+                let [sym] = args.extract_args::<1, 1>(op, &nil)?;
+                let name = to_symbol(&sym.borrow(), op)?;
+                let value = self.pop()?;
+                let old = self.env.get_variable(&name).cloned();
+                self.env.set_variable(&name, &value);
+                self.d.push(Dump::Binding { name, value: old });
+            }
+            "unbind" => {
+                let [n] = args.extract_args::<1, 1>(op, &nil)?;
+                for _ in 0..to_usize(&n.borrow(), op)? {
+                    match self.d.pop() {
+                        Some(Dump::Binding { name, value }) => self.restore_binding(&name, value),
+                        _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+                    }
+                }
+                self.values = values;
+            }
+            "prim" => {
+                let [sym, n] = args.extract_args::<2, 2>(op, &nil)?;
+                let name = to_symbol(&sym.borrow(), op)?;
+                let n = to_usize(&n.borrow(), op)?;
+                let p =
+                    primitive::find(&name).ok_or(types::RuccoRuntimeErr::VoidFunction { name })?;
+                let prim_args = self
+                    .s
+                    .split_off(
+                        self.s
+                            .len()
+                            .checked_sub(n)
+                            .ok_or(types::RuccoRuntimeErr::Unreachable)?,
+                    )
+                    .iter()
+                    .map(|arg| {
+                        arg.upgrade()
+                            .ok_or(types::RuccoRuntimeErr::InvalidReference)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let res = p.call(self, &prim_args)?;
+                self.s.push(res);
+            }
+            "rtn" => {
+                self.ret()?;
+                self.values = values;
+            }
+            "mvlist" => {
+                let value = self.pop()?;
+                let lst = self.arena.alloc_list(values.unwrap_or_else(|| vec![value]));
+                self.s.push(lst);
+            }
+            "mvargs" => {
+                let [n] = args.extract_args::<1, 1>(op, &nil)?;
+                let value = self.pop()?;
+                let mut values = values.unwrap_or_else(|| vec![value]);
+                values.resize(to_usize(&n.borrow(), op)?, nil.clone());
+                let lst = self.arena.alloc_list(values);
+                self.s.push(lst);
+            }
+            "pop" => {
+                self.pop()?;
+            }
+            "sel" => {
+                let [then_code, else_code] = args.extract_args::<2, 2>(op, &nil)?;
+                let test_ref = self.pop()?;
+                let test_ptr = test_ref
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                let c = std::mem::replace(
+                    &mut self.c,
+                    Rc::downgrade(if test_ptr.borrow().is_nil() {
+                        &else_code
+                    } else {
+                        &then_code
+                    }),
+                );
+                self.d.push(Dump::Join { c });
+            }
+            "selr" => {
+                let [then_code, else_code] = args.extract_args::<2, 2>(op, &nil)?;
+                let test_ref = self.pop()?;
+                let test_ptr = test_ref
+                    .upgrade()
+                    .ok_or(types::RuccoRuntimeErr::InvalidReference)?;
+                self.c = Rc::downgrade(if test_ptr.borrow().is_nil() {
+                    &else_code
+                } else {
+                    &then_code
+                });
+            }
+            "join" => match self.d.pop() {
+                Some(Dump::Join { c }) => {
+                    self.c = c;
+                    self.values = values;
+                }
+                _ => anyhow::bail!(types::RuccoRuntimeErr::Unreachable),
+            },
+            "stop" => {
+                self.values = values;
+                return Ok(Some(self.pop()?));
+            }
+            _ => anyhow::bail!(types::RuccoRuntimeErr::InvalidInstruction {
+                name: op.to_string()
+            }),
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+
+    fn run(input: &str) -> String {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let exp = core::read(input, env, arena).unwrap();
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let res = Vm::new(&code, env, arena).run().unwrap();
+        let res_ptr = res.upgrade().unwrap();
+        let res = res_ptr.borrow().to_string();
+        res
+    }
+
+    #[test]
+    fn test_ldc() {
+        assert_eq!(run("1"), "1");
+        assert_eq!(run("nil"), "nil");
+        assert_eq!(run("'(1 2 . 3)"), "(1 2 . 3)");
+    }
+
+    #[test]
+    fn test_sel() {
+        assert_eq!(run("(if t 1 2)"), "1");
+        assert_eq!(run("(if nil 1 2)"), "2");
+        assert_eq!(run("(if nil 1)"), "nil");
+        assert_eq!(run("(if (if nil t nil) 1 (if t 'a 'b))"), "a");
+    }
+
+    #[test]
+    fn test_ap() {
+        assert_eq!(run("((lambda (x) x) 1)"), "1");
+        assert_eq!(run("((lambda (x y) y) 1 2)"), "2");
+        assert_eq!(run("((lambda () 1 2 3))"), "3");
+        assert_eq!(run("((lambda ()))"), "nil");
+        assert_eq!(run("(funcall (lambda (x) (if x 'a 'b)) nil)"), "b");
+        assert_eq!(run("(funcall (function (lambda (x) x)) 1)"), "1");
+    }
+
+    #[test]
+    fn test_closure() {
+        assert_eq!(run("(funcall ((lambda (x) (lambda (y) x)) 1) 2)"), "1");
+        assert_eq!(
+            run("((lambda (f) (funcall f 2)) ((lambda (x) (lambda (y) (if x y x))) 1))"),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_let() {
+        assert_eq!(run("(let ((x 1) (y 2)) (let ((x y) z) (if z z x)))"), "2");
+        assert_eq!(run("(let* ((x 1) (y x)) y)"), "1");
+        assert_eq!(run("(let* () (progn 1 2))"), "2");
+    }
+
+    #[test]
+    fn test_ap_error() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let exp = core::read("((lambda (x) x))", env, arena).unwrap();
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let err = Vm::new(&code, env, arena).run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::WrongNumberOfArguments {
+                name: "lambda".to_string(),
+                expected: types::RuccoArity::exact(1),
+                actual: 0
+            }
+            .to_string()
+        );
+
+        let exp = core::read("(funcall 1)", env, arena).unwrap();
+        let env = &mut core::default_env(arena);
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let err = Vm::new(&code, env, arena).run().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongTypeArgument { .. })
+        ));
+
+        let exp = core::read("(f 1)", env, arena).unwrap();
+        let env = &mut core::default_env(arena);
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let err = Vm::new(&code, env, arena).run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::VoidFunction {
+                name: "f".to_string()
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_tail_call() {
+        assert_eq!(run("((lambda (x) (if x ((lambda (y) y) 1) 2)) t)"), "1");
+        assert_eq!(run("((lambda (x) (if x 1 ((lambda (y) y) 2))) nil)"), "2");
+
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let exp = core::read(
+            "((lambda (f) (funcall f f)) (lambda (f) (if t (funcall f f) nil)))",
+            env,
+            arena,
+        )
+        .unwrap();
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let mut vm = Vm::new(&code, env, arena);
+        for _ in 0..10000 {
+            assert!(vm.step().unwrap().is_none());
+            assert!(vm.s.len() <= 3);
+            assert!(vm.d.len() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_ld() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let code = core::read("((ld (1 . 2)) (stop))", env, arena).unwrap();
+        let e = core::read("((a b) (c d e))", env, arena).unwrap();
+        let mut vm = Vm::new(&code, env, arena);
+        vm.e = e;
+        let res = vm.run().unwrap();
+        assert_eq!(res.upgrade().unwrap().borrow().to_string(), "e");
+    }
+
+    #[test]
+    fn test_ldg() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let exp = core::read("x", env, arena).unwrap();
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let err = Vm::new(&code, env, arena).run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::VoidVariable {
+                name: "x".to_string()
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_invalid_instruction() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let code = core::read("((foo))", env, arena).unwrap();
+        let err = Vm::new(&code, env, arena).run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            types::RuccoRuntimeErr::InvalidInstruction {
+                name: "foo".to_string()
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_rap() {
+        assert_eq!(
+            run("(labels ((even (n) (if (= n 0) t (odd (- n 1)))) (odd (n) (if (= n 0) nil (even (- n 1)))))
+                   (list (even 10) (odd 10)))"),
+            "(t nil)"
+        );
+        assert_eq!(
+            run("(labels ((f () (function f))) (eq (f) (funcall (f))))"),
+            "t"
+        );
+    }
+
+    #[test]
+    fn test_throw() {
+        assert_eq!(run("(catch 'a (list 1 (throw 'a 2)))"), "2");
+        assert_eq!(run("(catch 'a (catch 'b (throw 'a 1)) 2)"), "1");
+        assert_eq!(
+            run("(list 1 (block b (list 2 (return-from b 3))))"),
+            "(1 3)"
+        );
+        assert_eq!(run("(block nil (return) 1)"), "nil");
+    }
+
+    #[test]
+    fn test_go() {
+        assert_eq!(
+            run("(let ((n 0) (acc nil)) (tagbody top (if (= n 3) (go end)) (setq acc (cons n acc)) (setq n (+ n 1)) (go top) end) acc)"),
+            "(2 1 0)"
+        );
+    }
+
+    #[test]
+    fn test_protect() {
+        assert_eq!(
+            run("(let ((x nil)) (list (unwind-protect 1 (setq x 2) 3) x))"),
+            "(1 2)"
+        );
+        assert_eq!(
+            run("(let ((x nil)) (list (catch 'a (unwind-protect (throw 'a 1) (setq x 2))) x))"),
+            "(1 2)"
+        );
+        assert_eq!(
+            run("(let ((x nil)) (tagbody (unwind-protect (go out) (setq x (cons 1 x))) (setq x 'skipped) out) x)"),
+            "(1)"
+        );
+        assert_eq!(
+            run("(let ((x nil)) (list (catch 'a (unwind-protect (unwind-protect (throw 'a 1) (setq x (cons 2 x))) (setq x (cons 3 x)))) x))"),
+            "(1 (3 2))"
+        );
+        assert_eq!(
+            run("(catch 'a (unwind-protect (throw 'a 1) (throw 'a 2)))"),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_rest() {
+        assert_eq!(run("((lambda (a &rest r) (list a r)) 1 2 3)"), "(1 (2 3))");
+        assert_eq!(run("((lambda (&rest r) r))"), "nil");
+        assert_eq!(run("(apply (lambda (a b) (list b a)) 1 '(2))"), "(2 1)");
+        assert_eq!(run("(apply '+ 1 2 '(3 4))"), "10");
+    }
+
+    #[test]
+    fn test_lambda_list() {
+        assert_eq!(
+            run("((lambda (a &optional (b 10 b-p) c) (list a b b-p c)) 1)"),
+            "(1 10 nil nil)"
+        );
+        assert_eq!(
+            run("((lambda (a &optional (b 10 b-p)) (list a b b-p)) 1 2)"),
+            "(1 2 t)"
+        );
+        assert_eq!(
+            run("((lambda (&rest r &key (x 1) ((:why y) x y-p)) (list r x y y-p)) :x 2)"),
+            "((:x 2) 2 2 nil)"
+        );
+        assert_eq!(run("((lambda (&key x) x) :x 1 :x 2)"), "1");
+        assert_eq!(
+            run("((lambda (&key x &allow-other-keys) x) :y 1 :x 2)"),
+            "2"
+        );
+        assert_eq!(run("((lambda (&key x) x) :y 1 :allow-other-keys t)"), "nil");
+        assert_eq!(
+            run("((lambda (a &aux (b (* a 2)) c) (list a b c)) 3)"),
+            "(3 6 nil)"
+        );
+
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let err = core::rep("((lambda (a &optional b &rest r) a))", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongNumberOfArguments { expected, .. })
+                if *expected == types::RuccoArity::new(1, None)
+        ));
+        let err = core::rep("((lambda (&optional b) b) 1 2)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongNumberOfArguments { expected, .. })
+                if *expected == types::RuccoArity::new(0, Some(1))
+        ));
+        let err = core::rep("((lambda (&key x) x) :y 1)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::UnknownKeywordArgument { keyword, .. }) if keyword == ":y"
+        ));
+        let err = core::rep("((lambda (&key x) x) :x)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::OddNumberOfKeywordArguments { .. })
+        ));
+        let err = core::rep("(defun g (&foo a) a)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::UnknownLambdaListKeyword { keyword }) if keyword == "&foo"
+        ));
+        assert!(core::rep("(g 1)", env, arena).is_err_and(|err| matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::VoidFunction { .. })
+        )));
+        let err = core::rep("(car 1 2)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongNumberOfArguments { expected, actual: 2, .. })
+                if *expected == types::RuccoArity::exact(1)
+        ));
+
+        // errors name the function called
+        core::rep("(defun h (x &key y) (list x y))", env, arena).unwrap();
+        let err = core::rep("(h)", env, arena).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "h: wrong number of arguments, 0 given, 1.. expected"
+        );
+        let err = core::rep("(h 1 :z 2)", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::UnknownKeywordArgument { name, .. }) if name == "h"
+        ));
+        let err = core::rep("(flet ((k (x) x)) (k))", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongNumberOfArguments { name, .. }) if name == "k"
+        ));
+        let err = core::rep("((lambda (x) x))", env, arena).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(types::RuccoRuntimeErr::WrongNumberOfArguments { name, .. }) if name == "lambda"
+        ));
+    }
+
+    #[test]
+    fn test_signal_error() {
+        assert_eq!(
+            run("(handler-case (car 1) (type-error (c) (type-error-datum c)))"),
+            "1"
+        );
+        assert_eq!(
+            run("(catch 'out (handler-bind ((error (lambda (c) (throw 'out 'thrown)))) (car 1)))"),
+            "thrown"
+        );
+    }
+
+    #[test]
+    fn test_debugger() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let def = "(defun f (x) (restart-case (car x) (use-value (v) :report \"Use a value of x\" (list x v))))";
+        core::rep(def, env, arena).unwrap();
+
+        let exp = core::read("(f 1)", env, arena).unwrap();
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let mut vm = Vm::new(&code, env, arena);
+        vm.set_debugger(Box::new(|vm, err| {
+            assert_eq!(
+                vm.describe_error(err),
+                "type-error: car: 1 is not of type list"
+            );
+            let backtrace = vm.backtrace().unwrap();
+            let k = backtrace.iter().position(|f| f.name == "f").unwrap();
+            let locals = &backtrace[k].locals;
+            assert_eq!(locals[0].0, "x");
+            assert_eq!(locals[0].1.upgrade().unwrap().borrow().to_string(), "1");
+
+            let exp = vm.read("(setq x (+ x 1))").unwrap();
+            vm.eval_in_frame(k, &exp).unwrap();
+            let restarts = vm.restarts().unwrap();
+            assert_eq!(restarts[0].name, "use-value");
+            assert_eq!(restarts[0].report.as_deref(), Some("Use a value of x"));
+            let exp = vm.read("'v").unwrap();
+            let v = vm.eval(&exp).unwrap();
+            DebuggerAction::Restart {
+                restart: restarts[0].restart.clone(),
+                args: vec![v],
+            }
+        }));
+        let res = vm.run().unwrap();
+        assert_eq!(res.upgrade().unwrap().borrow().to_string(), "(2 v)");
+        drop(vm);
+
+        let exp = core::read("(f 1)", env, arena).unwrap();
+        let code = compiler::compile(&exp, env, arena).unwrap();
+        let mut vm = Vm::new(&code, env, arena);
+        vm.set_debugger(Box::new(|_, _| DebuggerAction::Abort));
+        assert!(vm.run().is_err());
+        drop(vm);
+        assert!(env.get_variable("*restart-clusters*").is_some_and(|r| r
+            .upgrade()
+            .unwrap()
+            .borrow()
+            .is_nil()));
+    }
+
+    #[test]
+    fn test_describe_error() {
+        let arena = &mut types::RuccoArena::default();
+        let env = &mut core::default_env(arena);
+        let describe = |buf: &str, env: &mut core::RuccoEnv, arena: &mut types::RuccoArena| {
+            let err = core::rep(buf, env, arena).unwrap_err();
+            let exp = core::read("nil", env, arena).unwrap();
+            let code = compiler::compile(&exp, env, arena).unwrap();
+            Vm::new(&code, env, arena).describe_error(&err)
+        };
+        assert_eq!(
+            describe("(car 1 2)", env, arena),
+            "simple-program-error: car: wrong number of arguments, 2 given, 1 expected"
+        );
+        assert_eq!(
+            describe("(return-from nowhere 1)", env, arena),
+            "simple-control-error: no block named nowhere"
+        );
+        assert_eq!(
+            describe("(error 'control-error)", env, arena),
+            "control-error"
+        );
+        assert_eq!(
+            describe("(error \"~A is ~S~~\" \"x\" \"y\")", env, arena),
+            "simple-error: x is \"y\"~"
+        );
+        assert_eq!(
+            describe(
+                "(error 'type-error :datum 1 :expected-type 'list)",
+                env,
+                arena
+            ),
+            "type-error: datum 1, expected-type list"
+        );
+        assert_eq!(describe("(", env, arena), "unexpected end of input");
+    }
+}